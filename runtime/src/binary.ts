@@ -1,4 +1,4 @@
-import { Pointer } from './types'
+import { Map, Pointer } from './types'
 
 export interface Exports {
   memory: { buffer: ArrayBuffer }
@@ -15,21 +15,80 @@ const callback_opcodes = {
   ALLOC: 0,
   DEALLOC: 1,
   CALLBACK: 2,
+  ALLOC_BYTES: 3,
+  FREE_BYTES: 4,
+}
+
+// The format byte of a pair header, which follows the pointer and the length
+// words. This and `headerLen` must match `Header` in `data.rs`.
+const HEADER_FORMAT = 1
+
+// Returned by the binary when it rejects a request. Pointers are returned as
+// signed 32-bit integers, so this is `-1` rather than `0xFFFFFFFF`.
+const ERROR_SENTINEL = -1
+
+// Returned by the binary for an opcode it does not know, which means the
+// runtime and the binary were built from different versions of stasis.
+const UNKNOWN_OPCODE_SENTINEL = -2
+
+// Strings cross the boundary as UTF-8 in both directions, which is what Rust
+// and `serde_json` use.
+const encoder = new TextEncoder()
+const decoder = new TextDecoder()
+
+// Binary data is copied straight into linear memory rather than turned into
+// JSON. Typed arrays other than `Uint8Array` are sent as their raw bytes.
+function asBytes(value: any): undefined | Uint8Array {
+  if (value instanceof Uint8Array) {
+    return value
+  }
+
+  if (value instanceof ArrayBuffer) {
+    return new Uint8Array(value)
+  }
+
+  if (ArrayBuffer.isView(value)) {
+    return new Uint8Array(value.buffer, value.byteOffset, value.byteLength)
+  }
+
+  return undefined
 }
 
 export default class Binary {
   private callbackPointer: undefined | number
 
+  // The size of a pointer in bytes, 4 on `wasm32` and 8 on `wasm64`.
+  private wordLen = BYTES.U32
+
+  // Set by a panicking callback, this is thrown once the callback returns.
+  private pendingThrow: undefined | string
+
+  // Values held by Rust through a `Handle`, shared by every module.
+  private handles: Map<any> = {}
+  private nextHandle = 0
+
   constructor(private exports: Exports) {}
 
-  public setCallbackPointer(p: number) {
+  // Binaries which predate `wasm64` support do not send a word size.
+  public setCallbackPointer(p: number, wordLen: number) {
     this.callbackPointer = p
+    this.wordLen = wordLen || BYTES.U32
+  }
+
+  public setPendingThrow(message: string) {
+    this.pendingThrow = message
   }
 
   private mem(): Uint8Array {
     return new Uint8Array(this.exports.memory.buffer)
   }
 
+  // A view of the whole linear memory. Growing the memory detaches it, so it
+  // must not be kept across anything that may allocate.
+  public memory(): Uint8Array {
+    return this.mem()
+  }
+
   private writeU32(ptr: Pointer, n: number) {
     const mem = this.mem()
 
@@ -39,6 +98,10 @@ export default class Binary {
     mem[ptr + 3] = (n & 0xFF000000) >> 24
   }
 
+  public readU8(ptr: Pointer): number {
+    return this.mem()[ptr]
+  }
+
   private readU32(ptr: Pointer): number {
     const mem = this.mem()
 
@@ -52,6 +115,26 @@ export default class Binary {
     return n
   }
 
+  private writeWord(ptr: Pointer, n: number) {
+    if (this.wordLen === BYTES.U32) {
+      this.writeU32(ptr, n)
+    } else {
+      this.writeU32(ptr, n % 0x100000000)
+      this.writeU32(ptr + BYTES.U32, Math.floor(n / 0x100000000))
+    }
+  }
+
+  private readWord(ptr: Pointer): number {
+    if (this.wordLen === BYTES.U32) {
+      return this.readU32(ptr)
+    }
+
+    const low = this.readU32(ptr) >>> 0
+    const high = this.readU32(ptr + BYTES.U32) >>> 0
+
+    return low + high * 0x100000000
+  }
+
   private stasisCallback(opcode: number, a: number, b: number): number {
     if (this.callbackPointer === undefined) {
       throw 'Callback handler uninitialized.'
@@ -59,7 +142,22 @@ export default class Binary {
 
     const handler = this.exports.__indirect_function_table.get(this.callbackPointer)
 
-    return handler(opcode, a, b)
+    // Pointer-sized values are `BigInt`s on `wasm64`.
+    const ret = this.wordLen !== BYTES.U32
+      ? Number(handler(opcode, BigInt(a), BigInt(b)))
+      : handler(opcode, a, b)
+
+    if (ret === UNKNOWN_OPCODE_SENTINEL) {
+      // The binary explains itself through a pending throw.
+      const message = this.pendingThrow
+        || `The binary does not understand opcode ${opcode}.`
+      this.pendingThrow = undefined
+
+      console.error(message)
+      throw new Error(message)
+    }
+
+    return ret
   }
 
   private dealloc(ptr: Pointer, len: number) {
@@ -86,30 +184,41 @@ export default class Binary {
 
     const [ptr, len] = this.makeJson(patchedArgs)
 
-    const landingPad = this.alloc(3 * BYTES.U32)
+    const word = this.wordLen
+    const landingPadLen = 3 * word
+    const landingPad = this.alloc(landingPadLen)
+
+    this.writeWord(landingPad + 0 * word, id)
+    this.writeWord(landingPad + 1 * word, ptr)
+    this.writeWord(landingPad + 2 * word, len)
 
-    this.writeU32(landingPad + 0 * BYTES.U32, id)
-    this.writeU32(landingPad + 1 * BYTES.U32, ptr)
-    this.writeU32(landingPad + 2 * BYTES.U32, len)
+    const ret = this.stasisCallback(
+      callback_opcodes.CALLBACK,
+      landingPad,
+      landingPadLen,
+    )
 
-    const ret = this.stasisCallback(callback_opcodes.CALLBACK, landingPad, 0)
+    if (this.pendingThrow !== undefined) {
+      const message = this.pendingThrow
+      this.pendingThrow = undefined
+
+      throw new Error(message)
+    }
 
     if (ret === 0) {
       return undefined
     }
 
+    if (ret === ERROR_SENTINEL) {
+      throw 'The binary rejected a callback request.'
+    }
+
     const returnPair = this.getPair(ret)
     return this.getJson(returnPair[0], returnPair[1])
   }
 
   public getString(ptr: Pointer, len: number): string {
-    const mem = this.mem()
-
-    let s = ''
-
-    for (let i = 0; i < len; i++) {
-      s += String.fromCharCode(mem[ptr + i])
-    }
+    const s = decoder.decode(this.mem().subarray(ptr, ptr + len))
 
     this.dealloc(ptr, len)
 
@@ -120,7 +229,9 @@ export default class Binary {
     const text = this.getString(ptr, len)
 
     try {
-      return JSON.parse(text)
+      return JSON.parse(text, (_key, value) => (
+        this.reviveUndefined(this.reviveHandle(this.reviveBytes(value)))
+      ))
     } catch(e) {
       // Don't deallocate here. This should never error in practice, if this
       // branch is reached it is indicative of memory corruption. There's not
@@ -129,45 +240,157 @@ export default class Binary {
     }
   }
 
+  // Byte buffers are passed as `{ $bytes: [ptr, len] }` rather than as a JSON
+  // array of numbers. The receiving side takes ownership of the buffer, but
+  // only if the binary confirms it sent one there. Anything else is user data
+  // which happens to look the same, and is left alone.
+  private reviveBytes(value: any): any {
+    if (
+      value === null ||
+      typeof value !== 'object' ||
+      !(value.$bytes instanceof Array)
+    ) {
+      return value
+    }
+
+    const [ptr, len] = value.$bytes
+
+    // Empty buffers are sent with a null pointer, there is nothing to free.
+    if (len === 0) {
+      return new Uint8Array(0)
+    }
+
+    if (typeof ptr !== 'number' || typeof len !== 'number') {
+      return value
+    }
+
+    const bytes = this.mem().slice(ptr, ptr + len)
+
+    if (
+      this.stasisCallback(callback_opcodes.FREE_BYTES, ptr, len) ===
+      ERROR_SENTINEL
+    ) {
+      return value
+    }
+
+    return bytes
+  }
+
+  // Store a value which Rust refers to by id, until Rust releases it.
+  public makeHandle(value: any): { $handle: number } {
+    const id = ++this.nextHandle
+
+    this.handles[id] = value
+
+    return { $handle: id }
+  }
+
+  public releaseHandle(id: number) {
+    delete this.handles[id]
+  }
+
+  // Handles are passed as `{ $handle: id }`, and stay valid until released.
+  private reviveHandle(value: any): any {
+    if (
+      value === null ||
+      typeof value !== 'object' ||
+      typeof value.$handle !== 'number'
+    ) {
+      return value
+    }
+
+    return this.handles[value.$handle]
+  }
+
+  // JSON has no `undefined`, so Rust sends `{ $undefined: true }` instead.
+  // Returning `undefined` from the reviver drops object fields, and leaves
+  // array elements, and so arguments, as `undefined`.
+  private reviveUndefined(value: any): any {
+    if (
+      value === null ||
+      typeof value !== 'object' ||
+      value.$undefined !== true
+    ) {
+      return value
+    }
+
+    return undefined
+  }
+
+  private makeBytes(bytes: Uint8Array): [Pointer, number] {
+    if (bytes.length === 0) {
+      return [0, 0]
+    }
+
+    // The binary only accepts buffers it allocated for this purpose.
+    const ptr = this.stasisCallback(
+      callback_opcodes.ALLOC_BYTES,
+      bytes.length,
+      0,
+    )
+
+    this.mem().set(bytes, ptr)
+
+    return [ptr, bytes.length]
+  }
+
   public makeJson(obj: any): [Pointer, number] {
     if (obj === undefined) {
       return [0, 0]
     }
 
-    const json = JSON.stringify(obj)
+    const json = JSON.stringify(obj, (_key, value) => {
+      const bytes = asBytes(value)
 
-    const mem = this.mem()
-    const len = json.length
-    const ptr = this.alloc(json.length)
+      return bytes !== undefined ? { $bytes: this.makeBytes(bytes) } : value
+    })
 
-    for (let i = 0; i < len; i++) {
-      mem[ptr + i] = json.charCodeAt(i)
-    }
+    const encoded = encoder.encode(json)
+    const len = encoded.length
+    const ptr = this.alloc(len)
+
+    // Allocating may grow the memory, so the view must be taken afterwards.
+    this.mem().set(encoded, ptr)
 
     return [ptr, len]
   }
 
-  private getPair(pad: Pointer): [Pointer, number] {
-    const ptr = this.readU32(pad)
-    const len = this.readU32(pad + 1 * BYTES.U32)
+  private headerLen(): number {
+    return 2 * this.wordLen + 1
+  }
 
-    this.dealloc(pad, 8)
+  private getPair(pad: Pointer): [Pointer, number] {
+    const ptr = this.readWord(pad)
+    const len = this.readWord(pad + 1 * this.wordLen)
+    const format = this.readU8(pad + 2 * this.wordLen)
+
+    this.dealloc(pad, this.headerLen())
+
+    if (format !== HEADER_FORMAT) {
+      throw new Error(
+        `Stasis: unknown header format ${format}, expected ` +
+        `${HEADER_FORMAT}. Make sure the runtime and the stasis crate are ` +
+        'the same version.'
+      )
+    }
 
     return [ptr, len]
   }
 
   public makePair(obj: any): Pointer {
+    // `undefined` is a null pointer, which Rust tells apart from a `null` value.
     if (obj === undefined) {
       return 0
     }
 
     const [ptr, len] = this.makeJson(obj)
 
-    // Alloc 8 bytes at a target address to write to.
-    const target = this.alloc(8)
+    // Alloc a header at a target address to write to.
+    const target = this.alloc(this.headerLen())
 
-    this.writeU32(target + 0 * BYTES.U32, ptr)
-    this.writeU32(target + 1 * BYTES.U32, len)
+    this.writeWord(target + 0 * this.wordLen, ptr)
+    this.writeWord(target + 1 * this.wordLen, len)
+    this.mem()[target + 2 * this.wordLen] = HEADER_FORMAT
 
     return target
   }
@@ -74,11 +74,16 @@ const stasisCall = (getHandle: () => Handle | null) =>
       REGISTER_FN: 2,
       REGISTER_CB: 3,
       CALL_FN: 4,
+      THROW: 5,
+      REGISTER_FN_BATCH: 6,
+      EVAL: 7,
+      RELEASE_HANDLE: 8,
+      CALL_FN_BATCH: 9,
     }
 
     switch (op) {
       case opcodes.REGISTER_STASIS_CB: {
-        handle.binary.setCallbackPointer(a)
+        handle.binary.setCallbackPointer(a, b)
         return 0
       }
 
@@ -91,6 +96,8 @@ const stasisCall = (getHandle: () => Handle | null) =>
           id: number
           name: string
           code: string
+          async?: boolean
+          memory?: boolean
         }
 
         const json: Register = handle.binary.getJson(a, b)
@@ -98,7 +105,28 @@ const stasisCall = (getHandle: () => Handle | null) =>
         handle
           .wrapper
           .getModule(json.id)
-          .register(json.name, json.code)
+          .register(json.name, json.code, json.async, json.memory)
+
+        return 0
+      }
+
+      case opcodes.REGISTER_FN_BATCH: {
+        interface RegisterBatch {
+          id: number
+          fns: Array<[string, string]>
+        }
+
+        const json: RegisterBatch = handle.binary.getJson(a, b)
+        const module = handle.wrapper.getModule(json.id)
+
+        // A broken function should not take the rest of the batch with it.
+        for (const [name, code] of json.fns) {
+          try {
+            module.register(name, code)
+          } catch (e) {
+            console.error(`Stasis: failed to register '${name}'.\n`, e)
+          }
+        }
 
         return 0
       }
@@ -125,28 +153,86 @@ const stasisCall = (getHandle: () => Handle | null) =>
           id: number
           name: string
           args: any
+          cancel?: number
+          catch?: boolean
         }
 
         const call: Call = handle.binary.getJson(a, b)
 
         const ret = handle.wrapper
           .getModule(call.id)
-          .call(call.name, call.args)
+          .call(call.name, call.args, call.cancel, call.catch)
 
         return handle.binary.makePair(ret)
       }
 
+      case opcodes.CALL_FN_BATCH: {
+        interface CallBatch {
+          id: number
+          calls: Array<[string, any]>
+        }
+
+        const json: CallBatch = handle.binary.getJson(a, b)
+        const module = handle.wrapper.getModule(json.id)
+
+        // Each call catches its own exception, so a failed call does not
+        // take the rest of the batch with it.
+        const rets = json.calls.map(([name, args]) => (
+          module.call(name, args, undefined, true)
+        ))
+
+        return handle.binary.makePair(rets)
+      }
+
+      case opcodes.EVAL: {
+        interface Eval {
+          id: number
+          code: string
+          catch?: boolean
+        }
+
+        const json: Eval = handle.binary.getJson(a, b)
+
+        const ret = handle.wrapper
+          .getModule(json.id)
+          .eval(json.code, json.catch)
+
+        return handle.binary.makePair(ret)
+      }
+
+      case opcodes.RELEASE_HANDLE: {
+        handle.binary.releaseHandle(a)
+
+        return 0
+      }
+
+      case opcodes.THROW: {
+        const message: string = handle.binary.getJson(a, b)
+
+        handle.binary.setPendingThrow(message)
+
+        return 0
+      }
+
       default: return -2
     }
   }
 
+// `wasm64` binaries pass and expect pointer-sized values as `BigInt`s.
+const widen = (f: (op: number, a: number, b: number) => number) =>
+  (op: number, a: number | bigint, b: number | bigint): number | bigint => {
+    const ret = f(op, Number(a), Number(b))
+
+    return typeof a === 'bigint' ? BigInt(ret) : ret
+  }
+
 export default async (url: string): Promise<void> => {
   const bytes = patchExport(await getBytes(url))
 
   let handle: null | Handle = null
 
   const env = {
-    __stasis_call: stasisCall(() => handle),
+    __stasis_call: widen(stasisCall(() => handle)),
   }
 
   const WebAssembly = await getWebAssembly()
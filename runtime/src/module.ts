@@ -1,43 +1,134 @@
 import { Map, Pointer } from './types'
 import Binary from './binary'
 
+function setFlag(flags: Map<boolean>, name: string, set?: boolean) {
+  if (set) {
+    flags[name] = true
+  } else {
+    delete flags[name]
+  }
+}
+
 export default class Module {
   private binary: Binary
+  private id: number
   private functions: Map<Function>
   private callbacks: Map<Function>
+  private asyncFunctions: Map<boolean>
+  private memoryFunctions: Map<boolean>
   private data: any
 
-  constructor(binary: Binary) {
+  constructor(binary: Binary, id: number) {
     this.binary = binary
+    this.id = id
     this.functions = {}
     this.callbacks = {}
+    this.asyncFunctions = {}
+    this.memoryFunctions = {}
     this.data = {}
   }
 
-  public register(name: string, f: string) {
+  // Async functions return a promise, which can only be awaited through the
+  // promise helpers. They are still called directly by those helpers.
+  // Functions registered with `memory` see linear memory as `this.memory`.
+  public register(
+    name: string,
+    f: string,
+    isAsync?: boolean,
+    memory?: boolean,
+  ) {
     this.functions[name] = new Function(`return (${f})`)()
+
+    setFlag(this.asyncFunctions, name, isAsync)
+    setFlag(this.memoryFunctions, name, memory)
   }
 
   public registerCallback(name: string, pointer: Pointer) {
-    this.callbacks[name] = (...args: Array<any>): any => {
-      return this.binary.callback(pointer, ...args)
-    }
+    this.callbacks[name] = this.handler(pointer)
+  }
+
+  // Turn a callback pointer into a JS function.
+  private handler = (pointer: Pointer) => (...args: Array<any>): any => {
+    return this.binary.callback(pointer, ...args)
   }
 
-  public call(name: string, args: any): any {
+  // `cancel` points to a flag set by Rust to cancel this call. With
+  // `catchErrors` set, the return value is tagged so that Rust can tell a
+  // thrown exception apart from a normal return.
+  public call(
+    name: string,
+    args: any,
+    cancel?: Pointer,
+    catchErrors?: boolean,
+  ): any {
     // Make sure this is always an array.
     if (!(args instanceof Array)) {
       args = [ args ]
     }
 
+    const cancelled = () => {
+      return cancel !== undefined && this.binary.readU8(cancel) !== 0
+    }
+
+    const f = this.functions[name]!
+    const thisArg = this.thisArg(cancelled, this.memoryFunctions[name])
+
+    const run = () => {
+      if (this.asyncFunctions[name]) {
+        throw new Error(`'${name}' is async, call it with call_promise.`)
+      }
+
+      return f.apply(thisArg, args)
+    }
+
+    return this.invoke(run, catchErrors)
+  }
+
+  // Run a one-off expression with the same `this` as registered functions,
+  // without adding it to the function table.
+  public eval(code: string, catchErrors?: boolean): any {
+    const run = () => {
+      const f = new Function(`return (${code})`)
+
+      return f.call(this.thisArg(() => false))
+    }
+
+    return this.invoke(run, catchErrors)
+  }
+
+  private thisArg(cancelled: () => boolean, memory?: boolean) {
     const thisArg = {
+      cancelled,
       data: this.data,
+      functions: this.functions,
       callbacks: this.callbacks,
+      handler: this.handler,
+      handle: (value: any) => this.binary.makeHandle(value),
+      moduleId: this.id,
     }
 
+    // A getter, as the memory may grow and detach any earlier view.
+    if (memory) {
+      const binary = this.binary
+
+      Object.defineProperty(thisArg, 'memory', {
+        get: () => binary.memory(),
+      })
+    }
+
+    return thisArg
+  }
+
+  private invoke(f: () => any, catchErrors?: boolean): any {
     try {
-      return this.functions[name]!.apply(thisArg, args)
+      const ret = f()
+
+      return catchErrors ? { ok: ret === undefined ? null : ret } : ret
     } catch (e) {
+      if (catchErrors) {
+        return { err: e instanceof Error ? e.message : String(e) }
+      }
+
       console.error(
         'An implementation error within a stasis module has occurred.\n' +
         'This module is now most likely in an invalid state.\n' +
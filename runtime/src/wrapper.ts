@@ -16,7 +16,7 @@ export default class Wrapper {
   public createModule(): number {
     this.counter++
 
-    this.modules[this.counter] = new Module(this.binary)
+    this.modules[this.counter] = new Module(this.binary, this.counter)
 
     return this.counter
   }
@@ -0,0 +1,53 @@
+//! Benchmarks of many small calls, made one by one and as a batch.
+//!
+//! This needs the mock runtime, so run it with `--features mock`. The mock
+//! does no JavaScript work, which leaves only the cost of each crossing.
+
+#[macro_use]
+extern crate criterion;
+#[macro_use]
+extern crate serde_json;
+extern crate stasis_internals;
+
+use criterion::Criterion;
+use serde_json::Value;
+use stasis_internals::{mock, outgoing};
+
+const CALLS: usize = 100;
+
+fn setup() -> u32 {
+    mock::implement("bench.set", |_| mock::undefined());
+
+    let id = outgoing::create_module();
+    outgoing::register_fn(id, "bench.set", "function(i, text) {}");
+
+    id
+}
+
+/// Updating 100 nodes with a call each.
+fn single(c: &mut Criterion) {
+    let id = setup();
+
+    c.bench_function("100 calls", |b| {
+        b.iter(|| {
+            for i in 0..CALLS {
+                let () = outgoing::call(id, "bench.set", (i, "text"));
+            }
+        })
+    });
+}
+
+/// Updating 100 nodes with a single batch.
+fn batch(c: &mut Criterion) {
+    let id = setup();
+
+    let args: Vec<Value> = (0..CALLS).map(|i| json!([i, "text"])).collect();
+    let calls: Vec<_> = args.iter().map(|args| ("bench.set", args)).collect();
+
+    c.bench_function("100 calls in a batch", |b| {
+        b.iter(|| outgoing::call_batch(id, &calls))
+    });
+}
+
+criterion_group!(benches, single, batch);
+criterion_main!(benches);
@@ -0,0 +1,108 @@
+//! Benchmarks of the traffic the runtime generates on every call.
+//!
+//! Compare against a previous run with `cargo bench -- --baseline <name>`.
+
+#[macro_use]
+extern crate criterion;
+extern crate serde_json;
+extern crate stasis_internals;
+
+use std::{mem, ptr, slice};
+
+use criterion::Criterion;
+use stasis_internals::{
+    Bytes,
+    Header,
+    decode_incoming,
+    incoming::incoming,
+    internal_callbacks,
+};
+
+const ALLOC: u32 = 0;
+const DEALLOC: u32 = 1;
+const CALLBACK: u32 = 2;
+const ALLOC_BYTES: u32 = 3;
+
+const WORD_LEN: usize = mem::size_of::<usize>();
+
+/// There is no runtime here, but the library still needs the symbol to link.
+#[cfg(not(feature = "mock"))]
+#[no_mangle]
+pub extern "C-unwind" fn __stasis_call(
+    _op: u32,
+    _a: usize,
+    _b: usize,
+) -> usize {
+    0
+}
+
+unsafe fn write_word(base: *mut u8, i: usize, n: usize) {
+    let bytes = n.to_le_bytes();
+
+    ptr::copy_nonoverlapping(bytes.as_ptr(), base.add(i * WORD_LEN), WORD_LEN);
+}
+
+/// The runtime reading a return value, which frees the header it was given.
+fn header(c: &mut Criterion) {
+    c.bench_function("header alloc", |b| {
+        b.iter(|| {
+            let header = incoming(ALLOC, Header::LEN, 0);
+            incoming(DEALLOC, header as usize, Header::LEN);
+        })
+    });
+}
+
+/// A full callback as made by the runtime, returning a value.
+fn callback(c: &mut Criterion) {
+    let id = internal_callbacks::register(|n: u32| n + 1);
+
+    c.bench_function("callback round trip", |b| {
+        b.iter(|| unsafe {
+            let params = incoming(ALLOC, 1, 0);
+            *params = b'1';
+
+            let pad = incoming(ALLOC, 3 * WORD_LEN, 0);
+            write_word(pad, 0, id as usize);
+            write_word(pad, 1, params as usize);
+            write_word(pad, 2, 1);
+
+            let header = incoming(CALLBACK, pad as usize, 3 * WORD_LEN);
+
+            let bytes = slice::from_raw_parts(header, Header::LEN);
+            let Header { ptr, len } = Header::decode(bytes).unwrap();
+
+            incoming(DEALLOC, ptr, len);
+            incoming(DEALLOC, header as usize, Header::LEN);
+        })
+    });
+}
+
+/// A 1MB binary return value, read as JSON numbers and as `Bytes`.
+fn returned_bytes(c: &mut Criterion) {
+    const LEN: usize = 1 << 20;
+
+    let data: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+    let numbers = serde_json::to_string(&data).unwrap();
+
+    c.bench_function("1MB return as Vec<u8>", |b| {
+        b.iter(|| serde_json::from_str::<Vec<u8>>(&numbers).unwrap())
+    });
+
+    c.bench_function("1MB return as Bytes", |b| {
+        b.iter(|| {
+            // The runtime copies the buffer in and sends only its location.
+            let ptr = incoming(ALLOC_BYTES, LEN, 0);
+            unsafe {
+                ptr::copy_nonoverlapping(data.as_ptr(), ptr, LEN);
+            }
+
+            let json = format!(r#"{{"$bytes":[{},{}]}}"#, ptr as usize, LEN);
+
+            decode_incoming(|| serde_json::from_str::<Bytes>(&json))
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, header, callback, returned_bytes);
+criterion_main!(benches);
@@ -1,9 +1,72 @@
 //! Data reading and writing.
 
-use std::mem;
+use std::{
+    any,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    error::Error,
+    fmt,
+    io,
+    mem,
+    ops::Deref,
+    ptr,
+    slice,
+};
 
 use serde_json;
-use serde::{Serialize};
+use serde_json::ser::{CharEscape, CompactFormatter, Formatter};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, IntoDeserializer, Visitor};
+use serde::de::value::UnitDeserializer;
+use serde::ser::SerializeMap;
+
+/// The error returned when a slice is not exactly 4 bytes long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LenError {
+    /// The length of the given slice.
+    pub len: usize,
+}
+
+impl fmt::Display for LenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Expected 4 bytes, got {}", self.len)
+    }
+}
+
+impl Error for LenError {}
+
+/// Little Endian read of `u32`.
+///
+/// Returns `None` if the given slice is not exactly 4 bytes long.
+pub fn try_read_u32(ptr: &[u8]) -> Option<u32> {
+    if ptr.len() != 4 {
+        return None;
+    }
+
+    Some(
+           (ptr[0] as u32)
+        + ((ptr[1] as u32) << 8)
+        + ((ptr[2] as u32) << 16)
+        + ((ptr[3] as u32) << 24)
+    )
+}
+
+/// Little Endian write for `u32`.
+///
+/// Fails if the given slice is not exactly 4 bytes long, in which case nothing
+/// is written.
+pub fn try_write_u32(ptr: &mut [u8], n: u32) -> Result<(), LenError> {
+    if ptr.len() != 4 {
+        return Err(LenError { len: ptr.len() });
+    }
+
+    ptr[0] = (n & 0xFF) as u8;
+    ptr[1] = ((n & 0xFF00) >> 8) as u8;
+    ptr[2] = ((n & 0xFF0000) >> 16) as u8;
+    ptr[3] = ((n & 0xFF000000) >> 24) as u8;
+
+    Ok(())
+}
 
 /// Little Endian read of `u32`.
 ///
@@ -11,12 +74,10 @@ use serde::{Serialize};
 ///
 /// This function will panic if the given slice is not exactly 4 bytes long.
 pub fn read_u32(ptr: &[u8]) -> u32 {
-    assert_eq!(ptr.len(), 4);
-
-       (ptr[0] as u32)
-    + ((ptr[1] as u32) << 8)
-    + ((ptr[2] as u32) << 16)
-    + ((ptr[3] as u32) << 24)
+    match try_read_u32(ptr) {
+        Some(n) => n,
+        None => panic!("{}", LenError { len: ptr.len() }),
+    }
 }
 
 /// Little Endian write for `u32`.
@@ -25,15 +86,156 @@ pub fn read_u32(ptr: &[u8]) -> u32 {
 ///
 /// This function will panic if the given slice is not exactly 4 bytes long.
 pub fn write_u32(ptr: &mut [u8], n: u32) {
-    assert_eq!(ptr.len(), 4);
+    if let Err(e) = try_write_u32(ptr, n) {
+        panic!("{}", e);
+    }
+}
 
-    ptr[0] = (n & 0xFF) as u8;
-    ptr[1] = ((n & 0xFF00) >> 8) as u8;
-    ptr[2] = ((n & 0xFF0000) >> 16) as u8;
-    ptr[3] = ((n & 0xFF000000) >> 24) as u8;
+/// Little Endian read of `u64`.
+///
+/// # Panics
+///
+/// This function will panic if the given slice is not exactly 8 bytes long.
+pub fn read_u64(ptr: &[u8]) -> u64 {
+    assert_eq!(ptr.len(), 8);
+
+    (read_u32(&ptr[0..4]) as u64) + ((read_u32(&ptr[4..8]) as u64) << 32)
+}
+
+/// Little Endian write for `u64`.
+///
+/// # Panics
+///
+/// This function will panic if the given slice is not exactly 8 bytes long.
+pub fn write_u64(ptr: &mut [u8], n: u64) {
+    assert_eq!(ptr.len(), 8);
+
+    write_u32(&mut ptr[0..4], n as u32);
+    write_u32(&mut ptr[4..8], (n >> 32) as u32);
+}
+
+/// The size of a pointer in bytes.
+///
+/// Pointers and lengths in a `Pair` or a callback landing pad take up this
+/// many bytes. The runtime is told this size when the binary registers
+/// itself.
+pub const WORD_LEN: usize = mem::size_of::<usize>();
+
+/// Read a pointer-sized word.
+#[cfg(target_pointer_width = "64")]
+pub fn read_word(ptr: &[u8]) -> usize {
+    read_u64(ptr) as usize
+}
+
+/// Read a pointer-sized word.
+#[cfg(not(target_pointer_width = "64"))]
+pub fn read_word(ptr: &[u8]) -> usize {
+    read_u32(ptr) as usize
+}
+
+/// Write a pointer-sized word.
+#[cfg(target_pointer_width = "64")]
+pub fn write_word(ptr: &mut [u8], n: usize) {
+    write_u64(ptr, n as u64)
+}
+
+/// Write a pointer-sized word.
+#[cfg(not(target_pointer_width = "64"))]
+pub fn write_word(ptr: &mut [u8], n: usize) {
+    write_u32(ptr, n as u32)
+}
+
+/// The layout of the header a `Pair` crosses the boundary as.
+///
+/// This is the one place the layout is defined on the Rust side, and the
+/// runtime mirrors it in `binary.ts`. Words are little-endian:
+///
+/// | Offset         | Size       | Field                       |
+/// |----------------|------------|-----------------------------|
+/// | 0              | `WORD_LEN` | Pointer to the data         |
+/// | `WORD_LEN`     | `WORD_LEN` | Length of the data in bytes |
+/// | `2 * WORD_LEN` | 1          | Format, always `FORMAT`     |
+///
+/// This is 9 bytes on `wasm32` and 17 bytes on `wasm64`. Any change to the
+/// layout must bump `FORMAT`, so a header from a mismatched runtime is
+/// rejected instead of misread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub ptr: usize,
+    pub len: usize,
 }
 
+impl Header {
+    /// The offset of the pointer.
+    pub const PTR_OFFSET: usize = 0;
+
+    /// The offset of the length.
+    pub const LEN_OFFSET: usize = WORD_LEN;
+
+    /// The offset of the format byte.
+    pub const FORMAT_OFFSET: usize = 2 * WORD_LEN;
+
+    /// The size of the header in bytes.
+    pub const LEN: usize = Header::FORMAT_OFFSET + 1;
+
+    /// The format this version of stasis reads and writes.
+    pub const FORMAT: u8 = 1;
+
+    /// Encode the header.
+    pub fn encode(&self) -> [u8; Header::LEN] {
+        let Header { ptr, len } = *self;
+        let mut bytes = [0; Header::LEN];
+
+        write_word(&mut bytes[Header::PTR_OFFSET..Header::LEN_OFFSET], ptr);
+        write_word(&mut bytes[Header::LEN_OFFSET..Header::FORMAT_OFFSET], len);
+        bytes[Header::FORMAT_OFFSET] = Header::FORMAT;
+
+        bytes
+    }
+
+    /// Decode a header, rejecting any other format than `FORMAT`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `bytes` is shorter than `LEN`.
+    pub fn decode(bytes: &[u8]) -> Result<Self, HeaderError> {
+        let format = bytes[Header::FORMAT_OFFSET];
+
+        if format != Header::FORMAT {
+            return Err(HeaderError { format });
+        }
+
+        Ok(Header {
+            ptr: read_word(&bytes[Header::PTR_OFFSET..Header::LEN_OFFSET]),
+            len: read_word(&bytes[Header::LEN_OFFSET..Header::FORMAT_OFFSET]),
+        })
+    }
+}
+
+/// The error returned when a header has an unknown format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderError {
+    /// The format byte of the header.
+    pub format: u8,
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unknown header format {}, expected {}. Make sure the runtime and \
+             the stasis crate are the same version.",
+            self.format,
+            Header::FORMAT,
+        )
+    }
+}
+
+impl Error for HeaderError {}
+
 /// A WebAssembly-friendly fat pointer.
+///
+/// Across the boundary, this is a `Header` holding the pointer and the length.
 #[derive(Debug)]
 pub struct Pair {
     pub ptr: *mut u8,
@@ -41,44 +243,367 @@ pub struct Pair {
 }
 
 impl Pair {
+    /// The size of the header in bytes.
+    pub const HEADER_LEN: usize = Header::LEN;
+}
+
+/// A buffer handed across the boundary.
+///
+/// Buffers cross as a bare pointer and length, so the allocation must be
+/// exactly `len` bytes long to be freed with the right layout. This is the one
+/// place such a buffer is allocated for the other side, or taken back from it.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct OwnedBytes(Box<[u8]>);
+
+impl OwnedBytes {
+    /// Allocate exactly `len` bytes for the runtime to write to.
+    ///
+    /// The bytes are uninitialized, and must all be written before the buffer
+    /// is taken back with `from_raw`.
+    pub fn alloc_raw(len: usize) -> *mut u8 {
+        Box::into_raw(Box::<[u8]>::new_uninit_slice(len)) as *mut u8
+    }
+
+    /// Take back a buffer handed over with `alloc_raw` or `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// Unless `len` is zero, `ptr` must come from `alloc_raw` or `into_raw`
+    /// with this exact `len`, every byte must be initialized, and `ptr` must
+    /// not be used afterwards. An empty buffer never reads `ptr`, which may
+    /// then be null.
+    pub unsafe fn from_raw(ptr: *mut u8, len: usize) -> Self {
+        if len == 0 {
+            return OwnedBytes::default();
+        }
+
+        OwnedBytes(Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)))
+    }
+
+    /// Hand over the buffer as a pointer and length, to be taken back later
+    /// with `from_raw`.
+    pub fn into_raw(self) -> (*mut u8, usize) {
+        let len = self.0.len();
+
+        (Box::into_raw(self.0) as *mut u8, len)
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.0.into_vec()
+    }
+}
+
+impl From<Vec<u8>> for OwnedBytes {
+    /// Any spare capacity is freed, so the allocation is exactly the length.
+    fn from(bytes: Vec<u8>) -> Self {
+        OwnedBytes(bytes.into_boxed_slice())
+    }
+}
+
+impl Deref for OwnedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// The most freed headers kept around for reuse.
+const HEADER_POOL_LIMIT: usize = 16;
+
+/// Freed blocks of `Pair::HEADER_LEN` bytes.
+struct HeaderPool(Vec<*mut u8>);
+
+impl Drop for HeaderPool {
+    fn drop(&mut self) {
+        for &header in &self.0 {
+            unsafe {
+                free_block(header, Pair::HEADER_LEN);
+            }
+        }
+    }
+}
+
+thread_local! {
+    // Every call hands a header to the runtime, which frees it straight after
+    // reading it. Reusing these saves an allocation on both sides of nearly
+    // every call.
+    static HEADER_POOL: RefCell<HeaderPool> = const {
+        RefCell::new(HeaderPool(Vec::new()))
+    };
+}
+
+unsafe fn free_block(ptr: *mut u8, len: usize) {
+    drop(OwnedBytes::from_raw(ptr, len));
+}
+
+/// Allocate a block of `Pair::HEADER_LEN` bytes, reusing a freed one if
+/// possible.
+pub fn alloc_header() -> *mut u8 {
+    let pooled = HEADER_POOL.with(|pool| pool.borrow_mut().0.pop());
+
+    pooled.unwrap_or_else(|| OwnedBytes::alloc_raw(Pair::HEADER_LEN))
+}
+
+/// Free a block of `Pair::HEADER_LEN` bytes, keeping it for reuse.
+///
+/// Any allocation of this size may be freed here, not only headers, as they
+/// all share the same layout.
+pub unsafe fn free_header(header: *mut u8) {
+    let kept = HEADER_POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+
+        if pool.0.len() < HEADER_POOL_LIMIT {
+            pool.0.push(header);
+            true
+        } else {
+            false
+        }
+    });
+
+    if !kept {
+        free_block(header, Pair::HEADER_LEN);
+    }
+}
+
+/// The default maximum nesting depth of serialized values.
+pub const DEFAULT_DEPTH_LIMIT: usize = 128;
+
+/// A JSON formatter which fails past a maximum nesting depth.
+///
+/// `serde_json` serializes recursive types such as `Option<Box<T>>` by
+/// recursing, so a deep enough value overflows the stack. Failing at the first
+/// array or object past the limit unwinds the serializer with a regular error
+/// instead. Everything else is written by the wrapped formatter.
+struct DepthLimited<F> {
+    inner: F,
+    depth: usize,
+    limit: usize,
+}
+
+impl<F> DepthLimited<F> {
+    fn enter(&mut self) -> io::Result<()> {
+        self.depth += 1;
+
+        if self.depth > self.limit {
+            Err(io::Error::other(
+                format!("maximum nesting depth of {} exceeded", self.limit),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Forward formatter methods to the wrapped formatter.
+macro_rules! forward {
+    ($($name:ident($($arg:ident: $ty:ty),*);)*) => {$(
+        fn $name<W>(&mut self, writer: &mut W $(, $arg: $ty)*) -> io::Result<()>
+        where
+            W: ?Sized + io::Write,
+        {
+            self.inner.$name(writer $(, $arg)*)
+        }
+    )*};
+}
+
+impl<F> Formatter for DepthLimited<F>
+where
+    F: Formatter,
+{
+    forward! {
+        write_null();
+        write_bool(value: bool);
+        write_i8(value: i8);
+        write_i16(value: i16);
+        write_i32(value: i32);
+        write_i64(value: i64);
+        write_i128(value: i128);
+        write_u8(value: u8);
+        write_u16(value: u16);
+        write_u32(value: u32);
+        write_u64(value: u64);
+        write_u128(value: u128);
+        write_f32(value: f32);
+        write_f64(value: f64);
+        write_number_str(value: &str);
+        begin_string();
+        end_string();
+        write_string_fragment(fragment: &str);
+        write_char_escape(char_escape: CharEscape);
+        write_byte_array(value: &[u8]);
+        begin_array_value(first: bool);
+        end_array_value();
+        begin_object_key(first: bool);
+        end_object_key();
+        begin_object_value();
+        end_object_value();
+        write_raw_fragment(fragment: &str);
+    }
+
+    fn begin_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        self.enter()?;
+        self.inner.begin_array(writer)
+    }
+
+    fn end_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        self.depth -= 1;
+        self.inner.end_array(writer)
+    }
+
+    fn begin_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        self.enter()?;
+        self.inner.begin_object(writer)
+    }
+
+    fn end_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        self.depth -= 1;
+        self.inner.end_object(writer)
+    }
+}
+
+/// Serialize a value to JSON with a maximum nesting depth.
+pub fn to_json<T>(t: &T, limit: usize) -> Result<Vec<u8>, serde_json::Error>
+where
+    T: ?Sized + Serialize,
+{
+    to_json_with(t, CompactFormatter, limit)
+}
+
+/// Serialize a value to JSON with a custom formatter and a maximum nesting
+/// depth.
+///
+/// The formatter controls how each token is written, such as printing floats
+/// with fewer digits to shrink data-heavy payloads. Its output must still be
+/// valid JSON for the runtime to parse it.
+pub fn to_json_with<T, F>(
+    t: &T,
+    formatter: F,
+    limit: usize,
+) -> Result<Vec<u8>, serde_json::Error>
+where
+    T: ?Sized + Serialize,
+    F: Formatter,
+{
+    let mut out = Vec::new();
+
+    write_json_with(&mut out, t, formatter, limit)?;
+
+    Ok(out)
+}
+
+/// Serialize a value to JSON with a maximum nesting depth into a writer.
+pub fn write_json<W, T>(
+    writer: W,
+    t: &T,
+    limit: usize,
+) -> Result<(), serde_json::Error>
+where
+    W: io::Write,
+    T: ?Sized + Serialize,
+{
+    write_json_with(writer, t, CompactFormatter, limit)
+}
+
+/// Serialize a value to JSON with a custom formatter and a maximum nesting
+/// depth into a writer.
+pub fn write_json_with<W, T, F>(
+    writer: W,
+    t: &T,
+    formatter: F,
+    limit: usize,
+) -> Result<(), serde_json::Error>
+where
+    W: io::Write,
+    T: ?Sized + Serialize,
+    F: Formatter,
+{
+    let formatter = DepthLimited { inner: formatter, depth: 0, limit };
+    let mut ser = serde_json::Serializer::with_formatter(writer, formatter);
+
+    t.serialize(&mut ser)
+}
+
+impl Pair {
+    /// Serialize a value to JSON, failing if it is nested deeper than
+    /// `DEFAULT_DEPTH_LIMIT`.
     pub fn serialize<T>(t: T) -> Result<Self, serde_json::Error>
     where
         T: Serialize,
     {
-        serde_json::to_string(&t)
-            .map(|s| s.into())
+        Pair::serialize_with_depth_limit(t, DEFAULT_DEPTH_LIMIT)
+    }
+
+    /// Serialize a value to JSON with a custom maximum nesting depth.
+    ///
+    /// Every array, object and struct counts as one level, including the
+    /// wrapper added around call arguments.
+    pub fn serialize_with_depth_limit<T>(
+        t: T,
+        limit: usize,
+    ) -> Result<Self, serde_json::Error>
+    where
+        T: Serialize,
+    {
+        to_json(&t, limit).map(Pair::from)
     }
 
+    /// Take a header handed over by the runtime, freeing it.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the header has an unknown format, see
+    /// `try_from_u8_mut_ptr`.
     pub unsafe fn from_u8_mut_ptr(src: *mut u8) -> Self {
-        let bytes = Vec::from_raw_parts(src, 8, 8);
+        match Pair::try_from_u8_mut_ptr(src) {
+            Ok(pair) => pair,
+            Err(e) => panic!("stasis: {}", e),
+        }
+    }
 
-        let ptr = read_u32(&bytes[0..4]);
-        let len = read_u32(&bytes[4..8]);
+    /// Take a header handed over by the runtime, freeing it.
+    ///
+    /// A header with an unknown format is freed as well, but the data it
+    /// points to is leaked as it cannot be located.
+    pub unsafe fn try_from_u8_mut_ptr(
+        src: *mut u8,
+    ) -> Result<Self, HeaderError> {
+        let header = Header::decode(slice::from_raw_parts(src, Header::LEN));
 
-        // Deallocate the fat pointer. This call is not actually needed but
-        // helps illustrate what needs to happen here.
-        drop(bytes);
+        free_header(src);
 
-        Self {
+        header.map(|Header { ptr, len }| Self {
             ptr: ptr as *mut u8,
-            len: len as usize,
-        }
+            len,
+        })
     }
 
-    pub unsafe fn into_string(self) -> String {
-        String::from_raw_parts(self.ptr, self.len, self.len)
+    pub unsafe fn into_bytes(self) -> Vec<u8> {
+        OwnedBytes::from_raw(self.ptr, self.len).into_vec()
     }
 }
 
 impl From<String> for Pair {
     fn from(s: String) -> Self {
-        let mut bytes: Vec<u8> = s.into();
-        bytes.shrink_to_fit();
-
-        let ptr = bytes.as_mut_ptr();
-        let len = bytes.len();
+        Pair::from(s.into_bytes())
+    }
+}
 
-        mem::forget(bytes);
+impl From<Vec<u8>> for Pair {
+    fn from(bytes: Vec<u8>) -> Self {
+        let (ptr, len) = OwnedBytes::from(bytes).into_raw();
 
         Self { ptr, len }
     }
@@ -88,19 +613,626 @@ impl Into<*mut u8> for Pair {
     fn into(self) -> *mut u8 {
         let Self { ptr, len } = self;
 
-        let mut bytes = Vec::with_capacity(8);
+        let bytes = Header { ptr: ptr as usize, len }.encode();
+
+        let header = alloc_header();
 
-        for _ in 0..8 {
-            bytes.push(0);
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), header, Header::LEN);
         }
 
-        write_u32(&mut bytes[0..4], ptr as u32);
-        write_u32(&mut bytes[4..8], len as u32);
+        header
+    }
+}
 
-        let ret = bytes.as_mut_ptr();
+/// Produce a `T` without any input if `T` is `()`.
+///
+/// This is a stand-in for specialization, and like `is_unit` decides by type
+/// alone. Other types which deserialize from a unit, such as
+/// `Option<()>`, return `None`.
+pub fn unit<T>() -> Option<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    if !is_unit::<T>() {
+        return None;
+    }
+
+    let unit: UnitDeserializer<de::value::Error> = ().into_deserializer();
 
-        mem::forget(bytes);
+    T::deserialize(unit).ok()
+}
 
-        ret
+/// Whether `T` is `()`, or a reference to it.
+///
+/// Only the type counts. A `serde_json::Value::Null` or a unit struct is not
+/// a unit, even though it may serialize just like one.
+///
+/// Type names leave out lifetimes, so unlike `TypeId` this works for any `T`,
+/// such as borrowed arguments. No other type shares the name of `()`.
+pub fn is_unit<T>() -> bool
+where
+    T: ?Sized,
+{
+    let name = any::type_name::<T>();
+
+    name == any::type_name::<()>() || name == any::type_name::<&()>()
+}
+
+/// The key used to mark a raw byte buffer on the wire.
+const BYTES_KEY: &str = "$bytes";
+
+thread_local! {
+    /// The buffers of the payload being encoded for the runtime, if any.
+    static OUTGOING: RefCell<Option<Vec<OwnedBytes>>> = const {
+        RefCell::new(None)
+    };
+
+    /// Whether a value from the runtime is being decoded.
+    static DECODING: Cell<bool> = const { Cell::new(false) };
+
+    /// Lengths of the buffers sent to the runtime and not yet freed by it, by
+    /// pointer.
+    static SENT: RefCell<HashMap<usize, usize>> = RefCell::new(HashMap::new());
+
+    /// Buffers the runtime allocated for the value about to be decoded, by
+    /// pointer.
+    static RECEIVED: RefCell<HashMap<usize, OwnedBytes>> =
+        RefCell::new(HashMap::new());
+}
+
+/// The byte buffers of an encoded payload, freed unless sent.
+///
+/// See `encode_outgoing`.
+#[derive(Debug, Default)]
+pub struct Outgoing(Vec<OwnedBytes>);
+
+impl Outgoing {
+    /// Hand the buffers over to the runtime along with their payload.
+    ///
+    /// The runtime frees them once it has read them, see `take_sent`.
+    pub fn send(self) {
+        SENT.with(|sent| {
+            let mut sent = sent.borrow_mut();
+
+            for bytes in self.0 {
+                let (ptr, len) = bytes.into_raw();
+                sent.insert(ptr as usize, len);
+            }
+        });
+    }
+}
+
+/// Take back a buffer which was sent to the runtime.
+///
+/// Returns `None` unless `ptr` and `len` describe a buffer handed over with
+/// `Outgoing::send`. A `$bytes` key in any other value, such as user data, is
+/// then left alone rather than trusted.
+pub fn take_sent(ptr: usize, len: usize) -> Option<OwnedBytes> {
+    let sent = SENT.with(|sent| {
+        let mut sent = sent.borrow_mut();
+
+        match sent.get(&ptr) {
+            Some(&l) if l == len => sent.remove(&ptr).is_some(),
+            _ => false,
+        }
+    });
+
+    if !sent {
+        return None;
+    }
+
+    // Recorded by `Outgoing::send`, so this is exactly such a buffer.
+    Some(unsafe { OwnedBytes::from_raw(ptr as *mut u8, len) })
+}
+
+/// Allocate a buffer for the runtime to send a `Bytes` in.
+///
+/// Only buffers allocated here can be taken over by a `Bytes`, and only while
+/// decoding the next value from the runtime. Any left over by then are freed.
+pub fn alloc_received(len: usize) -> *mut u8 {
+    let bytes = OwnedBytes::from(vec![0; len]);
+    let ptr = bytes.as_ptr() as *mut u8;
+
+    RECEIVED.with(|r| r.borrow_mut().insert(ptr as usize, bytes));
+
+    ptr
+}
+
+/// Take over a buffer allocated with `alloc_received`.
+fn take_received(ptr: usize, len: usize) -> Option<OwnedBytes> {
+    RECEIVED.with(|r| {
+        let mut received = r.borrow_mut();
+
+        match received.get(&ptr) {
+            Some(bytes) if bytes.len() == len => received.remove(&ptr),
+            _ => None,
+        }
+    })
+}
+
+/// Run `f`, which encodes a payload that is about to be sent to the runtime.
+///
+/// Only within `f` is a `Bytes` copied into a buffer of its own and sent as a
+/// pointer, anywhere else it is encoded as plain bytes. The buffers are freed
+/// when the returned `Outgoing` is dropped, so call `send` right before the
+/// payload is handed over.
+pub fn encode_outgoing<F, R>(f: F) -> (R, Outgoing)
+where
+    F: FnOnce() -> R,
+{
+    let outer = OUTGOING.with(|o| o.replace(Some(Vec::new())));
+    let ret = f();
+    let buffers = OUTGOING.with(|o| o.replace(outer)).unwrap_or_default();
+
+    (ret, Outgoing(buffers))
+}
+
+/// Run `f`, which decodes a value that was received from the runtime.
+///
+/// Only within `f` may a `Bytes` take ownership of a buffer, and only of one
+/// the runtime allocated with `alloc_received`. Buffers which are not taken,
+/// such as those of a value decoded as `serde_json::Value`, are freed after.
+pub fn decode_incoming<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let outer = DECODING.with(|d| d.replace(true));
+    let ret = f();
+    DECODING.with(|d| d.set(outer));
+
+    if !outer {
+        RECEIVED.with(|r| r.borrow_mut().clear());
+    }
+
+    ret
+}
+
+/// A byte buffer passed through linear memory instead of as JSON.
+///
+/// A `Vec<u8>` serializes as an array of numbers, which is both large and slow
+/// to parse. `Bytes` is instead sent as `{"$bytes": [ptr, len]}` and surfaced
+/// in JavaScript as a `Uint8Array`.
+///
+/// Ownership of the buffer moves across the boundary with the value. When sent
+/// to JavaScript, a copy is handed to the runtime which frees it once read.
+/// When received from JavaScript, the runtime allocates the buffer and the
+/// `Bytes` takes ownership of it. Empty buffers are sent as a null pointer and
+/// are never dereferenced.
+///
+/// This only happens for values which actually cross the boundary. Anywhere
+/// else, such as with `serde_json::to_value`, `Bytes` serializes as plain
+/// bytes and deserializes from them, just like a `Vec<u8>`.
+///
+/// A JavaScript function can return binary data as a `Uint8Array`, an
+/// `ArrayBuffer` or any other typed array, which the runtime copies straight
+/// into linear memory. Call it with a `Bytes` return type to skip JSON, which
+/// is orders of magnitude faster than a `Vec<u8>` for large buffers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let sent = OUTGOING.with(|o| {
+            let mut o = o.borrow_mut();
+            let buffers = o.as_mut()?;
+
+            if self.0.is_empty() {
+                return Some((0, 0));
+            }
+
+            let bytes = OwnedBytes::from(self.0.clone());
+            let raw = (bytes.as_ptr() as usize, bytes.len());
+            buffers.push(bytes);
+
+            Some(raw)
+        });
+
+        match sent {
+            Some(raw) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(BYTES_KEY, &raw)?;
+                map.end()
+            }
+            None => serializer.serialize_bytes(&self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(BytesVisitor)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bytes, or a buffer from the runtime")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Bytes, E>
+    where
+        E: de::Error,
+    {
+        Ok(Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Bytes, E>
+    where
+        E: de::Error,
+    {
+        Ok(Bytes(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Bytes, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+
+        Ok(Bytes(bytes))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Bytes, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let (ptr, len): (usize, usize) = match map.next_key::<String>()? {
+            Some(ref key) if key == BYTES_KEY => map.next_value()?,
+            _ => return Err(de::Error::missing_field(BYTES_KEY)),
+        };
+
+        if len == 0 {
+            return Ok(Bytes(Vec::new()));
+        }
+
+        // Only the runtime hands out buffers, anything else could point
+        // anywhere.
+        let bytes = if DECODING.with(Cell::get) {
+            take_received(ptr, len)
+        } else {
+            None
+        };
+
+        match bytes {
+            Some(bytes) => Ok(Bytes(bytes.into_vec())),
+            None => Err(de::Error::custom(
+                "a `$bytes` buffer can only be received from the runtime",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, ptr};
+
+    use serde::{Deserialize, Deserializer};
+    use serde_json;
+    use serde_json::ser::{CompactFormatter, Formatter};
+
+    use super::{Bytes, Header, HeaderError, LenError, OwnedBytes, Pair};
+    use super::{try_read_u32, try_write_u32};
+    use super::{read_u64, write_u64, alloc_header, free_header, WORD_LEN};
+    use super::{decode_incoming, encode_outgoing, to_json_with};
+    use super::{alloc_received, take_sent, RECEIVED};
+    use wire::{JsonWith, WireFormat};
+
+    /// A recursive linked list.
+    #[derive(Serialize)]
+    struct List {
+        next: Option<Box<List>>,
+    }
+
+    fn list(len: usize) -> List {
+        let mut head = List { next: None };
+
+        for _ in 1..len {
+            head = List { next: Some(Box::new(head)) };
+        }
+
+        head
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let bytes = Bytes(vec![0, 1, 2, 254, 255]);
+
+        // Away from the boundary, these are plain bytes.
+        let json = serde_json::to_string(&bytes).unwrap();
+        assert_eq!(json, "[0,1,2,254,255]");
+
+        let back: Bytes = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, bytes);
+
+        // A payload for the runtime gets a buffer, which it frees once read.
+        let (json, outgoing) = encode_outgoing(|| {
+            serde_json::to_string(&bytes).unwrap()
+        });
+        outgoing.send();
+
+        let raw: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let ptr = raw["$bytes"][0].as_u64().unwrap() as usize;
+
+        assert!(take_sent(ptr, 4).is_none());
+        assert_eq!(&*take_sent(ptr, 5).unwrap(), &bytes.0[..]);
+        assert!(take_sent(ptr, 5).is_none());
+    }
+
+    #[test]
+    fn bytes_from_runtime() {
+        let received = |data: &[u8]| {
+            let ptr = alloc_received(data.len());
+            unsafe {
+                ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+            }
+            format!(r#"{{"$bytes":[{},{}]}}"#, ptr as usize, data.len())
+        };
+
+        let json = received(&[1, 2, 3]);
+        let back: Bytes = decode_incoming(|| serde_json::from_str(&json))
+            .unwrap();
+        assert_eq!(back, Bytes(vec![1, 2, 3]));
+
+        // A buffer is only taken once.
+        let again = decode_incoming(|| serde_json::from_str::<Bytes>(&json));
+        assert!(again.is_err());
+
+        // Buffers which are not taken are freed once decoding is done.
+        let json = received(&[4, 5]);
+        decode_incoming(|| serde_json::from_str::<serde_json::Value>(&json))
+            .unwrap();
+        assert!(RECEIVED.with(|r| r.borrow().is_empty()));
+    }
+
+    #[test]
+    fn bytes_rejects_foreign_buffers() {
+        // This would free arbitrary memory if it were trusted.
+        let forged = r#"{"$bytes":[1234,5]}"#;
+
+        assert!(serde_json::from_str::<Bytes>(forged).is_err());
+        assert!(decode_incoming(|| serde_json::from_str::<Bytes>(forged))
+            .is_err());
+    }
+
+    #[test]
+    fn unsent_bytes_are_freed() {
+        let (json, outgoing) = encode_outgoing(|| {
+            serde_json::to_string(&Bytes(vec![1, 2, 3])).unwrap()
+        });
+
+        assert!(json.starts_with(r#"{"$bytes":["#));
+        assert_eq!(outgoing.0.len(), 1);
+
+        // Dropping the buffers frees them, as the payload was never sent. The
+        // runtime can then no longer hand them back.
+        drop(outgoing);
+
+        let raw: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let ptr = raw["$bytes"][0].as_u64().unwrap() as usize;
+
+        assert!(take_sent(ptr, 3).is_none());
+    }
+
+    #[test]
+    fn owned_bytes_round_trip() {
+        let ptr = OwnedBytes::alloc_raw(3);
+
+        unsafe {
+            ptr::copy_nonoverlapping([1, 2, 3].as_ptr(), ptr, 3);
+            assert_eq!(&*OwnedBytes::from_raw(ptr, 3), &[1, 2, 3]);
+        }
+
+        // Spare capacity is not handed over.
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(b"abc");
+
+        let (ptr, len) = OwnedBytes::from(bytes).into_raw();
+        assert_eq!(len, 3);
+
+        let back = unsafe { OwnedBytes::from_raw(ptr, len) };
+        assert_eq!(back.into_vec(), b"abc");
+
+        let empty = unsafe { OwnedBytes::from_raw(ptr::null_mut(), 0) };
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn empty_bytes_round_trip() {
+        let (json, outgoing) = encode_outgoing(|| {
+            serde_json::to_string(&Bytes(Vec::new())).unwrap()
+        });
+        assert_eq!(json, r#"{"$bytes":[0,0]}"#);
+        assert!(outgoing.0.is_empty());
+
+        let back: Bytes = serde_json::from_str(&json).unwrap();
+        assert!(back.0.is_empty());
+    }
+
+    #[test]
+    fn unit_probe() {
+        /// Deserializes just like `()`.
+        #[derive(Debug, PartialEq)]
+        struct Unit;
+
+        impl<'de> Deserialize<'de> for Unit {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                <()>::deserialize(deserializer).map(|()| Unit)
+            }
+        }
+
+        assert_eq!(super::unit::<()>(), Some(()));
+        assert_eq!(super::unit::<Option<()>>(), None);
+        assert_eq!(super::unit::<u32>(), None);
+        assert_eq!(super::unit::<Unit>(), None);
+        assert!(super::unit::<serde_json::Value>().is_none());
+    }
+
+    #[test]
+    fn unit_type() {
+        #[derive(Serialize)]
+        struct Unit;
+
+        assert!(super::is_unit::<()>());
+        assert!(super::is_unit::<&()>());
+        assert!(!super::is_unit::<Option<()>>());
+        assert!(!super::is_unit::<((),)>());
+        assert!(!super::is_unit::<str>());
+
+        // These serialize just like `()`, but are values all the same.
+        assert!(!super::is_unit::<serde_json::Value>());
+        assert!(!super::is_unit::<Unit>());
+    }
+
+    #[test]
+    fn depth_limit() {
+        let pair = Pair::serialize_with_depth_limit(list(100), 100).unwrap();
+        let json = String::from_utf8(unsafe { pair.into_bytes() }).unwrap();
+        assert!(json.starts_with(r#"{"next":{"next":"#));
+
+        let err = Pair::serialize_with_depth_limit(list(101), 100).unwrap_err();
+        assert!(err.to_string().contains("depth"));
+    }
+
+    /// Writes floats with single precision.
+    #[derive(Default)]
+    struct ShortFloats;
+
+    impl Formatter for ShortFloats {
+        fn write_f64<W>(&mut self, writer: &mut W, value: f64) -> io::Result<()>
+        where
+            W: ?Sized + io::Write,
+        {
+            CompactFormatter.write_f32(writer, value as f32)
+        }
+    }
+
+    #[test]
+    fn custom_formatter() {
+        let floats = vec![0.1 + 0.2, 1.0 / 3.0];
+
+        let json = to_json_with(&floats, ShortFloats, 10).unwrap();
+        assert_eq!(json, b"[0.3,0.33333334]");
+
+        let json = JsonWith::<ShortFloats>::serialize(&floats).unwrap();
+        assert_eq!(json, b"[0.3,0.33333334]");
+
+        // The depth limit still applies.
+        assert!(to_json_with(&list(11), ShortFloats, 10).is_err());
+    }
+
+    #[test]
+    fn default_depth_limit_does_not_overflow() {
+        // Deep enough to overflow the stack without a limit.
+        let deep = list(100_000);
+
+        assert!(Pair::serialize(&deep).is_err());
+
+        // Dropping is recursive too, so unlink the list first.
+        let mut next = deep.next;
+        while let Some(node) = next {
+            next = node.next;
+        }
+    }
+
+    #[test]
+    fn checked_u32() {
+        let mut bytes = [0; 4];
+
+        assert_eq!(try_write_u32(&mut bytes, 0x0102_0304), Ok(()));
+        assert_eq!(bytes, [4, 3, 2, 1]);
+        assert_eq!(try_read_u32(&bytes), Some(0x0102_0304));
+
+        assert_eq!(try_read_u32(&bytes[..3]), None);
+        assert_eq!(try_write_u32(&mut [0; 5], 1), Err(LenError { len: 5 }));
+    }
+
+    #[test]
+    fn u64_round_trip() {
+        let mut bytes = [0; 8];
+
+        write_u64(&mut bytes, 0x0102_0304_0506_0708);
+        assert_eq!(bytes, [8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(read_u64(&bytes), 0x0102_0304_0506_0708);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn pair_keeps_wide_pointers() {
+        // Only the header is allocated, the pointer itself is never read.
+        let ptr = 0x1_2345_6789usize as *mut u8;
+        let header: *mut u8 = Pair { ptr, len: 3 }.into();
+
+        let pair = unsafe { Pair::from_u8_mut_ptr(header) };
+
+        assert_eq!(pair.ptr, ptr);
+        assert_eq!(pair.len, 3);
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = Header { ptr: 0x1234, len: 56 };
+        let bytes = header.encode();
+
+        assert_eq!(bytes.len(), 2 * WORD_LEN + 1);
+        assert_eq!(bytes[Header::FORMAT_OFFSET], Header::FORMAT);
+        assert_eq!(Header::decode(&bytes), Ok(header));
+
+        // Through the heap, as it crosses the boundary.
+        let src: *mut u8 = Pair { ptr: 0x1234 as *mut u8, len: 56 }.into();
+        let pair = unsafe { Pair::from_u8_mut_ptr(src) };
+
+        assert_eq!((pair.ptr as usize, pair.len), (0x1234, 56));
+    }
+
+    #[test]
+    fn header_rejects_unknown_format() {
+        let mut bytes = Header { ptr: 0x1234, len: 56 }.encode();
+        bytes[Header::FORMAT_OFFSET] = 2;
+
+        let e = Header::decode(&bytes).unwrap_err();
+        assert_eq!(e, HeaderError { format: 2 });
+        assert!(e.to_string().starts_with("Unknown header format 2, expected"));
+
+        // The header itself is still freed.
+        let src = alloc_header();
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), src, Header::LEN);
+        }
+
+        let result = unsafe { Pair::try_from_u8_mut_ptr(src) };
+        assert_eq!(result.unwrap_err(), HeaderError { format: 2 });
+    }
+
+    #[test]
+    fn header_pool_reuses_blocks() {
+        let header = alloc_header();
+
+        unsafe {
+            free_header(header);
+        }
+
+        assert_eq!(alloc_header(), header);
+
+        unsafe {
+            free_header(header);
+        }
     }
 }
@@ -2,23 +2,31 @@
 //!
 //! The contents of this module are *not guaranteed to be stable!*
 
-use std::mem;
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    panic::{self, AssertUnwindSafe},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use internal_callbacks;
-use data::{self, Pair};
+use outgoing;
+use data::{self, OwnedBytes, Pair};
 
 mod opcode {
     pub const ALLOC: u32 = 0;
     pub const DEALLOC: u32 = 1;
     pub const CALLBACK: u32 = 2;
+    pub const ALLOC_BYTES: u32 = 3;
+    pub const FREE_BYTES: u32 = 4;
 }
 
-pub extern fn incoming(op: u32, a: u32, b: u32) -> *mut u8 {
+pub extern fn incoming(op: u32, a: usize, b: usize) -> *mut u8 {
     use std::ptr;
     match op {
         // Allocate.
         opcode::ALLOC => {
-            alloc(a as usize)
+            alloc(a)
         }
 
         // Deallocate.
@@ -33,48 +41,217 @@ pub extern fn incoming(op: u32, a: u32, b: u32) -> *mut u8 {
         // Callback.
         opcode::CALLBACK => {
             unsafe {
-                callback(a as *mut u8)
+                callback(a as *mut u8, b)
+            }
+        }
+
+        // Allocate a buffer for a `Bytes` sent to the binary.
+        opcode::ALLOC_BYTES => {
+            data::alloc_received(a)
+        }
+
+        // Free a buffer of a `Bytes` sent by the binary, rejecting any other.
+        opcode::FREE_BYTES => {
+            match data::take_sent(a, b) {
+                Some(_) => ptr::null_mut(),
+                None => error(),
             }
         }
 
         // Unknown op code.
-        _ => (-1i32) as *mut u8,
+        _ => unknown_opcode(op),
     }
 }
 
+/// Returned for an opcode this version of stasis does not know.
+///
+/// This almost always means the runtime is newer than the binary, or the
+/// other way around. The runtime throws the diagnostic which is set alongside
+/// it. Like `error`, this is a signed 32-bit integer as far as the runtime is
+/// concerned.
+pub const UNKNOWN_OPCODE: isize = -2;
+
+/// Whether panics inside callbacks are rethrown in JavaScript.
+static CATCH_PANICS: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The number of callbacks on this thread running under `catch_unwind`.
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+
+    /// The message to throw for the panic unwinding on this thread.
+    static PANIC_MESSAGE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Rethrow panics inside callbacks as JavaScript exceptions.
+///
+/// A callback which panics is then caught with `catch_unwind`, and the runtime
+/// throws an exception to the JavaScript caller once the callback returns.
+/// This requires a target which unwinds on panic.
+pub fn set_catch_panics(enabled: bool) {
+    CATCH_PANICS.store(enabled, Ordering::SeqCst);
+}
+
+/// Whether a panic at this point would be rethrown in JavaScript.
+///
+/// Panic hooks use this to tell caught panics apart from top-level ones.
+pub fn is_catching() -> bool {
+    CATCHING.with(Cell::get) > 0
+}
+
+/// Set the message thrown for the panic currently unwinding.
+///
+/// This is meant to be called from a panic hook, which has access to the
+/// panic location. Without it the panic payload is thrown as is.
+pub fn set_panic_message(message: String) {
+    PANIC_MESSAGE.with(|m| *m.borrow_mut() = Some(message));
+}
+
+/// The sentinel returned when the runtime makes an invalid request.
+fn error() -> *mut u8 {
+    (-1i32) as *mut u8
+}
+
+/// Report an unknown opcode to the runtime, returning `UNKNOWN_OPCODE`.
+fn unknown_opcode(op: u32) -> *mut u8 {
+    outgoing::throw(&format!(
+        "stasis: The binary does not understand opcode {}. Make sure the \
+         runtime and the stasis crate are the same version.",
+        op,
+    ));
+
+    UNKNOWN_OPCODE as *mut u8
+}
+
 fn alloc(size: usize) -> *mut u8 {
-    let mut vec = Vec::with_capacity(size as usize);
-    let ptr = vec.as_mut_ptr();
-    mem::forget(vec);
-    ptr
+    // Headers are the most common allocation by far, and are pooled.
+    if size == Pair::HEADER_LEN {
+        return data::alloc_header();
+    }
+
+    OwnedBytes::alloc_raw(size)
 }
 
-unsafe fn dealloc(ptr: u32, len: u32) {
+unsafe fn dealloc(ptr: usize, len: usize) {
     let ptr = ptr as *mut u8;
-    let len = len as usize;
 
-    drop(Vec::from_raw_parts(ptr, len, len));
+    if len == Pair::HEADER_LEN {
+        return data::free_header(ptr);
+    }
+
+    drop(OwnedBytes::from_raw(ptr, len));
 }
 
-unsafe fn callback(data: *mut u8) -> *mut u8 {
-    const TRI_LEN: usize = 3 * mem::size_of::<u32>();
+unsafe fn callback(data: *mut u8, data_len: usize) -> *mut u8 {
+    // The callback id, and the pointer and length of the parameters.
+    const TRI_LEN: usize = 3 * data::WORD_LEN;
 
-    let bytes = Vec::from_raw_parts(data, TRI_LEN, TRI_LEN);
+    // Never read past a buffer the runtime did not fully provide.
+    if data_len != TRI_LEN {
+        return error();
+    }
 
-    let id = data::read_u32(&bytes[0..4]);
-    let ptr = data::read_u32(&bytes[4..8]) as *mut u8;
-    let len = data::read_u32(&bytes[8..12]) as usize;
+    let bytes = OwnedBytes::from_raw(data, TRI_LEN);
 
-    let ptr = ptr as *mut u8;
-    let len = len as usize;
+    let word = |i: usize| {
+        data::read_word(&bytes[i * data::WORD_LEN..(i + 1) * data::WORD_LEN])
+    };
 
-    let params = String::from_raw_parts(ptr, len, len);
+    let id = word(0) as u32;
+    let ptr = word(1) as *mut u8;
+    let len = word(2);
 
-    let ret = internal_callbacks::call(id, params);
+    let params = OwnedBytes::from_raw(ptr, len).into_vec();
 
-    match ret {
+    match run(id, params) {
         // Use `Pair` as an intermediate format.
-        Some(s) => Pair::from(s).into(),
+        Some(bytes) => Pair::from(bytes).into(),
         None => 0 as *mut u8,
     }
 }
+
+/// Run a callback, catching a panic if enabled with `set_catch_panics`.
+pub(crate) fn run(id: u32, params: Vec<u8>) -> Option<Vec<u8>> {
+    if CATCH_PANICS.load(Ordering::SeqCst) {
+        call_catching(id, params)
+    } else {
+        call(id, params)
+    }
+}
+
+/// Call a callback, throwing a JavaScript exception if it does not exist.
+fn call(id: u32, params: Vec<u8>) -> Option<Vec<u8>> {
+    match internal_callbacks::call(id, params) {
+        Ok(ret) => ret,
+        Err(e) => {
+            outgoing::throw(&format!("stasis: {}", e));
+            None
+        }
+    }
+}
+
+/// Call a callback, throwing a JavaScript exception if it panics.
+fn call_catching(id: u32, params: Vec<u8>) -> Option<Vec<u8>> {
+    CATCHING.with(|n| n.set(n.get() + 1));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| call(id, params)));
+
+    CATCHING.with(|n| n.set(n.get() - 1));
+
+    match result {
+        Ok(ret) => ret,
+        Err(payload) => {
+            let message = PANIC_MESSAGE
+                .with(|m| m.borrow_mut().take())
+                .unwrap_or_else(|| payload_message(payload));
+
+            outgoing::throw(&message);
+            None
+        }
+    }
+}
+
+fn payload_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(s) => *s,
+        Err(payload) => {
+            payload
+                .downcast_ref::<&str>()
+                .map(|&s| s.to_owned())
+                .unwrap_or_else(|| "Panic in a callback.".to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{incoming, error, opcode, payload_message, UNKNOWN_OPCODE};
+
+    #[test]
+    fn callback_rejects_short_buffer() {
+        // The pointer is never read, so a bogus value is fine here.
+        let ret = incoming(opcode::CALLBACK, 0xdead_beef, 8);
+
+        assert_eq!(ret, error());
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let ret = incoming(0xff, 0, 0);
+
+        assert_eq!(ret as isize, UNKNOWN_OPCODE);
+        assert_ne!(ret, error());
+    }
+
+    #[test]
+    fn free_bytes_rejects_unknown_buffers() {
+        // The pointer is never read, so a bogus value is fine here.
+        assert_eq!(incoming(opcode::FREE_BYTES, 0xdead_beef, 3), error());
+    }
+
+    #[test]
+    fn payload_messages() {
+        assert_eq!(payload_message(Box::new("static")), "static");
+        assert_eq!(payload_message(Box::new("owned".to_owned())), "owned");
+        assert_eq!(payload_message(Box::new(1)), "Panic in a callback.");
+    }
+}
@@ -1,15 +1,28 @@
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex, TryLockError};
 
-use serde_json;
 use serde::{Serialize, Deserialize};
 
+use data;
+use outgoing;
+#[cfg(feature = "trace")]
+use trace;
+use wire::{WireFormat, Json};
+
 lazy_static! {
     static ref HANDLER: Mutex<Callbacks> = Default::default();
+    static ref FALLBACK: Mutex<Option<Fallback>> = Default::default();
 }
 
+/// A handler for unknown callback ids.
+type Fallback = Box<dyn FnMut(u32, Vec<u8>) -> Option<Vec<u8>> + Send>;
+
 /// A registered callback.
-type Callback = Arc<Box<Fn(String) -> String + Send + Sync>>;
+///
+/// This returns `None` in place of a unit value.
+type Callback = Arc<Box<dyn Fn(Vec<u8>) -> Option<Vec<u8>> + Send + Sync>>;
 
 /// A global callback list.
 #[derive(Default)]
@@ -21,7 +34,7 @@ struct Callbacks {
 impl Callbacks {
     fn register<F>(&mut self, f: F) -> u32
     where
-        F: 'static + Send + Sync + Fn(String) -> String,
+        F: 'static + Send + Sync + Fn(Vec<u8>) -> Option<Vec<u8>>,
     {
         let id = self.current;
         self.current += 1;
@@ -32,6 +45,21 @@ impl Callbacks {
     }
 }
 
+/// Deserialize a callback argument, returning the error message on failure.
+fn deserialize_input<W, A>(input: &[u8]) -> Result<A, String>
+where
+    W: WireFormat,
+    A: for<'a> Deserialize<'a>,
+{
+    data::decode_incoming(|| W::deserialize(input)).map_err(|e| {
+        format!(
+            "Stasis: Failed to deserialize argument to callback.\n\
+             Error: {}",
+            e,
+        )
+    })
+}
+
 /// Register a callback.
 ///
 /// The function must be `Sync` as it can be recursively called.
@@ -40,46 +68,367 @@ where
     F: 'static + Send + Sync + Fn(A) -> R,
     A: for<'a> Deserialize<'a>,
     R: Serialize,
+{
+    register_with::<Json, F, A, R>(f)
+}
+
+/// Register a callback which may be given arguments of the wrong shape.
+///
+/// If the argument fails to deserialize, the JavaScript caller receives a
+/// thrown exception and `f` is not called. A plain `register` panics instead,
+/// which is only appropriate when the argument is known to match.
+pub fn register_fallible<F, A, R>(f: F) -> u32
+where
+    F: 'static + Send + Sync + Fn(A) -> R,
+    A: for<'a> Deserialize<'a>,
+    R: Serialize,
+{
+    register_inner::<Json, F, A, R>(f, true)
+}
+
+/// Register a callback which mutates its captured state.
+///
+/// The function is guarded by a mutex. A callback which ends up calling
+/// itself, such as through a JavaScript function it calls, panics rather than
+/// deadlocking.
+pub fn register_mut<F, A, R>(f: F) -> u32
+where
+    F: 'static + Send + FnMut(A) -> R,
+    A: for<'a> Deserialize<'a>,
+    R: Serialize,
+{
+    let f = Mutex::new(f);
+
+    register_inner::<Json, _, A, R>(move |input| {
+        let mut f = match f.try_lock() {
+            Ok(f) => f,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                panic!(
+                    "Stasis: A mutable callback was called recursively. Use \
+                     `register_callback` for callbacks which may re-enter."
+                )
+            }
+        };
+
+        (*f)(input)
+    }, false)
+}
+
+/// Register a callback using a custom wire format.
+pub fn register_with<W, F, A, R>(f: F) -> u32
+where
+    W: WireFormat,
+    F: 'static + Send + Sync + Fn(A) -> R,
+    A: for<'a> Deserialize<'a>,
+    R: Serialize,
+{
+    register_inner::<W, F, A, R>(f, false)
+}
+
+/// Register a callback, throwing rather than panicking on a bad argument if
+/// `fallible` is set.
+fn register_inner<W, F, A, R>(f: F, fallible: bool) -> u32
+where
+    W: WireFormat,
+    F: 'static + Send + Sync + Fn(A) -> R,
+    A: for<'a> Deserialize<'a>,
+    R: Serialize,
 {
     let mut guard = HANDLER.lock().unwrap();
 
     guard.register(move |input| {
-        // This is guaranteed to never fail by the user.
-        let input = match serde_json::from_str(&input) {
-            Ok(o) => o,
+        let input = match deserialize_input::<W, A>(&input) {
+            Ok(input) => input,
+            Err(message) => {
+                if !fallible {
+                    panic!("{}", message);
+                }
+
+                outgoing::throw(&message);
+                return None;
+            }
+        };
+
+        let output = f(input);
+
+        let (output, outgoing) =
+            data::encode_outgoing(|| W::serialize(&output));
+
+        let output = match output {
+            Ok(output) => output,
             Err(e) => {
                 panic!(
-                    "Stasis: Failed to deserialize argument to callback.\n\
+                    "Stasis: Failed to serialize callback return value.\n\
                      Error: {}",
                     e,
                 )
             }
         };
 
-        let output = f(input);
+        // The return value is handed over as soon as the callback returns.
+        outgoing.send();
+
+        // Optimize for the null pointer.
+        if output == W::UNIT {
+            None
+        } else {
+            Some(output)
+        }
+    })
+}
+
+/// Attach an anonymous handler.
+///
+/// Handlers are not bound to a name, JavaScript reaches them by id. As the
+/// handler is `FnMut` it is guarded by a mutex, like with `register_mut`. A
+/// handler which ends up calling itself panics rather than deadlocking, and a
+/// handler which panicked before can still be called.
+pub fn attach<F, A>(f: F) -> u32
+where
+    F: 'static + Send + FnMut(A),
+    A: for<'a> Deserialize<'a>,
+{
+    let f = Mutex::new(f);
+
+    let mut guard = HANDLER.lock().unwrap();
+
+    guard.register(move |input| {
+        let input = deserialize_input::<Json, A>(&input)
+            .unwrap_or_else(|message| panic!("{}", message));
+
+        let mut f = match f.try_lock() {
+            Ok(f) => f,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                panic!("Stasis: An attached handler was called recursively.")
+            }
+        };
+
+        (*f)(input);
 
-        // This should also never fail.
-        serde_json::to_string(&output).unwrap()
+        None
     })
 }
 
-pub fn call(id: u32, args: String) -> Option<String> {
+/// Remove a registered callback.
+///
+/// Removing a callback that is currently running is allowed, the running call
+/// will finish normally.
+pub fn detach(id: u32) {
+    HANDLER.lock().unwrap().registered.remove(&id);
+}
+
+/// Set a handler for calls to unknown callback ids.
+///
+/// This is useful when JavaScript may still call a callback that has just been
+/// removed. The handler receives the id and the encoded argument, and returns
+/// an encoded value or `None` for `undefined`. Without a handler, calling an
+/// unknown id is a `CallbackError::Missing`, which JavaScript receives as a
+/// thrown exception.
+///
+/// The handler must not call an unknown id itself.
+pub fn set_fallback<F>(f: F)
+where
+    F: 'static + Send + FnMut(u32, Vec<u8>) -> Option<Vec<u8>>,
+{
+    *FALLBACK.lock().unwrap() = Some(Box::new(f));
+}
+
+/// An error calling a callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallbackError {
+    /// No callback has this id, and there is no fallback. The callback was
+    /// either never registered or has since been removed.
+    Missing(u32),
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CallbackError::Missing(id) => write!(
+                f,
+                "No callback with id {}. It was never registered, or has \
+                 already been removed.",
+                id,
+            ),
+        }
+    }
+}
+
+impl Error for CallbackError {}
+
+/// Call a callback, or the fallback if there is no callback with this id.
+///
+/// This returns `None` in place of a unit value.
+pub fn call(id: u32, args: Vec<u8>) -> Result<Option<Vec<u8>>, CallbackError> {
     let guard = HANDLER.lock().unwrap();
 
     let f = guard.registered
         .get(&id)
-        .cloned()
-        .expect(
-            "FATAL: Failed to find callback. Make sure to register all\
-             callbacks."
-        );
+        .cloned();
 
     // Important: A callback may be called recursively.
     drop(guard);
 
-    match f(args) {
-        // Optimize for the null pointer.
-        ref s if s == "null" => None,
-        s => Some(s),
+    if let Some(f) = f {
+        #[cfg(feature = "trace")]
+        let crossing = trace::callback(id, &args);
+
+        let ret = f(args);
+
+        #[cfg(feature = "trace")]
+        trace::callback_return(crossing, id, ret.as_deref());
+
+        return Ok(ret);
+    }
+
+    match *FALLBACK.lock().unwrap() {
+        Some(ref mut fallback) => Ok(fallback(id, args)),
+        None => Err(CallbackError::Missing(id)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde::{Serialize, Deserialize};
+    use serde_json;
+
+    use wire::WireFormat;
+    use super::{
+        attach, call, detach, register_mut, register_with, set_fallback,
+        CallbackError, HANDLER,
+    };
+
+    /// JSON with a `#` prefix.
+    struct Prefixed;
+
+    impl WireFormat for Prefixed {
+        type Error = serde_json::Error;
+
+        const UNIT: &'static [u8] = b"#null";
+
+        fn serialize<T>(t: &T) -> Result<Vec<u8>, Self::Error>
+        where
+            T: ?Sized + Serialize,
+        {
+            let mut out = b"#".to_vec();
+            out.extend(serde_json::to_vec(t)?);
+            Ok(out)
+        }
+
+        fn deserialize<T>(bytes: &[u8]) -> Result<T, Self::Error>
+        where
+            T: for<'a> Deserialize<'a>,
+        {
+            serde_json::from_slice(&bytes[1..])
+        }
+    }
+
+    #[test]
+    fn fallback() {
+        let id = attach(|()| ());
+        detach(id);
+
+        // This is the only test which sets a fallback.
+        assert_eq!(call(id, b"x".to_vec()), Err(CallbackError::Missing(id)));
+
+        set_fallback(|id, args| {
+            let mut out = format!("{}:", id).into_bytes();
+            out.extend(args);
+            Some(out)
+        });
+
+        let expected = format!("{}:x", id).into_bytes();
+        assert_eq!(call(id, b"x".to_vec()), Ok(Some(expected)));
+    }
+
+    #[test]
+    fn custom_wire_format() {
+        let double = register_with::<Prefixed, _, _, _>(|n: u32| n * 2);
+        let unit = register_with::<Prefixed, _, _, _>(|_: u32| ());
+
+        assert_eq!(call(double, b"#21".to_vec()), Ok(Some(b"#42".to_vec())));
+        assert_eq!(call(unit, b"#21".to_vec()), Ok(None));
+    }
+
+    #[test]
+    fn attach_and_detach() {
+        let total = Arc::new(Mutex::new(0));
+
+        let id = {
+            let total = total.clone();
+            attach(move |n: u32| *total.lock().unwrap() += n)
+        };
+
+        assert_eq!(call(id, b"2".to_vec()), Ok(None));
+        assert_eq!(call(id, b"3".to_vec()), Ok(None));
+        assert_eq!(*total.lock().unwrap(), 5);
+
+        detach(id);
+
+        assert!(!HANDLER.lock().unwrap().registered.contains_key(&id));
+    }
+
+    #[test]
+    #[should_panic(expected = "called recursively")]
+    fn attach_reentry_panics() {
+        static ID: AtomicUsize = AtomicUsize::new(0);
+
+        let id = attach(|()| {
+            let _ = call(ID.load(Ordering::SeqCst) as u32, b"null".to_vec());
+        });
+
+        ID.store(id as usize, Ordering::SeqCst);
+        let _ = call(id, b"null".to_vec());
+    }
+
+    #[test]
+    fn attach_recovers_from_panics() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let id = {
+            let calls = calls.clone();
+
+            attach(move |fail: bool| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                assert!(!fail, "handler failed");
+            })
+        };
+
+        let ret = panic::catch_unwind(|| call(id, b"true".to_vec()));
+        assert!(ret.is_err());
+
+        assert_eq!(call(id, b"false".to_vec()), Ok(None));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn mutable_callback() {
+        let mut total = 0;
+        let id = register_mut(move |n: u32| {
+            total += n;
+            total
+        });
+
+        assert_eq!(call(id, b"2".to_vec()), Ok(Some(b"2".to_vec())));
+        assert_eq!(call(id, b"3".to_vec()), Ok(Some(b"5".to_vec())));
+    }
+
+    #[test]
+    #[should_panic(expected = "called recursively")]
+    fn mutable_callback_reentry_panics() {
+        static ID: AtomicUsize = AtomicUsize::new(0);
+
+        let id = register_mut(|()| {
+            let _ = call(ID.load(Ordering::SeqCst) as u32, b"null".to_vec());
+        });
+
+        ID.store(id as usize, Ordering::SeqCst);
+        let _ = call(id, b"null".to_vec());
     }
 }
@@ -2,8 +2,26 @@ extern crate serde;
 extern crate serde_json;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate lazy_static;
+#[cfg(feature = "messagepack")]
+extern crate rmp_serde;
 
 pub mod outgoing;
 pub mod incoming;
-mod internal_callbacks;
+pub mod internal_callbacks;
+pub mod wire;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "trace")]
+pub mod trace;
 mod data;
+
+pub use data::{
+    Bytes,
+    Header,
+    HeaderError,
+    LenError,
+    OwnedBytes,
+    decode_incoming,
+    try_read_u32,
+    try_write_u32,
+};
@@ -0,0 +1,529 @@
+//! A mock runtime for native tests.
+//!
+//! This module is only available with the `mock` feature. It provides
+//! `__stasis_call` in pure Rust, so code which calls into JavaScript can run
+//! under a regular `cargo test`. As there is no JavaScript engine, registered
+//! code is never run. Instead, each function is implemented by a Rust closure
+//! given to `implement`.
+//!
+//! Implementations are looked up by function name alone, and are shared by
+//! every module and thread. Tests which run in parallel should use distinct
+//! names. Evaluated code is looked up by its source text, and receives an
+//! empty array.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{self, Value, json};
+
+use data::{self, OwnedBytes, Pair};
+use incoming;
+use outgoing::opcode;
+
+/// A mocked JavaScript function.
+type Implementation = Arc<dyn Fn(Value) -> Value + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    modules: u32,
+    functions: HashSet<(u32, String)>,
+    async_functions: HashSet<(u32, String)>,
+    memory_functions: HashSet<(u32, String)>,
+    callbacks: HashMap<(u32, String), u32>,
+    implementations: HashMap<String, Implementation>,
+    released: HashSet<u32>,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Default::default();
+}
+
+thread_local! {
+    /// The exception pending on this thread, as tests run in parallel.
+    static THROWN: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Implement a JavaScript function with a Rust closure.
+///
+/// The closure receives the arguments as a JSON array, and returns the value
+/// to hand back to Rust. This replaces any previous implementation.
+pub fn implement<F>(name: &str, f: F)
+where
+    F: Fn(Value) -> Value + Send + Sync + 'static,
+{
+    STATE
+        .lock()
+        .unwrap()
+        .implementations
+        .insert(name.to_owned(), Arc::new(f));
+}
+
+/// The return value of a function which returns `undefined`.
+///
+/// JSON has no `undefined`, so an implementation returns this marker instead.
+/// It is handed back to Rust as a null pointer, just like the runtime does for
+/// a function without a `return` statement.
+pub fn undefined() -> Value {
+    json!({ "$undefined": true })
+}
+
+/// The return value of a function which throws an exception.
+///
+/// Like the runtime, a call which catches exceptions reports `message` as the
+/// error. Any other call panics, as an uncaught exception is fatal.
+pub fn exception(message: &str) -> Value {
+    json!({ "$throw": message })
+}
+
+/// Hand a byte buffer back to Rust, like a returned `Uint8Array`.
+///
+/// This allocates the buffer the way the runtime does, so the returned value
+/// can be read as a `Bytes`.
+pub fn bytes(bytes: &[u8]) -> Value {
+    if bytes.is_empty() {
+        return json!({ "$bytes": [0, 0] });
+    }
+
+    let ptr = data::alloc_received(bytes.len());
+
+    unsafe {
+        ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len());
+    }
+
+    json!({ "$bytes": [ptr as usize, bytes.len()] })
+}
+
+/// Whether a function has been registered on a module.
+pub fn is_registered(module_id: u32, name: &str) -> bool {
+    STATE
+        .lock()
+        .unwrap()
+        .functions
+        .contains(&(module_id, name.to_owned()))
+}
+
+/// Whether a function has been registered on a module as async.
+pub fn is_async(module_id: u32, name: &str) -> bool {
+    STATE
+        .lock()
+        .unwrap()
+        .async_functions
+        .contains(&(module_id, name.to_owned()))
+}
+
+/// Whether a function has been registered on a module with access to memory.
+///
+/// The mock runtime shares the address space of the test, so such a function
+/// can read the memory behind a pointer directly, just like `this.memory`.
+pub fn has_memory(module_id: u32, name: &str) -> bool {
+    STATE
+        .lock()
+        .unwrap()
+        .memory_functions
+        .contains(&(module_id, name.to_owned()))
+}
+
+/// Whether a handle has been released.
+pub fn is_released(id: u32) -> bool {
+    STATE.lock().unwrap().released.contains(&id)
+}
+
+/// Call a callback registered on a module, like `this.callbacks[name]` would.
+///
+/// Returns `Err` with the message of the exception the runtime would throw,
+/// which happens when the callback panics with panic catching enabled.
+///
+/// # Panics
+///
+/// This function will panic if no such callback exists.
+pub fn callback(
+    module_id: u32,
+    name: &str,
+    args: Value,
+) -> Result<Value, String> {
+    let id = STATE
+        .lock()
+        .unwrap()
+        .callbacks
+        .get(&(module_id, name.to_owned()))
+        .cloned();
+
+    let id = match id {
+        Some(id) => id,
+        None => panic!("mock: no callback '{}' on module {}", name, module_id),
+    };
+
+    let params = serde_json::to_vec(&args).unwrap();
+    let ret = incoming::run(id, params);
+
+    if let Some(message) = THROWN.with(|t| t.borrow_mut().take()) {
+        return Err(message);
+    }
+
+    match ret {
+        Some(bytes) => Ok(serde_json::from_slice(&bytes).unwrap()),
+        None => Ok(Value::Null),
+    }
+}
+
+/// Turn each `Bytes` sent by the binary into an array of numbers, freeing its
+/// buffer like the runtime does.
+fn revive_bytes(value: Value) -> Value {
+    match value {
+        Value::Array(values) => {
+            Value::Array(values.into_iter().map(revive_bytes).collect())
+        }
+        Value::Object(map) => {
+            let raw = map.get("$bytes").and_then(|raw| {
+                Some((raw.get(0)?.as_u64()? as usize, raw.get(1)?.as_u64()?))
+            });
+
+            let bytes = match raw {
+                Some((_, 0)) => Some(Vec::new()),
+                Some((ptr, len)) => data::take_sent(ptr, len as usize)
+                    .map(OwnedBytes::into_vec),
+                None => None,
+            };
+
+            match bytes {
+                Some(bytes) => json!(bytes),
+                None => Value::Object(
+                    map.into_iter()
+                        .map(|(k, v)| (k, revive_bytes(v)))
+                        .collect(),
+                ),
+            }
+        }
+        value => value,
+    }
+}
+
+/// Take ownership of a buffer handed over by the binary.
+unsafe fn take_json<T>(ptr: usize, len: usize) -> T
+where
+    T: for<'a> ::serde::Deserialize<'a>,
+{
+    let bytes = OwnedBytes::from_raw(ptr as *mut u8, len);
+
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+/// Hand a value back to the binary.
+fn make_pair(value: &Value) -> usize {
+    let header: *mut u8 = Pair::serialize(value).unwrap().into();
+
+    header as usize
+}
+
+/// Run the implementation of `name`.
+fn run(name: &str, args: Value, catch: bool) -> usize {
+    let f = STATE
+        .lock()
+        .unwrap()
+        .implementations
+        .get(name)
+        .cloned();
+
+    let f = match f {
+        Some(f) => f,
+        // Traced payloads go to stderr unless a test wants to see them.
+        #[cfg(feature = "trace")]
+        None if name == ::trace::FUNCTION => {
+            eprintln!("{}", args[0].as_str().unwrap_or_default());
+            return 0;
+        }
+        None => panic!("mock: no implementation of '{}'", name),
+    };
+
+    // The lock is released, so the implementation may call back into Rust.
+    let ret = f(args);
+
+    if catch {
+        make_pair(&tag(ret))
+    } else if let Some(message) = ret.get("$throw") {
+        panic!("mock: uncaught exception in '{}': {}", name, message)
+    } else if ret == undefined() {
+        0
+    } else {
+        make_pair(&ret)
+    }
+}
+
+/// Tag the return value of a call which catches exceptions, and send
+/// `undefined` as `null`.
+fn tag(ret: Value) -> Value {
+    match ret.get("$throw") {
+        Some(message) => json!({ "err": message }),
+        None if ret == undefined() => json!({ "ok": null }),
+        None => json!({ "ok": ret }),
+    }
+}
+
+fn call_fn(ptr: usize, len: usize) -> usize {
+    #[derive(Deserialize)]
+    struct Call {
+        name: String,
+        args: Value,
+        #[serde(default)]
+        catch: bool,
+    }
+
+    let call: Call = unsafe { take_json(ptr, len) };
+
+    // Make sure this is always an array, like the runtime does.
+    let args = match revive_bytes(call.args) {
+        Value::Array(args) => Value::Array(args),
+        args => Value::Array(vec![args]),
+    };
+
+    run(&call.name, args, call.catch)
+}
+
+fn call_fn_batch(ptr: usize, len: usize) -> usize {
+    #[derive(Deserialize)]
+    struct CallBatch {
+        calls: Vec<(String, Value)>,
+    }
+
+    let batch: CallBatch = unsafe { take_json(ptr, len) };
+
+    let rets: Vec<_> = batch
+        .calls
+        .into_iter()
+        .map(|(name, args)| {
+            let args = match revive_bytes(args) {
+                Value::Array(args) => Value::Array(args),
+                args => Value::Array(vec![args]),
+            };
+
+            let f = STATE.lock().unwrap().implementations.get(&name).cloned();
+
+            // A missing function throws, like it does in the runtime.
+            match f {
+                Some(f) => tag(f(args)),
+                None => json!({ "err": format!("'{}' is missing", name) }),
+            }
+        })
+        .collect();
+
+    make_pair(&Value::Array(rets))
+}
+
+fn eval(ptr: usize, len: usize) -> usize {
+    #[derive(Deserialize)]
+    struct Eval {
+        code: String,
+        #[serde(default)]
+        catch: bool,
+    }
+
+    let eval: Eval = unsafe { take_json(ptr, len) };
+
+    run(&eval.code, Value::Array(Vec::new()), eval.catch)
+}
+
+/// The mocked call interface.
+///
+/// A panic here, such as from a mocked implementation or an unknown function,
+/// unwinds into the test which made the call.
+#[no_mangle]
+pub extern "C-unwind" fn __stasis_call(op: u32, a: usize, b: usize) -> usize {
+    #[derive(Deserialize)]
+    struct RegisterFn {
+        id: u32,
+        name: String,
+        #[serde(default, rename = "async")]
+        is_async: bool,
+        #[serde(default)]
+        memory: bool,
+    }
+
+    #[derive(Deserialize)]
+    struct RegisterFns {
+        id: u32,
+        fns: Vec<(String, String)>,
+    }
+
+    #[derive(Deserialize)]
+    struct RegisterCallback {
+        module: u32,
+        callback: u32,
+        name: String,
+    }
+
+    match op {
+        opcode::REGISTER_STASIS_CB => 0,
+
+        opcode::CREATE_MODULE => {
+            let mut state = STATE.lock().unwrap();
+            state.modules += 1;
+            state.modules as usize
+        }
+
+        opcode::REGISTER_FN => {
+            let json: RegisterFn = unsafe { take_json(a, b) };
+            let mut state = STATE.lock().unwrap();
+
+            if json.is_async {
+                state.async_functions.insert((json.id, json.name.clone()));
+            }
+
+            if json.memory {
+                state.memory_functions.insert((json.id, json.name.clone()));
+            }
+
+            state.functions.insert((json.id, json.name));
+            0
+        }
+
+        opcode::REGISTER_FN_BATCH => {
+            let json: RegisterFns = unsafe { take_json(a, b) };
+            let mut state = STATE.lock().unwrap();
+
+            for (name, _) in json.fns {
+                state.functions.insert((json.id, name));
+            }
+
+            0
+        }
+
+        opcode::REGISTER_CB => {
+            let json: RegisterCallback = unsafe { take_json(a, b) };
+            let mut state = STATE.lock().unwrap();
+
+            state.callbacks.insert((json.module, json.name), json.callback);
+            0
+        }
+
+        opcode::CALL_FN => call_fn(a, b),
+
+        opcode::CALL_FN_BATCH => call_fn_batch(a, b),
+
+        opcode::EVAL => eval(a, b),
+
+        opcode::RELEASE_HANDLE => {
+            STATE.lock().unwrap().released.insert(a as u32);
+            0
+        }
+
+        opcode::THROW => {
+            let message: String = unsafe { take_json(a, b) };
+
+            THROWN.with(|t| *t.borrow_mut() = Some(message));
+            0
+        }
+
+        // Like the runtime, which leaves it to the binary to complain.
+        _ => opcode::UNKNOWN_OPCODE,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{Value, json};
+
+    use outgoing;
+    use super::{
+        callback,
+        exception,
+        implement,
+        is_async,
+        is_registered,
+        undefined,
+    };
+
+    #[test]
+    fn call() {
+        implement("mock.add", |args| {
+            json!(args[0].as_u64().unwrap() + args[1].as_u64().unwrap())
+        });
+
+        let id = outgoing::create_module();
+        let sum: u32 = outgoing::call(id, "mock.add", (1, 2));
+
+        assert_eq!(sum, 3);
+    }
+
+    #[test]
+    fn undefined_return() {
+        implement("mock.noReturn", |_| undefined());
+        implement("mock.null", |_| Value::Null);
+
+        let id = outgoing::create_module();
+
+        let () = outgoing::call(id, "mock.noReturn", ());
+        assert_eq!(
+            outgoing::call_opt::<_, Value>(id, "mock.noReturn", ()),
+            None,
+        );
+
+        let () = outgoing::call(id, "mock.null", ());
+        assert_eq!(
+            outgoing::call_opt::<_, Value>(id, "mock.null", ()),
+            Some(Value::Null),
+        );
+    }
+
+    #[test]
+    fn exceptions() {
+        implement("mock.throw", |_| exception("boom"));
+
+        let id = outgoing::create_module();
+
+        assert_eq!(
+            outgoing::try_call::<_, Value>(id, "mock.throw", ()),
+            Err("boom".to_owned()),
+        );
+        assert_eq!(
+            outgoing::call_batch(id, &[("mock.throw", &json!([]))]),
+            vec![Err("boom".to_owned())],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "no implementation of 'mock.missing'")]
+    fn panics_reach_the_caller() {
+        let id = outgoing::create_module();
+
+        // This would abort the test binary if the panic could not unwind.
+        let () = outgoing::call(id, "mock.missing", ());
+    }
+
+    #[test]
+    #[should_panic(expected = "does not understand opcode 99")]
+    fn unknown_opcode() {
+        unsafe {
+            outgoing::__stasis_call(99, 0, 0);
+        }
+    }
+
+    #[test]
+    fn register() {
+        let id = outgoing::create_module();
+
+        outgoing::register_fn(id, "mock.single", "function() {}");
+        outgoing::register_fns(id, &[("mock.batch", "function() {}")]);
+        outgoing::register_async_fn(id, "mock.async", "async function() {}");
+
+        assert!(is_registered(id, "mock.single"));
+        assert!(is_registered(id, "mock.batch"));
+        assert!(is_registered(id, "mock.async"));
+        assert!(!is_registered(id, "mock.missing"));
+
+        assert!(is_async(id, "mock.async"));
+        assert!(!is_async(id, "mock.single"));
+    }
+
+    #[test]
+    fn callbacks() {
+        let id = outgoing::create_module();
+
+        outgoing::register_callback(id, "double", |n: u32| n * 2);
+        outgoing::register_callback(id, "unit", |()| ());
+
+        assert_eq!(callback(id, "double", json!(4)), Ok(json!(8)));
+        assert_eq!(callback(id, "unit", Value::Null), Ok(Value::Null));
+    }
+}
@@ -1,12 +1,21 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    Arc,
+    Mutex,
+    atomic::{AtomicBool, Ordering},
+};
 
-use serde_json;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Serializer, Deserialize};
+use serde::ser::SerializeTuple;
+use serde::de::IgnoredAny;
+use serde_json::{self, Value};
 
 use internal_callbacks;
-use data::Pair;
+use data::{self, Pair};
+use wire::{WireFormat, Json};
+#[cfg(feature = "trace")]
+use trace;
 
-extern {
+extern "C-unwind" {
     /// The stasis call interface.
     ///
     /// This takes an opcode and 2 arguments, returning a value. The opcodes
@@ -17,15 +26,56 @@ extern {
     /// 2: Register function
     /// 3: Register callback
     /// 4: Call function
-    fn __stasis_call(op: u32, a: u32, b: u32) -> u32;
+    /// 5: Throw an exception once the current callback returns
+    /// 6: Register a batch of functions
+    /// 7: Evaluate an expression
+    /// 8: Release a handle
+    /// 9: Call a batch of functions
+    ///
+    /// The arguments and return value are pointer-sized, so pointers are
+    /// passed through unchanged on both `wasm32` and `wasm64`.
+    ///
+    /// This may unwind, so that a panic in the `mock` runtime reaches the
+    /// test which made the call rather than aborting the test binary.
+    #[link_name = "__stasis_call"]
+    fn raw_stasis_call(op: u32, a: usize, b: usize) -> usize;
 }
 
-mod opcode {
+/// Make a call into the runtime.
+///
+/// # Panics
+///
+/// This function will panic if the runtime does not know the opcode, which
+/// means it is older than this version of stasis.
+pub(crate) unsafe fn __stasis_call(op: u32, a: usize, b: usize) -> usize {
+    let ret = raw_stasis_call(op, a, b);
+
+    if ret == opcode::UNKNOWN_OPCODE {
+        panic!(
+            "STASIS: The runtime does not understand opcode {}, it is too old \
+             for this version of stasis. Make sure the runtime and the stasis \
+             crate are the same version.",
+            op,
+        );
+    }
+
+    ret
+}
+
+pub(crate) mod opcode {
     pub const REGISTER_STASIS_CB: u32 = 0;
     pub const CREATE_MODULE: u32 = 1;
     pub const REGISTER_FN: u32 = 2;
     pub const REGISTER_CB: u32 = 3;
     pub const CALL_FN: u32 = 4;
+    pub const THROW: u32 = 5;
+    pub const REGISTER_FN_BATCH: u32 = 6;
+    pub const EVAL: u32 = 7;
+    pub const RELEASE_HANDLE: u32 = 8;
+    pub const CALL_FN_BATCH: u32 = 9;
+
+    /// Returned by the runtime for an opcode it does not know.
+    pub const UNKNOWN_OPCODE: usize = -2isize as usize;
 }
 
 lazy_static! {
@@ -34,11 +84,12 @@ lazy_static! {
     };
 }
 
-type StasisCallback = extern fn(op: u32, a: u32, b: u32) -> *mut u8;
+type StasisCallback = extern fn(op: u32, a: usize, b: usize) -> *mut u8;
 
 pub fn register_stasis_callback(f: StasisCallback) {
     unsafe {
-        __stasis_call(opcode::REGISTER_STASIS_CB, f as u32, 0);
+        // The runtime reads every `Pair` with the pointer size given here.
+        __stasis_call(opcode::REGISTER_STASIS_CB, f as usize, data::WORD_LEN);
     }
 }
 
@@ -57,25 +108,84 @@ pub fn create_module() -> u32 {
     drop(guard);
 
     unsafe {
-        __stasis_call(opcode::CREATE_MODULE, 0, 0)
+        __stasis_call(opcode::CREATE_MODULE, 0, 0) as u32
     }
 }
 
+/// The payload of a function registration.
+#[derive(Serialize, Default)]
+struct RegisterFn<'a, 'b> {
+    // TODO: Rename this to module_id?
+    id: u32,
+    name: &'a str,
+    code: &'b str,
+    #[serde(rename = "async", skip_serializing_if = "is_false")]
+    is_async: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    memory: bool,
+}
+
 pub fn register_fn(module_id: u32, name: &str, code: &str) {
+    register(RegisterFn { id: module_id, name, code, ..Default::default() });
+}
+
+/// Register a function which returns a `Promise`.
+///
+/// The runtime refuses to call it synchronously, as the promise could not be
+/// sent back. It is meant to be called through the promise helpers instead.
+pub fn register_async_fn(module_id: u32, name: &str, code: &str) {
+    register(RegisterFn {
+        id: module_id,
+        name,
+        code,
+        is_async: true,
+        ..Default::default()
+    });
+}
+
+/// Register a function which can read linear memory through `this.memory`.
+pub fn register_memory_fn(module_id: u32, name: &str, code: &str) {
+    register(RegisterFn {
+        id: module_id,
+        name,
+        code,
+        memory: true,
+        ..Default::default()
+    });
+}
+
+fn register(data: RegisterFn) {
+    let Pair { ptr, len } = Pair::serialize(&data).unwrap();
+
+    unsafe {
+        __stasis_call(opcode::REGISTER_FN, ptr as usize, len);
+    }
+}
+
+/// Register many functions with a single call into the runtime.
+///
+/// The functions are registered in order. One which fails to compile is
+/// reported to the console and skipped, the rest are still registered.
+pub fn register_fns(module_id: u32, fns: &[(&str, &str)]) {
     #[derive(Serialize)]
-    struct RegisterFn<'a, 'b> {
-        // TODO: Rename this to module_id?
+    struct RegisterFns<'a, 'b: 'a, 'c: 'a> {
         id: u32,
-        name: &'a str,
-        code: &'b str,
+        fns: &'a [(&'b str, &'c str)],
     }
 
-    let data = RegisterFn { id: module_id, name, code };
+    let data = RegisterFns { id: module_id, fns };
 
     let Pair { ptr, len } = Pair::serialize(&data).unwrap();
 
     unsafe {
-        __stasis_call(opcode::REGISTER_FN, ptr as u32, len as u32);
+        __stasis_call(opcode::REGISTER_FN_BATCH, ptr as usize, len);
+    }
+}
+
+/// Release a JavaScript value held by the runtime.
+pub fn release_handle(id: u32) {
+    unsafe {
+        __stasis_call(opcode::RELEASE_HANDLE, id as usize, 0);
     }
 }
 
@@ -89,6 +199,53 @@ where
     A: for<'a> Deserialize<'a>,
     R: Serialize,
 {
+    register_callback_with::<Json, F, A, R>(module_id, name, f)
+}
+
+/// Register a callback which mutates its captured state. See
+/// `internal_callbacks::register_mut`.
+pub fn register_callback_mut<F, A, R>(module_id: u32, name: &str, f: F)
+where
+    F: 'static + Send + FnMut(A) -> R,
+    A: for<'a> Deserialize<'a>,
+    R: Serialize,
+{
+    let callback_id = internal_callbacks::register_mut(f);
+
+    register_callback_id(module_id, name, callback_id);
+}
+
+/// Register a callback using a custom wire format.
+///
+/// Only the callback's arguments and return value use the wire format, the
+/// registration itself is always sent as JSON.
+pub fn register_callback_with<W, F, A, R>(module_id: u32, name: &str, f: F)
+where
+    W: WireFormat,
+    F: 'static + Send + Sync + Fn(A) -> R,
+    A: for<'a> Deserialize<'a>,
+    R: Serialize,
+{
+    let callback_id = internal_callbacks::register_with::<W, F, A, R>(f);
+
+    register_callback_id(module_id, name, callback_id);
+}
+
+/// Register a callback which throws a JavaScript exception when given an
+/// argument it cannot deserialize. See `internal_callbacks::register_fallible`.
+pub fn register_callback_fallible<F, A, R>(module_id: u32, name: &str, f: F)
+where
+    F: 'static + Send + Sync + Fn(A) -> R,
+    A: for<'a> Deserialize<'a>,
+    R: Serialize,
+{
+    let callback_id = internal_callbacks::register_fallible(f);
+
+    register_callback_id(module_id, name, callback_id);
+}
+
+/// Bind a registered callback to a name on a module.
+fn register_callback_id(module_id: u32, name: &str, callback_id: u32) {
     #[derive(Serialize)]
     struct RegisterCallback<'a> {
         module: u32,
@@ -96,8 +253,6 @@ where
         name: &'a str,
     }
 
-    let callback_id = internal_callbacks::register(f);
-
     let data = RegisterCallback {
         module: module_id,
         callback: callback_id,
@@ -107,7 +262,7 @@ where
     let Pair { ptr, len } = Pair::serialize(&data).unwrap();
 
     unsafe {
-        __stasis_call(opcode::REGISTER_CB, ptr as u32, len as u32);
+        __stasis_call(opcode::REGISTER_CB, ptr as usize, len);
     }
 }
 
@@ -116,48 +271,689 @@ where
     T: Serialize,
     R: for<'a> Deserialize<'a>,
 {
+    call_with::<Json, T, R>(module_id, name, args)
+}
+
+/// Call a function using a custom wire format.
+pub fn call_with<W, T, R>(module_id: u32, name: &str, args: T) -> R
+where
+    W: WireFormat,
+    T: Serialize,
+    R: for<'a> Deserialize<'a>,
+{
+    let ret = call_raw::<W, T>(module_id, name, args, None, false);
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    unsafe {
+        read_return::<W, R>(ret)
+    }
+}
+
+/// Call a function, telling apart `undefined` from any other return value.
+///
+/// The runtime hands back a null pointer for `undefined`, which is read as
+/// `None`. Everything else, including an explicit `null`, is deserialized into
+/// `Some`. Use `R = serde_json::Value` to see a returned `null` as
+/// `Some(Value::Null)`.
+pub fn call_opt<T, R>(module_id: u32, name: &str, args: T) -> Option<R>
+where
+    T: Serialize,
+    R: for<'a> Deserialize<'a>,
+{
+    let ret = call_raw::<Json, T>(module_id, name, args, None, false);
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    unsafe {
+        read_return_opt::<Json, R>(ret)
+    }
+}
+
+/// Call a function which may be cooperatively cancelled.
+///
+/// The address of `cancel` is given to the runtime, which exposes it to the
+/// function as `this.cancelled()`. Returns `None` if the flag is set once the
+/// function returns, in which case the return value is discarded.
+pub fn call_cancellable<T, R>(
+    module_id: u32,
+    name: &str,
+    args: T,
+    cancel: &AtomicBool,
+) -> Option<R>
+where
+    T: Serialize,
+    R: for<'a> Deserialize<'a>,
+{
+    let ptr = cancel as *const AtomicBool as usize;
+    let ret = call_raw::<Json, T>(module_id, name, args, Some(ptr), false);
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    unsafe {
+        if cancel.load(Ordering::SeqCst) {
+            discard(ret);
+            None
+        } else {
+            Some(read_return::<Json, R>(ret))
+        }
+    }
+}
+
+/// Call a function whose return value has no known shape.
+///
+/// This never fails to read the return value. A null return is read as
+/// `Value::Null`, and a return which is not valid JSON is kept as a string.
+pub fn call_value<T>(module_id: u32, name: &str, args: T) -> Value
+where
+    T: Serialize,
+{
+    let ret = call_raw::<Json, T>(module_id, name, args, None, false);
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    unsafe {
+        read_value(ret)
+    }
+}
+
+/// Call many functions with a single call into the runtime.
+///
+/// The calls run in order, each catching its own exception like `try_call`,
+/// so a failed call does not stop the rest. The results are returned in the
+/// same order, with `undefined` read as `Value::Null`.
+pub fn call_batch(
+    module_id: u32,
+    calls: &[(&str, &Value)],
+) -> Vec<Result<Value, String>> {
     #[derive(Serialize)]
-    struct Call<'a, T> {
+    struct CallBatch<'a, 'b: 'a, 'c: 'a> {
         id: u32,
-        name: &'a str,
-        args: T,
+        calls: &'a [(&'b str, &'c Value)],
+    }
+
+    if calls.is_empty() {
+        return Vec::new();
+    }
+
+    let batch = CallBatch { id: module_id, calls };
+
+    let bytes = match data::to_json(&batch, data::DEFAULT_DEPTH_LIMIT) {
+        Ok(bytes) => bytes,
+        Err(e) => panic!("Failed to serialize arguments: {}", e),
+    };
+
+    let ret = cross(opcode::CALL_FN_BATCH, module_id, "(batch)", bytes);
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    let outcomes = unsafe {
+        read_return::<Json, Vec<Outcome<Value>>>(ret)
+    };
+
+    outcomes
+        .into_iter()
+        .map(|outcome| match outcome {
+            Outcome::Ok(r) => Ok(r),
+            Outcome::Err(e) => Err(e),
+        })
+        .collect()
+}
+
+/// An encoded return value, kept alive while it is read. See `call_scoped`.
+pub struct ScopedReturn {
+    bytes: Vec<u8>,
+}
+
+impl ScopedReturn {
+    /// Deserialize the return value, borrowing from it where possible.
+    ///
+    /// Strings without escapes are borrowed as `&str` straight out of the
+    /// buffer. Panics if the return value does not fit `R`, just like `call`.
+    pub fn deserialize<'de, R>(&'de self) -> R
+    where
+        R: Deserialize<'de>,
+    {
+        match data::decode_incoming(|| serde_json::from_slice(&self.bytes)) {
+            Ok(r) => r,
+            Err(e) => {
+                panic!(
+                    "STASIS: Failed to deserialize return value.\n\
+                     Given '{}'\n\
+                     Error {}",
+                    String::from_utf8_lossy(&self.bytes),
+                    e
+                )
+            }
+        }
+    }
+
+    /// The return value as encoded JSON.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Call a function, reading the return value inside `f`.
+///
+/// The encoded return value is only freed once `f` returns, so it can be
+/// deserialized into types which borrow from it.
+pub fn call_scoped<T, U, F>(module_id: u32, name: &str, args: T, f: F) -> U
+where
+    T: Serialize,
+    F: FnOnce(&ScopedReturn) -> U,
+{
+    let ret = call_raw::<Json, T>(module_id, name, args, None, false);
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    let bytes = if ret.is_null() {
+        Json::UNIT.to_vec()
+    } else {
+        unsafe { Pair::from_u8_mut_ptr(ret).into_bytes() }
+    };
+
+    f(&ScopedReturn { bytes })
+}
+
+/// Call a function with arguments which are already encoded as JSON.
+///
+/// The encoded return value is handed back as-is, with `null` for
+/// `undefined`. The arguments are checked to be valid JSON, but are never
+/// deserialized.
+pub fn call_json(module_id: u32, name: &str, args: &str) -> String {
+    if let Err(e) = serde_json::from_str::<IgnoredAny>(args) {
+        panic!("Invalid JSON arguments: {}", e);
+    }
+
+    let mut bytes = Vec::with_capacity(args.len() + name.len() + 32);
+    let head = format!(r#"{{"id":{},"name":"#, module_id);
+
+    bytes.extend_from_slice(head.as_bytes());
+    serde_json::to_writer(&mut bytes, name).unwrap();
+    bytes.extend_from_slice(br#","args":"#);
+    bytes.extend_from_slice(args.as_bytes());
+    bytes.push(b'}');
+
+    let ret = call_bytes(module_id, name, bytes);
+
+    if ret.is_null() {
+        return "null".to_owned();
+    }
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    let bytes = unsafe { Pair::from_u8_mut_ptr(ret).into_bytes() };
+
+    match String::from_utf8(bytes) {
+        Ok(json) => json,
+        Err(e) => {
+            panic!(
+                "STASIS: Return value of '{}' is not valid UTF-8.\n\
+                 Error: {}",
+                name,
+                e.utf8_error(),
+            )
+        }
+    }
+}
+
+/// Call a function, catching any exception it throws.
+///
+/// Returns the message of the exception if one was thrown. Exceptions which
+/// are not an `Error` are converted to a string.
+pub fn try_call<T, R>(
+    module_id: u32,
+    name: &str,
+    args: T,
+) -> Result<R, String>
+where
+    T: Serialize,
+    R: for<'a> Deserialize<'a>,
+{
+    let ret = call_raw::<Json, T>(module_id, name, args, None, true);
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    let outcome = unsafe {
+        read_return::<Json, Outcome<R>>(ret)
+    };
+
+    match outcome {
+        Outcome::Ok(r) => Ok(r),
+        Outcome::Err(e) => Err(e),
+    }
+}
+
+/// Evaluate a JavaScript expression on a module.
+///
+/// The expression sees the same `this` as the module's functions, and is not
+/// registered on it.
+pub fn eval<R>(module_id: u32, code: &str) -> R
+where
+    R: for<'a> Deserialize<'a>,
+{
+    let ret = eval_raw(module_id, code, false);
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    unsafe {
+        read_return::<Json, R>(ret)
+    }
+}
+
+/// Evaluate a JavaScript expression, catching any exception it throws.
+///
+/// See `try_call` for how exceptions are returned.
+pub fn try_eval<R>(module_id: u32, code: &str) -> Result<R, String>
+where
+    R: for<'a> Deserialize<'a>,
+{
+    let ret = eval_raw(module_id, code, true);
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    let outcome = unsafe {
+        read_return::<Json, Outcome<R>>(ret)
+    };
+
+    match outcome {
+        Outcome::Ok(r) => Ok(r),
+        Outcome::Err(e) => Err(e),
+    }
+}
+
+/// Evaluate an expression, returning the raw return pointer.
+fn eval_raw(module_id: u32, code: &str, catch: bool) -> *mut u8 {
+    #[derive(Serialize)]
+    struct Eval<'a> {
+        id: u32,
+        code: &'a str,
+        #[serde(skip_serializing_if = "is_false")]
+        catch: bool,
+    }
+
+    let data = Eval { id: module_id, code, catch };
+
+    let Pair { ptr, len } = Pair::serialize(&data).unwrap();
+
+    unsafe {
+        __stasis_call(opcode::EVAL, ptr as usize, len) as *mut u8
+    }
+}
+
+/// The tagged return value of a call which catches exceptions.
+#[derive(Deserialize)]
+enum Outcome<R> {
+    #[serde(rename = "ok")]
+    Ok(R),
+
+    #[serde(rename = "err")]
+    Err(String),
+}
+
+/// Throw a JavaScript exception with the given message.
+///
+/// The exception is thrown by the runtime once the current callback returns,
+/// so the Rust side is never unwound by JavaScript.
+pub fn throw(message: &str) {
+    let Pair { ptr, len } = Pair::serialize(message).unwrap();
+
+    unsafe {
+        __stasis_call(opcode::THROW, ptr as usize, len);
+    }
+}
+
+/// A function call.
+#[derive(Serialize)]
+struct Call<'a, T> {
+    id: u32,
+    name: &'a str,
+    #[serde(serialize_with = "serialize_args", bound = "T: Serialize")]
+    args: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cancel: Option<usize>,
+    #[serde(skip_serializing_if = "is_false")]
+    catch: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// `()` is sent as an empty array, so the function is called without any
+/// arguments rather than with a single `null`.
+fn serialize_args<T, S>(args: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    if data::is_unit::<T>() {
+        serializer.serialize_tuple(0)?.end()
+    } else {
+        args.serialize(serializer)
     }
+}
 
+/// Call a function, returning the raw return pointer.
+fn call_raw<W, T>(
+    module_id: u32,
+    name: &str,
+    args: T,
+    cancel: Option<usize>,
+    catch: bool,
+) -> *mut u8
+where
+    W: WireFormat,
+    T: Serialize,
+{
     let call = Call {
         id: module_id,
         name,
         args,
+        cancel,
+        catch,
     };
 
-    let Pair { ptr, len } = match Pair::serialize(call) {
-        Ok(pair) => pair,
+    let (result, outgoing) = data::encode_outgoing(|| W::serialize(&call));
+
+    match result {
+        Ok(bytes) => {
+            outgoing.send();
+            call_bytes(module_id, name, bytes)
+        }
         Err(e) => panic!("Failed to serialize arguments: {}", e),
-    };
+    }
+}
+
+/// Send an encoded call, returning the raw return pointer.
+fn call_bytes(module_id: u32, name: &str, bytes: Vec<u8>) -> *mut u8 {
+    cross(opcode::CALL_FN, module_id, name, bytes)
+}
+
+/// Send an encoded payload with `op`, returning the raw return pointer.
+///
+/// The module id and name are only used by the `trace` feature.
+#[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+fn cross(op: u32, module_id: u32, name: &str, bytes: Vec<u8>) -> *mut u8 {
+    #[cfg(feature = "trace")]
+    let crossing = trace::call(module_id, name, &bytes);
+
+    let Pair { ptr, len } = Pair::from(bytes);
+
+    unsafe {
+        let ret = __stasis_call(op, ptr as usize, len) as *mut u8;
+
+        #[cfg(feature = "trace")]
+        trace::call_return(crossing, module_id, name, ret);
+
+        ret
+    }
+}
 
-    let ret = unsafe {
-        __stasis_call(opcode::CALL_FN, ptr as u32, len as u32) as *mut u8
+/// Call a function with a list of strings as its first argument.
+///
+/// The call is encoded into a scratch buffer which is kept between calls, so
+/// after the first few calls the only allocation is a single exact-size copy
+/// handed to the runtime. The strings are written straight from the slice, no
+/// `String` is ever created.
+pub fn call_strs<T, R>(
+    module_id: u32,
+    name: &str,
+    strs: &[&str],
+    args: T,
+) -> R
+where
+    T: Serialize,
+    R: for<'a> Deserialize<'a>,
+{
+    let (bytes, outgoing) = encode_strs_call(module_id, name, strs, args);
+
+    outgoing.send();
+    let ret = call_bytes(module_id, name, bytes);
+
+    // `ret` is given to us by the FFI function so we must assume it is safe.
+    unsafe {
+        read_return::<Json, R>(ret)
+    }
+}
+
+fn encode_strs_call<T>(
+    module_id: u32,
+    name: &str,
+    strs: &[&str],
+    args: T,
+) -> (Vec<u8>, data::Outgoing)
+where
+    T: Serialize,
+{
+    lazy_static! {
+        static ref SCRATCH: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+    }
+
+    let call = Call {
+        id: module_id,
+        name,
+        args: StrsArgs { strs, args },
+        cancel: None,
+        catch: false,
     };
 
+    let mut scratch = SCRATCH.lock().unwrap();
+    scratch.clear();
+
+    let limit = data::DEFAULT_DEPTH_LIMIT;
+    let (result, outgoing) = data::encode_outgoing(|| {
+        data::write_json(&mut *scratch, &call, limit)
+    });
+    let result = result.map(|()| scratch.to_vec());
+
+    // Release the lock before a potential panic so it is never poisoned.
+    drop(scratch);
+
+    match result {
+        Ok(bytes) => (bytes, outgoing),
+        Err(e) => panic!("Failed to serialize arguments: {}", e),
+    }
+}
+
+/// The arguments of `call_strs`: the strings, followed by `args` unless it is
+/// `()`, as in `serialize_args`.
+struct StrsArgs<'a, T> {
+    strs: &'a [&'a str],
+    args: T,
+}
+
+impl<'a, T> Serialize for StrsArgs<'a, T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let unit = data::is_unit::<T>();
+
+        let mut tuple = serializer.serialize_tuple(if unit { 1 } else { 2 })?;
+        tuple.serialize_element(self.strs)?;
+
+        if !unit {
+            tuple.serialize_element(&self.args)?;
+        }
+
+        tuple.end()
+    }
+}
+
+/// Read a return value.
+///
+/// A null pointer is read as a unit. When `R` is `()` nothing is parsed, and
+/// any returned value is ignored.
+unsafe fn read_return<W, R>(ret: *mut u8) -> R
+where
+    W: WireFormat,
+    R: for<'a> Deserialize<'a>,
+{
+    // This is by far the most common return type, so skip parsing entirely.
+    if let Some(unit) = data::unit::<R>() {
+        discard(ret);
+        return unit;
+    }
+
     let value = if ret.is_null() {
-        "null".to_owned()
+        W::UNIT.to_vec()
     } else {
-        // `ret` is given to us by the FFI function so we must assume it is
-        // safe.
-        unsafe {
-            Pair::from_u8_mut_ptr(ret).into_string()
-        }
+        Pair::from_u8_mut_ptr(ret).into_bytes()
     };
 
-    match serde_json::from_str(&value) {
+    match data::decode_incoming(|| W::deserialize(&value)) {
         Ok(v) => v,
         Err(e) => {
             panic!(
                 "STASIS: Failed to deserialize return value.\n\
                  Given '{}'\n\
-                 Error {:?}",
-                value,
+                 Error {}",
+                String::from_utf8_lossy(&value),
                 e
             )
         }
     }
 }
+
+/// Read a return value, with `None` for `undefined`. See `call_opt`.
+unsafe fn read_return_opt<W, R>(ret: *mut u8) -> Option<R>
+where
+    W: WireFormat,
+    R: for<'a> Deserialize<'a>,
+{
+    if ret.is_null() {
+        None
+    } else {
+        Some(read_return::<W, R>(ret))
+    }
+}
+
+/// Read a return value of any shape. See `call_value`.
+unsafe fn read_value(ret: *mut u8) -> Value {
+    if ret.is_null() {
+        return Value::Null;
+    }
+
+    let bytes = Pair::from_u8_mut_ptr(ret).into_bytes();
+
+    match data::decode_incoming(|| serde_json::from_slice(&bytes)) {
+        Ok(value) => value,
+        Err(_) => Value::String(String::from_utf8_lossy(&bytes).into_owned()),
+    }
+}
+
+/// Free a return value without reading it.
+unsafe fn discard(ret: *mut u8) {
+    if !ret.is_null() {
+        // Any buffers the value refers to are freed along with it.
+        data::decode_incoming(|| drop(Pair::from_u8_mut_ptr(ret).into_bytes()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ptr;
+
+    use serde_json::{self, json};
+
+    use wire::Json;
+    use super::{
+        encode_strs_call,
+        read_return,
+        read_return_opt,
+        read_value,
+        Outcome,
+    };
+
+    /// There is no runtime in tests, but code which may call into it still
+    /// needs the symbol to link. The `mock` feature provides a real one.
+    #[cfg(not(feature = "mock"))]
+    #[no_mangle]
+    pub extern "C-unwind" fn __stasis_call(
+        _op: u32,
+        _a: usize,
+        _b: usize,
+    ) -> usize {
+        0
+    }
+
+    #[test]
+    fn strs_call() {
+        let (bytes, _) = encode_strs_call(1, "f", &["a", "b\"c"], (2, 3));
+
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            r#"{"id":1,"name":"f","args":[["a","b\"c"],[2,3]]}"#,
+        );
+
+        // The scratch buffer is cleared between calls.
+        let (bytes, _) = encode_strs_call(1, "f", &[], ());
+
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            r#"{"id":1,"name":"f","args":[[]]}"#,
+        );
+    }
+
+    #[test]
+    fn null_return() {
+        unsafe {
+            let () = read_return::<Json, _>(ptr::null_mut());
+            assert_eq!(read_return::<Json, Option<u32>>(ptr::null_mut()), None);
+        }
+    }
+
+    #[test]
+    fn value_return() {
+        use data::Pair;
+
+        fn pair(s: &str) -> *mut u8 {
+            Pair::from(s.to_owned()).into()
+        }
+
+        unsafe {
+            assert_eq!(read_return::<Json, u32>(pair("42")), 42);
+
+            // Values returned to a unit are freed and ignored.
+            let () = read_return::<Json, _>(pair("42"));
+            let () = read_return::<Json, _>(pair("not json"));
+        }
+    }
+
+    #[test]
+    fn undefined_return() {
+        use data::Pair;
+        use serde_json::Value;
+
+        unsafe {
+            let undefined = read_return_opt::<Json, Value>(ptr::null_mut());
+            let null = Pair::from("null".to_owned()).into();
+            let null = read_return_opt::<Json, Value>(null);
+
+            assert_eq!(undefined, None);
+            assert_eq!(null, Some(Value::Null));
+        }
+    }
+
+    #[test]
+    fn any_value_return() {
+        use data::Pair;
+
+        fn pair(s: &str) -> *mut u8 {
+            Pair::from(s.to_owned()).into()
+        }
+
+        unsafe {
+            assert_eq!(read_value(ptr::null_mut()), json!(null));
+            assert_eq!(read_value(pair(r#"{"a":[1]}"#)), json!({"a": [1]}));
+            assert_eq!(read_value(pair("not json")), json!("not json"));
+        }
+    }
+
+    #[test]
+    fn outcome() {
+        let ok: Outcome<()> = serde_json::from_str(r#"{"ok":null}"#).unwrap();
+        let err: Outcome<u32> = serde_json::from_str(r#"{"err":"boom"}"#)
+            .unwrap();
+
+        match (ok, err) {
+            (Outcome::Ok(()), Outcome::Err(ref e)) if e == "boom" => (),
+            _ => panic!("Wrong outcome"),
+        }
+    }
+}
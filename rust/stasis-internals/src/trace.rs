@@ -0,0 +1,244 @@
+//! Logging of every payload which crosses the boundary.
+//!
+//! This module is only available with the `trace` feature. Each call, its
+//! return value, and each callback argument and return value are logged with
+//! `console.debug`, exactly as encoded. This helps track down a mismatch
+//! between a Rust type and what JavaScript actually sends.
+//!
+//! Each call and callback is tagged with a correlation id, such as `#12`,
+//! which its return value is logged with as well. Ids are unique across every
+//! module and callback, and increase in the order the crossings started, so
+//! nested crossings can be told apart from their origin.
+//!
+//! Calls made while logging are not logged themselves.
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::slice;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use data::Header;
+use outgoing;
+
+/// The name of the logging function, which the mock runtime may implement.
+pub const FUNCTION: &str = "stasis.trace";
+
+lazy_static! {
+    /// The module the logging function is registered on.
+    static ref MODULE: Mutex<Option<u32>> = Default::default();
+}
+
+/// The correlation id of the next crossing. Zero is never handed out.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    static TRACING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Log an outgoing call, returning its correlation id.
+pub fn call(module_id: u32, name: &str, bytes: &[u8]) -> usize {
+    log(None, || format!("call {}.{} <- {}", module_id, name, text(bytes)))
+}
+
+/// Log the raw return pointer of a call, without taking ownership of it.
+///
+/// # Safety
+///
+/// `ret` must be null or a pointer handed back by the runtime.
+pub unsafe fn call_return(
+    crossing: usize,
+    module_id: u32,
+    name: &str,
+    ret: *mut u8,
+) {
+    let bytes = peek(ret);
+
+    log(Some(crossing), || {
+        format!("call {}.{} -> {}", module_id, name, returned(bytes))
+    });
+}
+
+/// Log the argument of a callback, returning its correlation id.
+pub fn callback(id: u32, bytes: &[u8]) -> usize {
+    log(None, || format!("callback {} <- {}", id, text(bytes)))
+}
+
+/// Log the return value of a callback.
+pub fn callback_return(crossing: usize, id: u32, bytes: Option<&[u8]>) {
+    log(Some(crossing), || {
+        format!("callback {} -> {}", id, returned(bytes))
+    });
+}
+
+/// Log a line tagged with `crossing`, or with a new correlation id which is
+/// returned. Nothing is logged, and zero is returned, while already logging.
+fn log<F>(crossing: Option<usize>, f: F) -> usize
+where
+    F: FnOnce() -> String,
+{
+    if TRACING.with(|t| t.replace(true)) {
+        return 0;
+    }
+
+    let crossing = crossing
+        .unwrap_or_else(|| NEXT_ID.fetch_add(1, Ordering::SeqCst));
+
+    let message = format!("stasis: #{} {}", crossing, f());
+    let module_id = *MODULE.lock().unwrap().get_or_insert_with(|| {
+        let id = outgoing::create_module();
+
+        outgoing::register_fn(id, FUNCTION, r#"
+            function(message) {
+                console.debug(message);
+            }
+        "#);
+
+        id
+    });
+
+    let () = outgoing::call(module_id, FUNCTION, message);
+
+    TRACING.with(|t| t.set(false));
+
+    crossing
+}
+
+/// The payload as text, which is exact for JSON.
+fn text<'a>(bytes: &'a [u8]) -> Cow<'a, str> {
+    String::from_utf8_lossy(bytes)
+}
+
+fn returned<'a>(bytes: Option<&'a [u8]>) -> Cow<'a, str> {
+    match bytes {
+        Some(bytes) => text(bytes),
+        None => Cow::Borrowed("undefined"),
+    }
+}
+
+/// The bytes behind a return pointer, which is left untouched.
+unsafe fn peek<'a>(ret: *mut u8) -> Option<&'a [u8]> {
+    if ret.is_null() {
+        return None;
+    }
+
+    // A header of an unknown format is reported once it is actually read.
+    let header = slice::from_raw_parts(ret, Header::LEN);
+    let Header { ptr, len } = Header::decode(header).ok()?;
+
+    if len == 0 {
+        Some(&[])
+    } else {
+        Some(slice::from_raw_parts(ptr as *const u8, len))
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod test {
+    use std::sync::Mutex;
+
+    use serde_json::Value;
+
+    use mock;
+    use outgoing;
+    use super::FUNCTION;
+
+    lazy_static! {
+        /// Every logged line, shared as mock implementations are global.
+        static ref LOGGED: Mutex<Vec<String>> = Default::default();
+    }
+
+    fn record() {
+        mock::implement(FUNCTION, |args| {
+            let line = args[0].as_str().unwrap().to_owned();
+
+            LOGGED.lock().unwrap().push(line);
+            Value::Null
+        });
+    }
+
+    /// The lines mentioning `needle`, split into correlation id and text.
+    fn logged(needle: &str) -> Vec<(usize, String)> {
+        LOGGED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.contains(needle))
+            .map(|line| {
+                let line = line.trim_start_matches("stasis: #");
+                let space = line.find(' ').unwrap();
+
+                (line[..space].parse().unwrap(), line[space + 1..].to_owned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn logs_calls() {
+        record();
+        mock::implement("trace.echo", |args| args[0].clone());
+
+        let id = outgoing::create_module();
+        let n: u32 = outgoing::call(id, "trace.echo", 7);
+
+        assert_eq!(n, 7);
+
+        let ours = logged("trace.echo");
+        let crossing = ours[0].0;
+
+        let sent = format!(
+            concat!(
+                "call {0}.trace.echo <- ",
+                r#"{{"id":{0},"name":"trace.echo","args":7}}"#,
+            ),
+            id,
+        );
+        let returned = format!("call {}.trace.echo -> 7", id);
+
+        assert_eq!(ours, vec![(crossing, sent), (crossing, returned)]);
+    }
+
+    #[test]
+    fn correlation_ids() {
+        record();
+
+        let id = outgoing::create_module();
+        outgoing::register_callback(id, "trace.double", |n: u32| n * 2);
+
+        mock::implement("trace.nested", move |args| {
+            mock::callback(id, "trace.double", args[0].clone()).unwrap()
+        });
+
+        let first: u32 = outgoing::call(id, "trace.nested", 1001);
+        let second: u32 = outgoing::call(id, "trace.nested", 1002);
+
+        assert_eq!((first, second), (2002, 2004));
+
+        let calls = logged("trace.nested");
+        assert_eq!(calls.len(), 4);
+
+        // A crossing and its return value share an id.
+        let (first, second) = (calls[0].0, calls[2].0);
+        assert_eq!((calls[1].0, calls[3].0), (first, second));
+
+        // Callbacks are logged with their argument, which is unique here.
+        let callback = |arg: &str, ret: &str| {
+            let sent = logged(&format!("<- {}", arg));
+            let returned = logged(&format!("-> {}", ret));
+
+            assert_eq!(sent.len(), 1);
+            assert!(returned.iter().any(|r| r.0 == sent[0].0));
+
+            sent[0].0
+        };
+
+        let first_callback = callback("1001", "2002");
+        let second_callback = callback("1002", "2004");
+
+        // The ids are unique and follow the order the crossings started in,
+        // across both calls and callbacks.
+        assert!(first < first_callback);
+        assert!(first_callback < second);
+        assert!(second < second_callback);
+    }
+}
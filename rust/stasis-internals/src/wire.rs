@@ -0,0 +1,171 @@
+//! Wire formats.
+//!
+//! Values crossing the boundary are encoded with a `WireFormat`. `Json` is the
+//! default, and the only format understood by the bundled runtime. Any other
+//! format needs a runtime which decodes it.
+//!
+//! `JsonWith` is JSON written by a custom `serde_json` formatter, which the
+//! bundled runtime understands as long as the output is valid JSON. With the
+//! `messagepack` feature, `MessagePack` is also available.
+
+use std::fmt::Display;
+use std::marker::PhantomData;
+
+use serde_json;
+use serde_json::ser::Formatter;
+use serde::{Serialize, Deserialize};
+
+use data;
+
+/// An encoding for values sent across the boundary.
+pub trait WireFormat {
+    type Error: Display;
+
+    /// The encoding of `()`.
+    ///
+    /// A unit value is never sent, a null pointer is used in its place.
+    const UNIT: &'static [u8];
+
+    fn serialize<T>(t: &T) -> Result<Vec<u8>, Self::Error>
+    where
+        T: ?Sized + Serialize;
+
+    fn deserialize<T>(bytes: &[u8]) -> Result<T, Self::Error>
+    where
+        T: for<'a> Deserialize<'a>;
+}
+
+/// The default JSON wire format.
+///
+/// Serialization fails past a nesting depth of `DEFAULT_DEPTH_LIMIT`.
+pub struct Json;
+
+impl WireFormat for Json {
+    type Error = serde_json::Error;
+
+    const UNIT: &'static [u8] = b"null";
+
+    fn serialize<T>(t: &T) -> Result<Vec<u8>, Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        data::to_json(t, data::DEFAULT_DEPTH_LIMIT)
+    }
+
+    fn deserialize<T>(bytes: &[u8]) -> Result<T, Self::Error>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        serde_json::from_slice(bytes)
+    }
+}
+
+/// JSON written by a custom formatter.
+///
+/// This is `Json` with every token written by `F`, for example to print floats
+/// with fewer digits and shrink data-heavy calls. Values are read back as
+/// regular JSON. Serialization fails past a nesting depth of
+/// `DEFAULT_DEPTH_LIMIT`, like `Json`.
+pub struct JsonWith<F> {
+    _formatter: PhantomData<F>,
+}
+
+impl<F> WireFormat for JsonWith<F>
+where
+    F: Formatter + Default,
+{
+    type Error = serde_json::Error;
+
+    const UNIT: &'static [u8] = b"null";
+
+    fn serialize<T>(t: &T) -> Result<Vec<u8>, Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        data::to_json_with(t, F::default(), data::DEFAULT_DEPTH_LIMIT)
+    }
+
+    fn deserialize<T>(bytes: &[u8]) -> Result<T, Self::Error>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        serde_json::from_slice(bytes)
+    }
+}
+
+/// A MessagePack wire format.
+///
+/// This is more compact than JSON, and keeps floats exact. Structs are encoded
+/// as maps, so JavaScript can decode them without a schema, for example with
+/// `@msgpack/msgpack`.
+///
+/// This is only available with the `messagepack` feature.
+#[cfg(feature = "messagepack")]
+pub struct MessagePack;
+
+#[cfg(feature = "messagepack")]
+impl WireFormat for MessagePack {
+    type Error = MessagePackError;
+
+    /// MessagePack `nil`.
+    const UNIT: &'static [u8] = &[0xc0];
+
+    fn serialize<T>(t: &T) -> Result<Vec<u8>, Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        rmp_serde::to_vec_named(t).map_err(MessagePackError::Encode)
+    }
+
+    fn deserialize<T>(bytes: &[u8]) -> Result<T, Self::Error>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        rmp_serde::from_slice(bytes).map_err(MessagePackError::Decode)
+    }
+}
+
+/// An error from the `MessagePack` wire format.
+#[cfg(feature = "messagepack")]
+#[derive(Debug)]
+pub enum MessagePackError {
+    Encode(rmp_serde::encode::Error),
+    Decode(rmp_serde::decode::Error),
+}
+
+#[cfg(feature = "messagepack")]
+impl Display for MessagePackError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            MessagePackError::Encode(ref e) => write!(f, "{}", e),
+            MessagePackError::Decode(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "messagepack"))]
+mod test {
+    use super::{MessagePack, WireFormat};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Sample {
+        ratio: f64,
+        scale: f32,
+        bytes: Vec<u8>,
+    }
+
+    #[test]
+    fn messagepack_round_trip() {
+        let sample = Sample {
+            ratio: 0.1 + 0.2,
+            scale: -1.5,
+            bytes: vec![0, 1, 255],
+        };
+
+        let encoded = MessagePack::serialize(&sample).unwrap();
+        let decoded: Sample = MessagePack::deserialize(&encoded).unwrap();
+
+        assert_eq!(decoded, sample);
+        assert_eq!(MessagePack::serialize(&()).unwrap(), MessagePack::UNIT);
+    }
+}
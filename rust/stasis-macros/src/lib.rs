@@ -0,0 +1,304 @@
+//! Procedural macros for stasis.
+//!
+//! These are re-exported by `stasis` with the `bindings` feature, and should
+//! be used from there.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate syn;
+#[macro_use] extern crate quote;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::{
+    Attribute, Block, Error, Expr, FnArg, ImplItem, Item, ItemImpl, ItemTrait,
+    Lit, LitStr, Pat, ReturnType, Signature, Stmt, TraitItem, Visibility,
+};
+
+/// Generate a global module from a trait or impl block of JavaScript
+/// functions.
+///
+/// This writes the pattern from tutorial 3 for you. Every function body is a
+/// string of JavaScript, which is registered under the function's name the
+/// first time any of them is called. Arguments are serialized and the return
+/// value deserialized, just like `Module::call`.
+///
+/// On a trait, a unit struct of the same name is generated, with each
+/// function as an associated function:
+///
+/// ```rust,no_run
+/// extern crate stasis;
+///
+/// #[stasis::bindings]
+/// pub trait Random {
+///     fn random() -> f32 {
+///         "function() { return Math.random(); }"
+///     }
+///
+///     fn between(low: f32, high: f32) -> f32 {
+///         "function(low, high) { return low + Math.random() * (high - low); }"
+///     }
+/// }
+///
+/// fn main() {
+///     let n = Random::between(1.0, 6.0);
+///     stasis::console::log(n + Random::random());
+/// }
+/// ```
+///
+/// On an impl block, the functions are added to an existing type instead, and
+/// keep their own visibility:
+///
+/// ```rust,no_run
+/// extern crate stasis;
+///
+/// pub struct Page;
+///
+/// #[stasis::bindings]
+/// impl Page {
+///     pub fn set_title(title: &str) {
+///         "function(title) { document.title = title; }"
+///     }
+/// }
+///
+/// fn main() {
+///     Page::set_title("Hello");
+/// }
+/// ```
+///
+/// Functions cannot take `self`, be generic, or destructure their arguments.
+///
+/// This requires the `bindings` feature of `stasis`.
+#[proc_macro_attribute]
+pub fn bindings(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let result = if !attr.is_empty() {
+        Err(Error::new(
+            TokenStream2::from(attr).into_iter().next().unwrap().span(),
+            "#[bindings] takes no arguments",
+        ))
+    } else {
+        match syn::parse::<Item>(item) {
+            Ok(Item::Trait(t)) => expand_trait(t),
+            Ok(Item::Impl(i)) => expand_impl(i),
+            Ok(other) => Err(Error::new_spanned(
+                other,
+                "#[bindings] expects a trait or an impl block",
+            )),
+            Err(e) => Err(e),
+        }
+    };
+
+    result.unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// A single JavaScript function.
+struct Binding {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    sig: Signature,
+    code: LitStr,
+}
+
+impl Binding {
+    fn new(
+        attrs: Vec<Attribute>,
+        vis: Visibility,
+        sig: Signature,
+        block: Option<&Block>,
+    ) -> Result<Self, Error> {
+        if let Some(receiver) = sig.receiver() {
+            return Err(Error::new_spanned(
+                receiver,
+                "#[bindings] functions cannot take self",
+            ));
+        }
+
+        if !sig.generics.params.is_empty() {
+            return Err(Error::new_spanned(
+                &sig.generics,
+                "#[bindings] functions cannot be generic",
+            ));
+        }
+
+        if let Some(ref asyncness) = sig.asyncness {
+            return Err(Error::new_spanned(
+                asyncness,
+                "#[bindings] functions cannot be async",
+            ));
+        }
+
+        for arg in &sig.inputs {
+            if let FnArg::Typed(ref arg) = *arg {
+                if let Pat::Ident(_) = *arg.pat {
+                    continue;
+                }
+
+                return Err(Error::new_spanned(
+                    &arg.pat,
+                    "#[bindings] arguments must be plain names",
+                ));
+            }
+        }
+
+        let code = match block.map(|b| &b.stmts[..]) {
+            Some(&[Stmt::Expr(Expr::Lit(ref expr), None)]) => match expr.lit {
+                Lit::Str(ref code) => Some(code.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let code = match code {
+            Some(code) => code,
+            None => return Err(Error::new_spanned(
+                &sig,
+                "#[bindings] functions must have a JavaScript string as \
+                 their body",
+            )),
+        };
+
+        Ok(Binding { attrs, vis, sig, code })
+    }
+
+    /// The wrapper which calls the function on `MODULE`.
+    fn wrapper(&self) -> TokenStream2 {
+        let Binding { ref attrs, ref vis, ref sig, .. } = *self;
+        let name = sig.ident.to_string();
+
+        let args = sig.inputs.iter().filter_map(|arg| match *arg {
+            FnArg::Typed(ref arg) => match *arg.pat {
+                Pat::Ident(ref pat) => Some(&pat.ident),
+                _ => None,
+            },
+            FnArg::Receiver(_) => None,
+        });
+
+        // Arguments are always sent as a tuple, so a single argument which
+        // is itself an array is not spread.
+        let call = quote! {
+            MODULE.lock().0.call(#name, (#(#args,)*))
+        };
+
+        let body = match sig.output {
+            ReturnType::Default => quote! { let () = #call; },
+            ReturnType::Type(..) => call,
+        };
+
+        quote! {
+            #(#attrs)*
+            #vis #sig {
+                #body
+            }
+        }
+    }
+}
+
+fn expand_trait(t: ItemTrait) -> Result<TokenStream2, Error> {
+    if !t.generics.params.is_empty() || !t.supertraits.is_empty() {
+        return Err(Error::new_spanned(
+            &t.ident,
+            "#[bindings] traits cannot be generic or have supertraits",
+        ));
+    }
+
+    let mut bindings = Vec::new();
+
+    for item in t.items {
+        match item {
+            TraitItem::Fn(f) => bindings.push(Binding::new(
+                f.attrs,
+                t.vis.clone(),
+                f.sig,
+                f.default.as_ref(),
+            )?),
+            other => return Err(Error::new_spanned(
+                other,
+                "#[bindings] traits may only contain functions",
+            )),
+        }
+    }
+
+    let ItemTrait { attrs, vis, ident, .. } = t;
+    let module = module(&quote!(#ident), &bindings);
+
+    Ok(quote! {
+        #(#attrs)*
+        #[derive(Clone, Copy, Debug)]
+        #vis struct #ident;
+
+        #module
+    })
+}
+
+fn expand_impl(i: ItemImpl) -> Result<TokenStream2, Error> {
+    if let Some((_, ref path, _)) = i.trait_ {
+        return Err(Error::new_spanned(
+            path,
+            "#[bindings] expects an inherent impl, not a trait impl",
+        ));
+    }
+
+    if !i.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &i.generics,
+            "#[bindings] impls cannot be generic",
+        ));
+    }
+
+    let mut bindings = Vec::new();
+
+    for item in i.items {
+        match item {
+            ImplItem::Fn(f) => bindings.push(Binding::new(
+                f.attrs,
+                f.vis,
+                f.sig,
+                Some(&f.block),
+            )?),
+            other => return Err(Error::new_spanned(
+                other,
+                "#[bindings] impls may only contain functions",
+            )),
+        }
+    }
+
+    let ty = &i.self_ty;
+
+    Ok(module(&quote!(#ty), &bindings))
+}
+
+/// The module, its global and the wrappers on `ty`.
+///
+/// Everything but the wrappers is hidden in an anonymous constant, so several
+/// bindings can share a scope.
+fn module(ty: &TokenStream2, bindings: &[Binding]) -> TokenStream2 {
+    let registers = bindings.iter().map(|b| {
+        let name = b.sig.ident.to_string();
+        let code = &b.code;
+
+        quote! { m.register(#name, #code); }
+    });
+
+    let wrappers = bindings.iter().map(Binding::wrapper);
+
+    quote! {
+        const _: () = {
+            struct Bindings(::stasis::Module);
+
+            impl ::std::default::Default for Bindings {
+                fn default() -> Self {
+                    let m = ::stasis::Module::new();
+                    #(#registers)*
+                    Bindings(m)
+                }
+            }
+
+            static MODULE: ::stasis::global::Global<Bindings> =
+                ::stasis::global::Global::INIT;
+
+            impl #ty {
+                #(#wrappers)*
+            }
+        };
+    }
+}
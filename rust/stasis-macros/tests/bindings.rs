@@ -0,0 +1,61 @@
+extern crate stasis;
+extern crate serde_json;
+
+use std::sync::Mutex;
+
+use serde_json::{Value, json};
+use stasis::mock;
+
+#[stasis::bindings]
+trait Counter {
+    fn add(a: i32, b: i32) -> i32 {
+        "function(a, b) { return a + b; }"
+    }
+
+    fn lengths(items: Vec<String>) -> usize {
+        "function(items) { return items.length; }"
+    }
+
+    fn reset() {
+        "function() {}"
+    }
+}
+
+struct Page;
+
+#[stasis::bindings]
+impl Page {
+    fn set_title(title: &str) {
+        "function(title) { document.title = title; }"
+    }
+}
+
+static TITLES: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+
+#[test]
+fn trait_bindings() {
+    mock::implement("add", |args| {
+        json!(args[0].as_i64().unwrap() + args[1].as_i64().unwrap())
+    });
+    mock::implement("lengths", |args| {
+        // A single argument is not spread, even when it is an array.
+        json!(args[0].as_array().unwrap().len())
+    });
+    mock::implement("reset", |_| Value::Null);
+
+    assert_eq!(Counter::add(2, 3), 5);
+    assert_eq!(Counter::lengths(vec!["a".into(), "b".into()]), 2);
+    Counter::reset();
+}
+
+#[test]
+fn impl_bindings() {
+    mock::implement("set_title", |args| {
+        TITLES.lock().unwrap().push(args);
+        Value::Null
+    });
+
+    Page::set_title("Hello");
+
+    assert_eq!(*TITLES.lock().unwrap(), vec![json!(["Hello"])]);
+}
@@ -7,6 +7,10 @@
 //! `Callbacks` acts as a stack upon which you can push and pop items. Popping
 //! an item requires you to pass an updated callback.
 //!
+//! Any number of persistent listeners can also be attached to a single
+//! `CallbackId` with `add_listener`. Every listener is notified of each push,
+//! and they all share the same stack.
+//!
 //! ```rust,no_run
 //! #[macro_use] extern crate stasis;
 //!
@@ -51,6 +55,7 @@ use std::{
 use serde::Deserialize;
 use global::Global;
 use once_nonstatic::Once;
+use stasis_internals::internal_callbacks;
 
 /// A reference to a registered callback.
 ///
@@ -59,21 +64,75 @@ use once_nonstatic::Once;
 #[derive(Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct CallbackId(u32);
 
+/// A reference to a listener attached with [`Callbacks::add_listener`].
+///
+/// [`Callbacks::add_listener`]: ::callbacks::Callbacks::add_listener
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub struct ListenerId(u32);
+
+/// The id of a handler attached with [`Module::attach_handler`].
+///
+/// Unlike a `CallbackId`, this refers directly to a Rust closure. JavaScript
+/// turns it into a function with `this.handler(id)`.
+///
+/// [`Module::attach_handler`]: ::Module::attach_handler
+#[derive(Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub struct HandlerId(u32);
+
+/// An attached handler.
+///
+/// The handler is detached when this is dropped. JavaScript must not call the
+/// handler after this point.
+pub struct HandlerHandle {
+    id: HandlerId,
+}
+
+impl HandlerHandle {
+    #[doc(hidden)]
+    pub fn new(id: u32) -> Self {
+        Self { id: HandlerId(id) }
+    }
+
+    /// The id to pass to JavaScript.
+    pub fn id(&self) -> HandlerId {
+        self.id
+    }
+}
+
+impl Drop for HandlerHandle {
+    fn drop(&mut self) {
+        internal_callbacks::detach((self.id).0);
+    }
+}
+
 struct Inner<T> {
     current: u32,
+    listener: u32,
     map: HashMap<CallbackId, Callback<T>>,
 }
 
+type Notify = Box<dyn FnMut() + Send>;
+
+struct Listener {
+    id: ListenerId,
+    // This is taken while the listener is running.
+    f: Option<Notify>,
+}
+
 struct Callback<T> {
-    notify: Option<Box<FnMut() + Send>>,
+    notify: Option<Notify>,
+    listeners: Vec<Listener>,
     stack: VecDeque<T>,
+    capacity: Option<usize>,
 }
 
 impl<T> Default for Callback<T> {
     fn default() -> Self {
         Self {
             notify: None,
+            listeners: Vec::new(),
             stack: VecDeque::new(),
+            capacity: None,
         }
     }
 }
@@ -103,12 +162,37 @@ impl<T> Inner<T> {
             f();
         }));
     }
+
+    fn add_listener(&mut self, id: CallbackId, f: Notify) -> ListenerId {
+        self.listener += 1;
+        let listener = ListenerId(self.listener);
+
+        self.map
+            .entry(id)
+            .or_insert_with(Callback::default)
+            .listeners
+            .push(Listener { id: listener, f: Some(f) });
+
+        listener
+    }
+
+    /// Put back a listener taken by `push`, unless it was removed meanwhile.
+    fn restore(&mut self, id: CallbackId, listener: ListenerId, f: Notify) {
+        let slot = self.map
+            .get_mut(&id)
+            .and_then(|cb| cb.listeners.iter_mut().find(|l| l.id == listener));
+
+        if let Some(slot) = slot {
+            slot.f = Some(f);
+        }
+    }
 }
 
 impl<T> Default for Inner<T> {
     fn default() -> Self {
         Self {
             current: 0,
+            listener: 0,
             map: HashMap::new(),
         }
     }
@@ -138,6 +222,16 @@ impl LazyId {
             self.id
         }
     }
+
+    /// Mark the ID as uninitialized, so the next `get_or_init` runs its
+    /// initializer again.
+    ///
+    /// The same `CallbackId` is kept and stays valid. Any values and listeners
+    /// attached to it are untouched, so use `Callbacks::remove` first for a
+    /// clean slate.
+    pub fn reset(&mut self) {
+        self.initialized = false;
+    }
 }
 
 /// A callback manager.
@@ -153,17 +247,6 @@ pub struct Callbacks<T> {
     inner: UnsafeCell<Option<Global<Inner<T>>>>,
 }
 
-impl<T> Drop for Callbacks<T> {
-    fn drop(&mut self) {
-        use std::mem;
-
-        // Currently, destructors are not supported.
-        // TODO: Destructor support.
-        let cell = mem::replace(&mut self.inner, UnsafeCell::new(None));
-        mem::forget(cell);
-    }
-}
-
 // The bound here is taken directly from the `unsafe impl` of `Sync` on
 // `Global<T>`. With this in mind, the impl is safe as `Once` guards access to
 // the inner cell.
@@ -220,25 +303,87 @@ impl<T: 'static + Send> Callbacks<T> {
         }
     }
 
-    /// Push a value onto the stack and notify the listener.
+    /// Push a value onto the stack and notify the listeners.
+    ///
+    /// The listener set with `listen` is notified first, followed by those
+    /// attached with `add_listener` in the order they were added. If the stack
+    /// is at capacity, the oldest value is dropped to make room.
     pub fn push(&self, id: CallbackId, t: T) {
-        let notify = self.with(|inner| {
+        let mut evicted = None;
+
+        let taken = self.with(|inner| {
             let cb = inner.map.get_mut(&id)?;
 
+            match cb.capacity {
+                Some(n) if cb.stack.len() >= n => evicted = cb.stack.pop_front(),
+                _ => {}
+            }
+
             cb.stack.push_back(t);
-            cb.notify.take()
+
+            let listeners = cb.listeners
+                .iter_mut()
+                .filter_map(|l| Some((l.id, l.f.take()?)))
+                .collect::<Vec<_>>();
+
+            Some((cb.notify.take(), listeners))
         });
 
+        // See `clear`.
+        drop(evicted);
+
+        let (notify, listeners) = match taken {
+            Some(taken) => taken,
+            None => return,
+        };
+
         if let Some(mut f) = notify {
             f();
         }
+
+        // Listeners run without the lock held, so they are free to `pop`, add
+        // listeners or remove themselves.
+        for (listener, mut f) in listeners {
+            f();
+            self.with(|inner| inner.restore(id, listener, f));
+        }
+    }
+
+    /// Attach a persistent listener for push events.
+    ///
+    /// Unlike `listen`, this does not replace any other listener, and the
+    /// listener stays attached until removed with `remove_listener`. All
+    /// listeners share a single stack, so a value popped by one listener is not
+    /// seen by the next.
+    ///
+    /// A listener is not notified of pushes made while it is running.
+    pub fn add_listener<F>(&self, id: CallbackId, f: F) -> ListenerId
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.with(|inner| inner.add_listener(id, Box::new(f)))
+    }
+
+    /// Detach a listener attached with `add_listener` or `on`.
+    pub fn remove_listener(&self, id: CallbackId, listener: ListenerId) {
+        let removed = self.with(|inner| {
+            let cb = inner.map.get_mut(&id)?;
+            let i = cb.listeners.iter().position(|l| l.id == listener)?;
+
+            Some(cb.listeners.remove(i))
+        });
+
+        // See `clear`.
+        drop(removed);
     }
 
-    // TODO: Allow unregistering these callbacks.
     /// Register a callback handler.
     ///
-    /// Any incoming `push` will immediately trigger the given handler.
-    pub fn on<F>(&self, id: CallbackId, f: F)
+    /// Any incoming `push` will immediately trigger the given handler with the
+    /// pushed value. This is a listener like any other, so it can be detached
+    /// with `remove_listener`. If an earlier listener has already popped the
+    /// value, the handler is not called.
+    pub fn on<F>(&self, id: CallbackId, mut f: F) -> ListenerId
     where
         F: FnMut(T) + Send + 'static,
         T: for<'a> Deserialize<'a>,
@@ -252,34 +397,70 @@ impl<T: 'static + Send> Callbacks<T> {
         // Used to transmute between pointers and thread-safe values.
         type Ptr<T> = *const Option<Global<Inner<T>>>;
 
-        // This listener is self-referential as it must re-register itself when
-        // done.
-        unsafe fn listener<T, F>(id: CallbackId, mut f: F, addr: usize)
-        where
-            T: Send + 'static,
-            F: Send + 'static + FnMut(T),
-        {
-            let ptr: Ptr<T> = mem::transmute(addr);
-            let opt = (*ptr).as_ref().unwrap();
+        self.ensure_exists();
+        let addr: usize = unsafe { mem::transmute(self.inner.get()) };
 
-            let mut guard = opt.lock();
-            let t = guard.pop(id).unwrap();
+        self.add_listener(id, move || {
+            let t = unsafe {
+                let ptr: Ptr<T> = mem::transmute(addr);
 
-            // The callback is run before the listener is re-registered.
-            drop(guard);
-            f(t);
+                (*ptr).as_ref().unwrap().with(|inner| inner.pop(id))
+            };
 
-            let mut guard = opt.lock();
-            guard.listen(id, move || listener::<T, _>(id, f, addr));
-        }
+            // The lock is released before the handler runs.
+            if let Some(t) = t {
+                f(t);
+            }
+        })
+    }
 
-        unsafe {
-            self.ensure_exists();
-            let ptr = self.inner.get();
-            let addr = mem::transmute(ptr);
+    /// Register a callback handler which is also told which id fired.
+    ///
+    /// This is `on` with the `CallbackId` passed along with each value, so a
+    /// single handler can be attached to many ids, such as one handler for
+    /// every timer.
+    pub fn on_with_id<F>(&self, id: CallbackId, mut f: F) -> ListenerId
+    where
+        F: FnMut(CallbackId, T) + Send + 'static,
+        T: for<'a> Deserialize<'a>,
+    {
+        self.on(id, move |t| f(id, t))
+    }
 
-            self.listen(id, move || listener::<T, _>(id, f, addr));
-        }
+    /// Remove every pending value and listener.
+    ///
+    /// Statics are never dropped, so long-running applications can use this to
+    /// reclaim memory. Issued IDs are not reused.
+    pub fn clear(&self) {
+        let map = self.with(|inner| mem::take(&mut inner.map));
+
+        // Values and listeners are dropped outside of the lock as they are free
+        // to access this manager in their destructors.
+        drop(map);
+    }
+
+    /// Remove every pending value and listener, and start issuing IDs from the
+    /// beginning again.
+    ///
+    /// This is meant for a full teardown. Any `CallbackId` or `LazyId` still
+    /// held from before refers to whichever callback is given the same ID
+    /// next, so use `clear` if any may outlive the reset.
+    pub fn clear_and_reset_ids(&self) {
+        let map = self.with(|inner| {
+            inner.current = 0;
+            mem::take(&mut inner.map)
+        });
+
+        // See `clear`.
+        drop(map);
+    }
+
+    /// Remove a single callback along with its pending values and listeners.
+    pub fn remove(&self, id: CallbackId) {
+        let cb = self.with(|inner| inner.map.remove(&id));
+
+        // See `clear`.
+        drop(cb);
     }
 
     /// Pop the next value off the stack.
@@ -287,9 +468,43 @@ impl<T: 'static + Send> Callbacks<T> {
         self.with(|inner| inner.pop(id))
     }
 
+    /// Limit the number of values held on the stack.
+    ///
+    /// Once the stack is full, each `push` drops the oldest value. This keeps
+    /// memory bounded for high frequency sources such as `mousemove`, where
+    /// only recent values matter. Values already over the limit are dropped
+    /// immediately. The limit stays in place until the callback is removed.
+    pub fn set_capacity(&self, id: CallbackId, n: usize) {
+        let evicted = self.with(|inner| {
+            let cb = inner.map
+                .entry(id)
+                .or_insert_with(Callback::default);
+
+            let excess = cb.stack.len().saturating_sub(n);
+            cb.capacity = Some(n);
+
+            cb.stack.drain(..excess).collect::<Vec<_>>()
+        });
+
+        // See `clear`.
+        drop(evicted);
+    }
+
+    /// Pop every value off the stack at once, in the order they were pushed.
+    ///
+    /// This takes the lock only once, unlike calling `pop` in a loop.
+    pub fn pop_all(&self, id: CallbackId) -> Vec<T> {
+        self.with(|inner| match inner.map.get_mut(&id) {
+            Some(cb) => cb.stack.drain(..).collect(),
+            None => Vec::new(),
+        })
+    }
+
     /// Listen for push events.
     ///
-    /// This will override the previous listener.
+    /// The listener is run on the next push only, and overrides the previous
+    /// listener set with this method. Listeners attached with `add_listener`
+    /// are unaffected.
     pub fn listen<F>(&self, id: CallbackId, f: F)
     where
         F: FnOnce() + Send + 'static,
@@ -299,14 +514,289 @@ impl<T: 'static + Send> Callbacks<T> {
 
     /// Pop the next item off the stack and attach a listener for a future item.
     ///
-    /// This is essentially a combination of `pop` and `listen`.
+    /// This is essentially a combination of `pop` and `listen`, done under a
+    /// single lock so that no `push` can land in between. The returned item is
+    /// always the oldest one, and every item pushed afterwards comes strictly
+    /// after it. The listener only runs on the next `push`, even when older
+    /// items are still on the stack, so callers should keep popping until this
+    /// returns `None`.
     pub fn pop_listen<F>(&self, id: CallbackId, f: F) -> Option<T>
     where
         F: FnOnce() + Send + 'static,
     {
         self.with(|inner| {
+            let t = inner.pop(id);
             inner.listen(id, f);
-            inner.pop(id)
+            t
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::Callbacks;
+
+    /// Counts how many times it has been dropped.
+    struct Counted(&'static AtomicUsize);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn drop_frees_values() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        {
+            let callbacks = Callbacks::INIT;
+            let id = callbacks.create();
+
+            callbacks.listen(id, || ());
+            callbacks.push(id, Counted(&DROPPED));
+            callbacks.push(id, Counted(&DROPPED));
+
+            assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+        }
+
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn clear_frees_values() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        static CALLBACKS: Callbacks<Counted> = Callbacks::INIT;
+
+        let id = CALLBACKS.create();
+
+        CALLBACKS.listen(id, || ());
+        CALLBACKS.push(id, Counted(&DROPPED));
+        CALLBACKS.clear();
+
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+        assert!(CALLBACKS.pop(id).is_none());
+
+        // IDs keep increasing after a clear.
+        assert!(CALLBACKS.create() != id);
+    }
+
+    #[test]
+    fn on_with_id() {
+        static CALLBACKS: Callbacks<u32> = Callbacks::INIT;
+
+        let fired = Arc::new(Mutex::new(Vec::new()));
+
+        let handler = {
+            let fired = fired.clone();
+            move |id, n| fired.lock().unwrap().push((id, n))
+        };
+
+        let a = CALLBACKS.create();
+        let b = CALLBACKS.create();
+
+        CALLBACKS.on_with_id(a, handler.clone());
+        CALLBACKS.on_with_id(b, handler);
+
+        CALLBACKS.push(b, 1);
+        CALLBACKS.push(a, 2);
+        CALLBACKS.push(b, 3);
+
+        assert!(*fired.lock().unwrap() == vec![(b, 1), (a, 2), (b, 3)]);
+    }
+
+    #[test]
+    fn clear_empties_every_id() {
+        static CALLBACKS: Callbacks<u32> = Callbacks::INIT;
+
+        let ids: Vec<_> = (0..3).map(|_| CALLBACKS.create()).collect();
+
+        for (n, &id) in ids.iter().enumerate() {
+            CALLBACKS.push(id, n as u32);
+            CALLBACKS.push(id, n as u32 + 10);
+        }
+
+        CALLBACKS.clear();
+
+        for &id in &ids {
+            assert_eq!(CALLBACKS.pop(id), None);
+        }
+
+        CALLBACKS.push(ids[0], 1);
+        CALLBACKS.clear_and_reset_ids();
+
+        assert_eq!(CALLBACKS.pop(ids[0]), None);
+        assert!(CALLBACKS.create() == ids[0]);
+    }
+
+    #[test]
+    fn remove_frees_values() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        static CALLBACKS: Callbacks<Counted> = Callbacks::INIT;
+
+        let a = CALLBACKS.create();
+        let b = CALLBACKS.create();
+
+        CALLBACKS.listen(a, || ());
+        CALLBACKS.listen(b, || ());
+        CALLBACKS.push(a, Counted(&DROPPED));
+        CALLBACKS.push(b, Counted(&DROPPED));
+        CALLBACKS.remove(a);
+
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+        assert!(CALLBACKS.pop(a).is_none());
+        assert!(CALLBACKS.pop(b).is_some());
+    }
+
+    #[test]
+    fn pop_all_drains_in_order() {
+        static CALLBACKS: Callbacks<u32> = Callbacks::INIT;
+
+        let id = CALLBACKS.create();
+
+        CALLBACKS.listen(id, || ());
+        CALLBACKS.push(id, 1);
+        CALLBACKS.push(id, 2);
+        CALLBACKS.push(id, 3);
+
+        assert_eq!(CALLBACKS.pop_all(id), vec![1, 2, 3]);
+        assert!(CALLBACKS.pop(id).is_none());
+        assert!(CALLBACKS.pop_all(CALLBACKS.create()).is_empty());
+    }
+
+    #[test]
+    fn capacity_drops_oldest() {
+        static CALLBACKS: Callbacks<u32> = Callbacks::INIT;
+
+        let id = CALLBACKS.create();
+
+        CALLBACKS.push(id, 0);
+        CALLBACKS.set_capacity(id, 3);
+
+        for n in 1..6 {
+            CALLBACKS.push(id, n);
+        }
+
+        assert_eq!(CALLBACKS.pop_all(id), vec![3, 4, 5]);
+
+        CALLBACKS.push(id, 6);
+        CALLBACKS.push(id, 7);
+        CALLBACKS.set_capacity(id, 1);
+
+        assert_eq!(CALLBACKS.pop_all(id), vec![7]);
+    }
+
+    #[test]
+    fn lazy_reset() {
+        static CALLBACKS: Callbacks<()> = Callbacks::INIT;
+
+        let mut lazy = CALLBACKS.lazy();
+        let mut runs = 0;
+
+        let id = lazy.get_or_init(|_| runs += 1);
+        lazy.get_or_init(|_| runs += 1);
+        assert_eq!(runs, 1);
+
+        lazy.reset();
+        assert!(lazy.get_or_init(|_| runs += 1) == id);
+        assert_eq!(runs, 2);
+    }
+
+    #[test]
+    fn pop_listen_order() {
+        static CALLBACKS: Callbacks<u32> = Callbacks::INIT;
+
+        let id = CALLBACKS.create();
+        let fired = Arc::new(AtomicUsize::new(0));
+
+        let listener = || {
+            let fired = fired.clone();
+            move || { fired.fetch_add(1, Ordering::SeqCst); }
+        };
+
+        // Nothing is kept before the first listener.
+        CALLBACKS.push(id, 0);
+        assert_eq!(CALLBACKS.pop_listen(id, listener()), None);
+
+        CALLBACKS.push(id, 1);
+        CALLBACKS.push(id, 2);
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+
+        // The oldest item comes first, and the listener waits for a new push
+        // even though an older item is still queued.
+        assert_eq!(CALLBACKS.pop_listen(id, listener()), Some(1));
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+
+        CALLBACKS.push(id, 3);
+        assert_eq!(fired.load(Ordering::SeqCst), 2);
+
+        // Items pushed after the listener was armed come after the rest.
+        assert_eq!(CALLBACKS.pop_listen(id, listener()), Some(2));
+        assert_eq!(CALLBACKS.pop_listen(id, listener()), Some(3));
+        assert_eq!(CALLBACKS.pop_listen(id, listener()), None);
+    }
+
+    #[test]
+    fn pop_listen_from_listener() {
+        static CALLBACKS: Callbacks<u32> = Callbacks::INIT;
+
+        let id = CALLBACKS.create();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        // Like a future polled from within `push`, pop one item and re-arm.
+        fn consume(id: super::CallbackId, seen: Arc<Mutex<Vec<u32>>>) {
+            let next = seen.clone();
+            let t = CALLBACKS.pop_listen(id, move || consume(id, next));
+
+            seen.lock().unwrap().extend(t);
+        }
+
+        CALLBACKS.listen(id, {
+            let seen = seen.clone();
+            move || consume(id, seen)
+        });
+
+        for n in 1..5 {
+            CALLBACKS.push(id, n);
+        }
+
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn listeners_share_stack() {
+        static CALLBACKS: Callbacks<u32> = Callbacks::INIT;
+
+        let id = CALLBACKS.create();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let first = {
+            let log = log.clone();
+
+            CALLBACKS.on(id, move |n| log.lock().unwrap().push(("on", n)))
+        };
+
+        {
+            let log = log.clone();
+
+            CALLBACKS.add_listener(id, move || {
+                let n = CALLBACKS.pop(id);
+                log.lock().unwrap().push(("second", n.unwrap_or(0)));
+            });
+        }
+
+        CALLBACKS.push(id, 1);
+
+        // The first listener popped the value, but both were notified in order.
+        assert_eq!(*log.lock().unwrap(), vec![("on", 1), ("second", 0)]);
+
+        CALLBACKS.remove_listener(id, first);
+        CALLBACKS.push(id, 2);
+
+        assert_eq!(log.lock().unwrap()[2], ("second", 2));
+        assert_eq!(log.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,261 @@
+//! The browser `navigator.clipboard` API.
+//!
+//! This module is only available with the `browser` feature, which is enabled
+//! by default. The clipboard is only available in secure contexts, and the
+//! browser may ask the user for permission first.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # extern crate futures_v02x;
+//! # use futures_v02x::FutureExt;
+//! # use stasis::{clipboard, console, futures::v02};
+//! # fn main() {
+//! v02::spawn(clipboard::read().then(|result| {
+//!     match result {
+//!         Ok(text) => console::log(text),
+//!         Err(e) => console::error(e.to_string()),
+//!     }
+//!
+//!     Ok(())
+//! }));
+//! # }
+//! ```
+
+use std::{
+    error::Error,
+    fmt,
+    future,
+    pin::Pin,
+    task,
+};
+
+use futures_v02x::{self, Async};
+use serde::Deserialize;
+
+use Module;
+use global::Global;
+use futures::{Promise, PromiseError};
+
+/// The clipboard module.
+struct Clipboard(Module);
+
+static CLIPBOARD: Global<Clipboard> = Global::INIT;
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        let m = Module::new();
+
+        // Failures are resolved as an `Outcome`, so that Rust can tell them
+        // apart without parsing error messages.
+        m.register("run", r#"
+            function(f) {
+                if (!window.isSecureContext || !navigator.clipboard) {
+                    return Promise.resolve("insecure");
+                }
+
+                return f().then(
+                    function(value) {
+                        return { ok: value === undefined ? null : value };
+                    },
+                    function(e) {
+                        var message = e instanceof Error
+                            ? e.message
+                            : String(e);
+
+                        return e && e.name === "NotAllowedError"
+                            ? { denied: message }
+                            : { failed: message };
+                    }
+                );
+            }
+        "#);
+
+        m.register("write", r#"
+            function(text) {
+                return this.functions.run(function() {
+                    return navigator.clipboard.writeText(text);
+                });
+            }
+        "#);
+
+        m.register("read", r#"
+            function() {
+                return this.functions.run(function() {
+                    return navigator.clipboard.readText();
+                });
+            }
+        "#);
+
+        Clipboard(m)
+    }
+}
+
+/// The settled clipboard promise as sent from JavaScript.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Outcome<T> {
+    Ok(T),
+    Denied(String),
+    Insecure,
+    Failed(String),
+}
+
+/// A failed clipboard operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardError {
+    /// The user or the browser denied access to the clipboard.
+    PermissionDenied(String),
+
+    /// The page is not a secure context, so there is no clipboard API.
+    InsecureContext,
+
+    /// Any other failure, with its message.
+    Failed(String),
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClipboardError::PermissionDenied(ref message) => {
+                write!(f, "Clipboard permission denied: {}", message)
+            }
+            ClipboardError::InsecureContext => {
+                write!(f, "The clipboard requires a secure context")
+            }
+            ClipboardError::Failed(ref message) => {
+                write!(f, "Clipboard error: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for ClipboardError {}
+
+fn convert<T>(
+    settled: Result<Outcome<T>, PromiseError>,
+) -> Result<T, ClipboardError> {
+    match settled {
+        Ok(Outcome::Ok(t)) => Ok(t),
+        Ok(Outcome::Denied(message)) => {
+            Err(ClipboardError::PermissionDenied(message))
+        }
+        Ok(Outcome::Insecure) => Err(ClipboardError::InsecureContext),
+        Ok(Outcome::Failed(message)) => Err(ClipboardError::Failed(message)),
+        Err(e) => Err(ClipboardError::Failed(e.message().to_owned())),
+    }
+}
+
+/// A pending clipboard operation.
+///
+/// This is created by the [`read`] and [`write`] functions and works with both
+/// the `v02` and the `v03` executors.
+///
+/// [`read`]: fn.read.html
+/// [`write`]: fn.write.html
+pub struct ClipboardFuture<T> {
+    promise: Promise<Outcome<T>>,
+}
+
+impl<T> futures_v02x::Future for ClipboardFuture<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    type Item = T;
+    type Error = ClipboardError;
+
+    fn poll(
+        &mut self,
+        cx: &mut futures_v02x::task::Context,
+    ) -> futures_v02x::Poll<T, ClipboardError> {
+        match self.promise.poll(cx) {
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Ok(Async::Ready(outcome)) => convert(Ok(outcome)).map(Async::Ready),
+            Err(e) => convert(Err(e)).map(Async::Ready),
+        }
+    }
+}
+
+impl<T> future::Future for ClipboardFuture<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    type Output = Result<T, ClipboardError>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context,
+    ) -> task::Poll<Self::Output> {
+        let promise = Pin::new(&mut self.promise);
+
+        match future::Future::poll(promise, cx) {
+            task::Poll::Ready(settled) => task::Poll::Ready(convert(settled)),
+            task::Poll::Pending => task::Poll::Pending,
+        }
+    }
+}
+
+/// Write text to the clipboard.
+pub fn write(text: &str) -> ClipboardFuture<()> {
+    let promise = CLIPBOARD.lock().0.call_promise("write", text);
+
+    ClipboardFuture { promise }
+}
+
+/// Read text from the clipboard.
+pub fn read() -> ClipboardFuture<String> {
+    let promise = CLIPBOARD.lock().0.call_promise("read", ());
+
+    ClipboardFuture { promise }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use futures_v02x::FutureExt;
+    use serde_json::json;
+
+    use futures::v02;
+    use futures::promise::testing;
+    use super::{CLIPBOARD, ClipboardError, read, write};
+
+    #[test]
+    fn clipboard() {
+        testing::install();
+
+        let results = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let results = results.clone();
+
+            v02::spawn(write("copied").then(move |result| {
+                results.lock().unwrap().push(result.map(|()| String::new()));
+                Ok(())
+            }));
+        }
+
+        for _ in 0..2 {
+            let results = results.clone();
+
+            v02::spawn(read().then(move |result| {
+                results.lock().unwrap().push(result);
+                Ok(())
+            }));
+        }
+
+        let writes = testing::started("write");
+        let reads = testing::started("read");
+        assert_eq!((writes.len(), reads.len()), (1, 2));
+
+        let m = CLIPBOARD.lock().0;
+        testing::settle(&m, &writes[0].0, Ok(json!({ "ok": null })));
+        testing::settle(&m, &reads[0].0, Ok(json!({ "ok": "pasted" })));
+        testing::settle(&m, &reads[1].0, Ok(json!({ "denied": "Blocked" })));
+
+        assert_eq!(*results.lock().unwrap(), vec![
+            Ok(String::new()),
+            Ok("pasted".to_owned()),
+            Err(ClipboardError::PermissionDenied("Blocked".to_owned())),
+        ]);
+    }
+}
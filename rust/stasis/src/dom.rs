@@ -0,0 +1,226 @@
+//! DOM event listeners.
+//!
+//! This module is only available with the `browser` feature, which is enabled
+//! by default.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # use stasis::{console, dom};
+//! # fn main() {
+//! let subscription = dom::on("#name", "keydown", |event| {
+//!     if let Some(key) = event.key() {
+//!         console::log(key);
+//!     }
+//! });
+//!
+//! // Keep listening for the rest of the program.
+//! std::mem::forget(subscription);
+//! # }
+//! ```
+
+use Module;
+use global::Global;
+use callbacks::{Callbacks, CallbackId};
+
+/// Every subscription shares this manager, with one ID per subscription.
+static CALLBACKS: Callbacks<Event> = Callbacks::INIT;
+
+/// The DOM module.
+struct Dom(Module);
+
+static DOM: Global<Dom> = Global::INIT;
+
+impl Default for Dom {
+    fn default() -> Self {
+        let m = Module::new();
+
+        m.register_callback2("event", |id: CallbackId, event: Event| {
+            CALLBACKS.push(id, event);
+        });
+
+        m.register("addEventListener", r#"
+            function(id, selector, type) {
+                var element = document.querySelector(selector);
+
+                if (element === null) {
+                    return false;
+                }
+
+                var event = this.callbacks.event;
+                var listener = function(e) {
+                    event(id, {
+                        type: e.type,
+                        target: e.target && e.target.id ? e.target.id : null,
+                        key: typeof e.key === 'string' ? e.key : null,
+                    });
+                };
+
+                element.addEventListener(type, listener);
+                this.data[id] = {
+                    element: element,
+                    type: type,
+                    listener: listener,
+                };
+
+                return true;
+            }
+        "#);
+
+        m.register("removeEventListener", r#"
+            function(id) {
+                var subscription = this.data[id];
+
+                if (subscription) {
+                    subscription.element.removeEventListener(
+                        subscription.type,
+                        subscription.listener
+                    );
+                    delete this.data[id];
+                }
+            }
+        "#);
+
+        Dom(m)
+    }
+}
+
+/// A DOM event.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    target: Option<String>,
+    key: Option<String>,
+}
+
+impl Event {
+    /// The event type, such as `"click"`.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// The `id` attribute of the element the event was dispatched to, if it
+    /// has one.
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    /// The key pressed, for keyboard events.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+}
+
+/// An attached event listener, created by [`on`].
+///
+/// The listener is removed from the element when this is dropped.
+///
+/// [`on`]: fn.on.html
+pub struct Subscription {
+    id: CallbackId,
+}
+
+impl Subscription {
+    /// Remove the listener.
+    ///
+    /// This is the same as dropping the subscription.
+    pub fn cancel(self) {}
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // The JavaScript listener goes first, so no event arrives after the
+        // Rust closure is gone.
+        let () = DOM.lock().0.call("removeEventListener", self.id);
+        CALLBACKS.remove(self.id);
+    }
+}
+
+/// Call `f` for every `event` on the first element matching `selector`.
+///
+/// This is `document.querySelector(selector).addEventListener(event, ...)`.
+/// Returns `None` if no element matches.
+pub fn on<F>(selector: &str, event: &str, f: F) -> Option<Subscription>
+where
+    F: FnMut(Event) + Send + 'static,
+{
+    let id = CALLBACKS.create();
+
+    CALLBACKS.on(id, f);
+
+    let found: bool = DOM
+        .lock()
+        .0
+        .call("addEventListener", (id, selector, event));
+
+    if found {
+        Some(Subscription { id })
+    } else {
+        CALLBACKS.remove(id);
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{self, Value, json};
+    use stasis_internals::mock;
+
+    use super::{CALLBACKS, Event, on};
+    use callbacks::CallbackId;
+
+    #[test]
+    fn subscription() {
+        let attached = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let attached = attached.clone();
+
+            mock::implement("addEventListener", move |args| {
+                if args[1] != json!("#missing") {
+                    attached.lock().unwrap().push(args[0].clone());
+                }
+
+                json!(args[1] != json!("#missing"))
+            });
+        }
+
+        {
+            let attached = attached.clone();
+
+            mock::implement("removeEventListener", move |args| {
+                attached.lock().unwrap().retain(|id| *id != args[0]);
+                Value::Null
+            });
+        }
+
+        assert!(on("#missing", "click", |_| ()).is_none());
+
+        let subscription = {
+            let events = events.clone();
+
+            on("#name", "keydown", move |e| events.lock().unwrap().push(e))
+                .unwrap()
+        };
+
+        let id = attached.lock().unwrap()[0].clone();
+        let id: CallbackId = serde_json::from_value(id).unwrap();
+        let event: Event = serde_json::from_value(json!({
+            "type": "keydown",
+            "target": "name",
+            "key": "a",
+        })).unwrap();
+
+        CALLBACKS.push(id, event.clone());
+        assert_eq!(*events.lock().unwrap(), vec![event.clone()]);
+
+        subscription.cancel();
+        assert!(attached.lock().unwrap().is_empty());
+
+        CALLBACKS.push(id, event);
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+}
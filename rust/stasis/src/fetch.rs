@@ -0,0 +1,215 @@
+//! The browser `fetch` API.
+//!
+//! This module is only available with the `browser` feature, which is enabled
+//! by default.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # extern crate futures_v02x;
+//! # use futures_v02x::FutureExt;
+//! # use stasis::{console, fetch, futures::v02};
+//! # fn main() {
+//! v02::spawn(fetch::fetch("/index.html").then(|result| {
+//!     match result {
+//!         Ok(ref response) if response.ok() => console::log(response.text()),
+//!         Ok(response) => console::warn(("Bad status", response.status())),
+//!         Err(e) => console::error(e.to_string()),
+//!     }
+//!
+//!     Ok(())
+//! }));
+//! # }
+//! ```
+
+use std::{
+    error::Error,
+    fmt,
+    future,
+    pin::Pin,
+    task,
+};
+
+use futures_v02x::{self, Async};
+use serde::Deserialize;
+use serde_json;
+
+use Module;
+use global::Global;
+use futures::{Promise, PromiseError};
+
+/// The fetch module.
+struct Fetch(Module);
+
+static FETCH: Global<Fetch> = Global::INIT;
+
+impl Default for Fetch {
+    fn default() -> Self {
+        let m = Module::new();
+
+        m.register("fetch", r#"
+            function(url) {
+                return fetch(url).then(function(r) {
+                    return r.text().then(function(body) {
+                        return { status: r.status, body: body };
+                    });
+                });
+            }
+        "#);
+
+        Fetch(m)
+    }
+}
+
+/// The response as sent from JavaScript.
+#[derive(Deserialize)]
+struct RawResponse {
+    status: u16,
+    body: String,
+}
+
+/// A completed request.
+///
+/// Like the browser API, any response counts as a success. Check `status` or
+/// `ok` to handle error statuses.
+#[derive(Debug, Clone)]
+pub struct FetchResponse {
+    status: u16,
+    body: String,
+}
+
+impl FetchResponse {
+    /// The HTTP status code.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Whether the status is in the range 200-299.
+    pub fn ok(&self) -> bool {
+        self.status >= 200 && self.status < 300
+    }
+
+    /// The body as text.
+    pub fn text(&self) -> &str {
+        &self.body
+    }
+
+    /// Parse the body as JSON.
+    pub fn json<T>(&self) -> Result<T, FetchError>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        serde_json::from_str(&self.body).map_err(FetchError::Json)
+    }
+}
+
+/// A failed request.
+#[derive(Debug)]
+pub enum FetchError {
+    /// The request itself failed, for example due to a network error.
+    Network(PromiseError),
+
+    /// The body was not valid JSON for the requested type.
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FetchError::Network(ref e) => write!(f, "Network error: {}", e),
+            FetchError::Json(ref e) => write!(f, "Invalid JSON: {}", e),
+        }
+    }
+}
+
+impl Error for FetchError {}
+
+/// A pending request.
+///
+/// This is created by the [`fetch`] function and works with both the `v02` and
+/// the `v03` executors.
+///
+/// [`fetch`]: fn.fetch.html
+pub struct FetchFuture {
+    promise: Promise<RawResponse>,
+}
+
+fn convert(raw: RawResponse) -> FetchResponse {
+    FetchResponse {
+        status: raw.status,
+        body: raw.body,
+    }
+}
+
+impl futures_v02x::Future for FetchFuture {
+    type Item = FetchResponse;
+    type Error = FetchError;
+
+    fn poll(
+        &mut self,
+        cx: &mut futures_v02x::task::Context,
+    ) -> futures_v02x::Poll<FetchResponse, FetchError> {
+        match self.promise.poll(cx) {
+            Ok(Async::Ready(raw)) => Ok(Async::Ready(convert(raw))),
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err(e) => Err(FetchError::Network(e)),
+        }
+    }
+}
+
+impl future::Future for FetchFuture {
+    type Output = Result<FetchResponse, FetchError>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context,
+    ) -> task::Poll<Self::Output> {
+        let promise = Pin::new(&mut self.promise);
+
+        match future::Future::poll(promise, cx) {
+            task::Poll::Ready(Ok(raw)) => task::Poll::Ready(Ok(convert(raw))),
+            task::Poll::Ready(Err(e)) => {
+                task::Poll::Ready(Err(FetchError::Network(e)))
+            }
+            task::Poll::Pending => task::Poll::Pending,
+        }
+    }
+}
+
+/// Fetch a resource.
+///
+/// The request is sent immediately and the whole body is read as text.
+pub fn fetch(url: &str) -> FetchFuture {
+    let promise = FETCH.lock().0.call_promise("fetch", url);
+
+    FetchFuture { promise }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FetchError, FetchResponse};
+
+    fn response(status: u16, body: &str) -> FetchResponse {
+        FetchResponse {
+            status,
+            body: body.to_owned(),
+        }
+    }
+
+    #[test]
+    fn status() {
+        assert!(response(200, "").ok());
+        assert!(response(204, "").ok());
+        assert!(!response(404, "").ok());
+        assert!(!response(500, "").ok());
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(response(200, "[1,2]").json::<Vec<u32>>().unwrap(), [1, 2]);
+
+        match response(200, "<html>").json::<Vec<u32>>() {
+            Err(FetchError::Json(_)) => (),
+            _ => panic!("Expected a JSON error"),
+        }
+    }
+}
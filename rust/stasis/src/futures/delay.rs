@@ -0,0 +1,265 @@
+//! A `setTimeout` based delay.
+
+use std::{
+    future,
+    pin::Pin,
+    task,
+};
+
+use futures_v02x::{self, Async, Never};
+
+use Module;
+use global::Global;
+use callbacks::{Callbacks, CallbackId, LazyId};
+
+/// Every delay shares this manager, which hands out a unique ID per delay.
+static CALLBACKS: Callbacks<()> = Callbacks::INIT;
+
+/// The timer module.
+struct Timer(Module);
+
+static TIMER: Global<Timer> = Global::INIT;
+
+impl Default for Timer {
+    fn default() -> Self {
+        let m = Module::new();
+
+        m.register_callback("done", |id: CallbackId| {
+            CALLBACKS.push(id, ());
+        });
+
+        m.register("setTimeout", r#"
+            function(id, ms) {
+                var done = this.callbacks.done;
+                setTimeout(function() { done(id) }, ms);
+            }
+        "#);
+
+        Timer(m)
+    }
+}
+
+/// A future which completes after a number of milliseconds.
+///
+/// This is created by the [`delay`] function and works with both the `v02` and
+/// the `v03` executors.
+///
+/// [`delay`]: fn.delay.html
+pub struct Delay {
+    id: LazyId,
+    ms: u32,
+    done: bool,
+}
+
+impl Delay {
+    /// Check whether the timer fired, otherwise register `wake` to run when it
+    /// does.
+    fn poll_timer<F>(&mut self, wake: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.done {
+            return true;
+        }
+
+        let ms = self.ms;
+
+        // The timer only starts on the first poll, like any other future.
+        let id = self.id.get_or_init(|id| {
+            let () = TIMER.lock().0.call("setTimeout", (id, ms));
+        });
+
+        self.done = CALLBACKS.pop_listen(id, wake).is_some();
+        self.done
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        // A pending timer may still fire, which will simply be ignored.
+        CALLBACKS.remove(self.id.get_or_init(|_| ()));
+    }
+}
+
+impl futures_v02x::Future for Delay {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(
+        &mut self,
+        cx: &mut futures_v02x::task::Context,
+    ) -> futures_v02x::Poll<(), Never> {
+        let waker = cx.waker().clone();
+
+        if self.poll_timer(move || waker.wake()) {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::Pending)
+        }
+    }
+}
+
+impl future::Future for Delay {
+    type Output = ();
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context,
+    ) -> task::Poll<()> {
+        let waker = cx.waker().clone();
+
+        if self.poll_timer(move || waker.wake()) {
+            task::Poll::Ready(())
+        } else {
+            task::Poll::Pending
+        }
+    }
+}
+
+/// Complete after `ms` milliseconds.
+///
+/// Any number of delays may run at once.
+///
+/// ```rust,no_run
+/// # extern crate stasis;
+/// # extern crate futures_v02x;
+/// # use futures_v02x::FutureExt;
+/// # use stasis::{console, futures::{delay, v02}};
+/// # fn main() {
+/// v02::spawn(delay(1000).map(|()| console::log("done")));
+/// # }
+/// ```
+pub fn delay(ms: u32) -> Delay {
+    Delay {
+        id: CALLBACKS.lazy(),
+        ms,
+        done: false,
+    }
+}
+
+/// A future which gives the thread back to the event loop once.
+///
+/// This is created by the [`yield_now`] function and works with both the `v02`
+/// and the `v03` executors.
+///
+/// [`yield_now`]: fn.yield_now.html
+pub struct YieldNow(Delay);
+
+impl futures_v02x::Future for YieldNow {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(
+        &mut self,
+        cx: &mut futures_v02x::task::Context,
+    ) -> futures_v02x::Poll<(), Never> {
+        futures_v02x::Future::poll(&mut self.0, cx)
+    }
+}
+
+impl future::Future for YieldNow {
+    type Output = ();
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context,
+    ) -> task::Poll<()> {
+        future::Future::poll(Pin::new(&mut self.0), cx)
+    }
+}
+
+/// Let the browser run before continuing.
+///
+/// WebAssembly runs on the main thread, so a long computation freezes the page
+/// until it returns. Awaiting this in between chunks of work continues in a
+/// `setTimeout` of zero, which gives the browser a chance to render and handle
+/// input first. Unlike a microtask, this does not hold up rendering.
+///
+/// Browsers wait at least a few milliseconds for nested timeouts, so yield
+/// every few milliseconds of work rather than on every iteration.
+///
+/// ```rust,no_run
+/// # extern crate stasis;
+/// # extern crate futures_v02x;
+/// # use futures_v02x::{FutureExt, future::{loop_fn, Loop}};
+/// # use stasis::futures::{v02, yield_now};
+/// # fn work(_chunk: u32) {}
+/// # fn main() {
+/// v02::spawn(loop_fn(0, |chunk| {
+///     work(chunk);
+///
+///     yield_now().map(move |()| match chunk {
+///         99 => Loop::Break(()),
+///         _ => Loop::Continue(chunk + 1),
+///     })
+/// }));
+/// # }
+/// ```
+pub fn yield_now() -> YieldNow {
+    YieldNow(delay(0))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures_v02x::{FutureExt, future::{loop_fn, Loop}};
+    use serde_json::{Value, json};
+
+    use stasis_internals::mock;
+
+    use futures::v02;
+    use super::{TIMER, yield_now};
+
+    #[test]
+    fn yield_now_returns_to_executor() {
+        let timers = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let timers = timers.clone();
+
+            mock::implement("setTimeout", move |args| {
+                assert_eq!(args[1], json!(0));
+
+                timers.lock().unwrap().push(args[0].clone());
+                Value::Null
+            });
+        }
+
+        let steps = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(AtomicUsize::new(0));
+
+        {
+            let steps = steps.clone();
+            let done = done.clone();
+
+            let work = loop_fn(0, move |i| {
+                steps.fetch_add(1, Ordering::SeqCst);
+
+                yield_now().map(move |()| {
+                    if i == 2 { Loop::Break(()) } else { Loop::Continue(i + 1) }
+                })
+            });
+
+            v02::spawn(work.map(move |()| {
+                done.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+
+        let m = TIMER.lock().0;
+
+        // Each step runs only once the timer of the previous one has fired.
+        for step in 1..4 {
+            assert_eq!(steps.load(Ordering::SeqCst), step);
+            assert_eq!(timers.lock().unwrap().len(), step);
+            assert_eq!(done.load(Ordering::SeqCst), 0);
+
+            let id = timers.lock().unwrap()[step - 1].clone();
+            mock::callback(m.id(), "done", id).unwrap();
+        }
+
+        assert_eq!(steps.load(Ordering::SeqCst), 3);
+        assert_eq!(done.load(Ordering::SeqCst), 1);
+    }
+}
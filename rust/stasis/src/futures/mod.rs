@@ -1,7 +1,8 @@
 //! Support for various versions of `futures`.
 //!
 //! The executors in this module will never fail to spawn a future, barring
-//! extreme circumstances such as OOM errors.
+//! extreme circumstances such as OOM errors. Each executor offers `try_spawn`
+//! to handle running out of memory instead of aborting.
 //!
 //! ## A note on poll order
 //!
@@ -9,6 +10,86 @@
 //! polled. The implementations here do not use a queue to handle this
 //! situation, rather they immediately poll the freshly spawned future. This
 //! should not affect usage of futures.
+//!
+//! Deeply nested spawns can however exhaust the stack. The `v02` executor
+//! offers an opt-in queue for this case, see `v02::StasisExecutor::with_queue`.
+
+use std::{error::Error, fmt};
+
+mod delay;
+pub(crate) mod promise;
+mod stream;
 
 pub mod v01;
 pub mod v02;
+pub mod v03;
+
+pub use self::delay::{delay, yield_now, Delay, YieldNow};
+pub use self::promise::{Promise, PromiseError};
+pub use self::stream::{callback_stream, push_stream, CallbackStream};
+
+/// The number of pending futures in each executor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolStats {
+    pub v01: usize,
+    pub v02: usize,
+    pub v03: usize,
+}
+
+/// The error returned by `try_spawn` when there is no memory left to store a
+/// future.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnError {
+    _private: (),
+}
+
+impl SpawnError {
+    pub(crate) fn out_of_memory() -> Self {
+        SpawnError { _private: () }
+    }
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Out of memory while spawning a future")
+    }
+}
+
+impl Error for SpawnError {}
+
+/// Count the pending futures in each executor.
+///
+/// A future is pending when it has been spawned but has not completed yet.
+/// This is useful to find out which executor a stuck future was spawned on.
+pub fn active_pools() -> PoolStats {
+    PoolStats {
+        v01: v01::pending(),
+        v02: v02::pending(),
+        v03: v03::pending(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures_v01x::future;
+
+    use super::{active_pools, v01};
+
+    #[test]
+    fn try_spawn() {
+        let token = v01::try_spawn(future::empty()).unwrap();
+        token.cancel();
+    }
+
+    #[test]
+    fn counts_pending() {
+        // Other tests may spawn futures meanwhile, so only compare counts.
+        let before = active_pools().v01;
+
+        let token = v01::spawn(future::empty());
+        assert_eq!(active_pools().v01, before + 1);
+
+        token.cancel();
+        assert_eq!(active_pools().v01, before);
+    }
+}
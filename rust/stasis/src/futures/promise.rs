@@ -0,0 +1,268 @@
+//! JavaScript `Promise` support.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt,
+    future,
+    marker::PhantomData,
+    pin::Pin,
+    task,
+};
+
+use futures_v02x::{self, Async};
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+use Module;
+use global::Global;
+use callbacks::{Callbacks, CallbackId};
+
+/// The outcome of a settled promise.
+type Settled = Result<Value, PromiseError>;
+
+/// Every promise shares this manager, which hands out a unique ID per call.
+static CALLBACKS: Callbacks<Settled> = Callbacks::INIT;
+
+/// Modules which have the promise helpers registered.
+static MODULES: Global<HashSet<u32>> = Global::INIT;
+
+/// Register the promise helpers on a module if it does not have them yet.
+fn ensure_helpers(m: &Module) {
+    if !MODULES.lock().insert(m.id()) {
+        return;
+    }
+
+    #[derive(Deserialize)]
+    struct Settle {
+        id: CallbackId,
+        ok: Option<Value>,
+        err: Option<String>,
+    }
+
+    m.register_callback("__stasis_settle", |settle: Settle| {
+        let settled = match settle.err {
+            Some(message) => Err(PromiseError { message }),
+            None => Ok(settle.ok.unwrap_or(Value::Null)),
+        };
+
+        CALLBACKS.push(settle.id, settled);
+    });
+
+    m.register("__stasis_promise", r#"
+        function(name, id, args) {
+            var settle = this.callbacks.__stasis_settle;
+
+            if (!(args instanceof Array)) {
+                args = [ args ];
+            }
+
+            var promise;
+
+            try {
+                promise = Promise.resolve(this.functions[name].apply(this, args));
+            } catch (e) {
+                promise = Promise.reject(e);
+            }
+
+            promise.then(
+                function(value) { settle({ id: id, ok: value }) },
+                function(e) {
+                    var err = e instanceof Error ? e.message : String(e);
+                    settle({ id: id, err: err });
+                }
+            );
+        }
+    "#);
+}
+
+/// A rejected promise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromiseError {
+    message: String,
+}
+
+impl PromiseError {
+    /// The rejection reason.
+    ///
+    /// This is the `message` of a rejected `Error`, or the reason converted to
+    /// a string otherwise.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for PromiseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Promise rejected: {}", self.message)
+    }
+}
+
+impl Error for PromiseError {}
+
+/// A future which completes when a JavaScript promise settles.
+///
+/// This is created by [`Module::call_promise`] and works with both the `v02`
+/// and the `v03` executors.
+///
+/// [`Module::call_promise`]: ../struct.Module.html#method.call_promise
+pub struct Promise<R> {
+    id: CallbackId,
+    settled: bool,
+    _marker: PhantomData<fn() -> R>,
+}
+
+impl<R> Promise<R>
+where
+    R: for<'a> Deserialize<'a>,
+{
+    #[doc(hidden)]
+    pub fn call<T>(m: &Module, name: &str, args: T) -> Self
+    where
+        T: Serialize,
+    {
+        // The helpers are shared by every namespace of a module.
+        let helpers = m.without_namespace();
+        ensure_helpers(&helpers);
+
+        let id = CALLBACKS.create();
+
+        // This is timed as the call to `name` by `Module::call_promise`.
+        let () = stasis_internals::outgoing::call(
+            helpers.id,
+            "__stasis_promise",
+            (m.qualify(name), id, args),
+        );
+
+        Promise {
+            id,
+            settled: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Take the result if the promise has settled, otherwise register `wake`
+    /// to run when it does.
+    fn poll_settled<F>(&mut self, wake: F) -> Option<Result<R, PromiseError>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        assert!(!self.settled, "Promise polled after completion");
+
+        let settled = CALLBACKS.pop_listen(self.id, wake)?;
+
+        self.settled = true;
+
+        let value = match settled {
+            Ok(value) => value,
+            Err(e) => return Some(Err(e)),
+        };
+
+        match serde_json::from_value(value) {
+            Ok(r) => Some(Ok(r)),
+            Err(e) => {
+                panic!(
+                    "STASIS: Failed to deserialize promise value.\n\
+                     Error {:?}",
+                    e
+                )
+            }
+        }
+    }
+}
+
+impl<R> Drop for Promise<R> {
+    fn drop(&mut self) {
+        // A pending promise may still settle, which will simply be ignored.
+        CALLBACKS.remove(self.id);
+    }
+}
+
+impl<R> futures_v02x::Future for Promise<R>
+where
+    R: for<'a> Deserialize<'a>,
+{
+    type Item = R;
+    type Error = PromiseError;
+
+    fn poll(
+        &mut self,
+        cx: &mut futures_v02x::task::Context,
+    ) -> futures_v02x::Poll<R, PromiseError> {
+        let waker = cx.waker().clone();
+
+        match self.poll_settled(move || waker.wake()) {
+            Some(Ok(r)) => Ok(Async::Ready(r)),
+            Some(Err(e)) => Err(e),
+            None => Ok(Async::Pending),
+        }
+    }
+}
+
+impl<R> future::Future for Promise<R>
+where
+    R: for<'a> Deserialize<'a>,
+{
+    type Output = Result<R, PromiseError>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context,
+    ) -> task::Poll<Self::Output> {
+        let waker = cx.waker().clone();
+
+        match self.poll_settled(move || waker.wake()) {
+            Some(result) => task::Poll::Ready(result),
+            None => task::Poll::Pending,
+        }
+    }
+}
+
+/// Promises under the mock runtime, which cannot run the promise helpers.
+///
+/// Mock implementations are shared by every test, so the helper records every
+/// started promise here and tests pick out those of their own functions.
+#[cfg(test)]
+pub(crate) mod testing {
+    use serde_json::{Value, json};
+
+    use stasis_internals::mock;
+
+    use Module;
+    use global::Global;
+
+    /// Started promises as `(name, id, args)`, oldest first.
+    static STARTED: Global<Vec<(Value, Value, Value)>> = Global::INIT;
+
+    /// Record promises instead of starting them.
+    pub fn install() {
+        mock::implement("__stasis_promise", |args| {
+            let promise = (args[0].clone(), args[1].clone(), args[2].clone());
+
+            STARTED.lock().push(promise);
+            Value::Null
+        });
+    }
+
+    /// Take the ids and arguments of the promises started for `name`.
+    pub fn started(name: &str) -> Vec<(Value, Value)> {
+        let mut started = STARTED.lock();
+        let (taken, rest): (Vec<_>, _) =
+            started.drain(..).partition(|s| s.0 == name);
+
+        *started = rest;
+
+        taken.into_iter().map(|(_, id, args)| (id, args)).collect()
+    }
+
+    /// Settle a promise started on `m`.
+    pub fn settle(m: &Module, id: &Value, result: Result<Value, &str>) {
+        let settle = match result {
+            Ok(value) => json!({ "id": id, "ok": value }),
+            Err(message) => json!({ "id": id, "err": message }),
+        };
+
+        let m = m.without_namespace();
+        mock::callback(m.id(), "__stasis_settle", settle).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+//! Streams of values pushed by a repeatedly-firing JavaScript callback.
+
+use std::marker::PhantomData;
+
+use futures_v02x::{self, Async, Never};
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+use callbacks::{Callbacks, CallbackId};
+
+/// Every stream shares this manager, as a static cannot be generic. Values are
+/// decoded into the item type of the stream once they are polled.
+static CALLBACKS: Callbacks<Value> = Callbacks::INIT;
+
+/// A stream of values pushed with [`push_stream`].
+///
+/// This is created by the [`callback_stream`] function. The stream never ends
+/// on its own, and stops receiving values once dropped.
+///
+/// [`push_stream`]: fn.push_stream.html
+/// [`callback_stream`]: fn.callback_stream.html
+pub struct CallbackStream<T> {
+    id: CallbackId,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> CallbackStream<T> {
+    /// The id which values are pushed to.
+    pub fn id(&self) -> CallbackId {
+        self.id
+    }
+}
+
+impl<T> Drop for CallbackStream<T> {
+    fn drop(&mut self) {
+        // Later pushes to this id are ignored.
+        CALLBACKS.remove(self.id);
+    }
+}
+
+impl<T> futures_v02x::Stream for CallbackStream<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    type Item = T;
+    type Error = Never;
+
+    fn poll_next(
+        &mut self,
+        cx: &mut futures_v02x::task::Context,
+    ) -> futures_v02x::Poll<Option<T>, Never> {
+        let waker = cx.waker().clone();
+
+        // Values are popped oldest first. The executor may poll this stream
+        // from within `push_stream`, see the note on poll order, which still
+        // only ever yields the value at the front.
+        let value = match CALLBACKS.pop_listen(self.id, move || waker.wake()) {
+            Some(value) => value,
+            None => return Ok(Async::Pending),
+        };
+
+        match serde_json::from_value(value) {
+            Ok(t) => Ok(Async::Ready(Some(t))),
+            Err(e) => panic!("Failed to deserialize stream item: {}", e),
+        }
+    }
+}
+
+/// Create a stream of the values pushed to a new `CallbackId`.
+///
+/// `setup` receives the id right away, and should hand it to JavaScript. Any
+/// callback can then forward values to the stream with [`push_stream`],
+/// including values pushed before the stream is first polled. Items are
+/// yielded in the order they were pushed.
+///
+/// ```rust,no_run
+/// # extern crate stasis;
+/// # extern crate futures_v02x;
+/// # use futures_v02x::{FutureExt, StreamExt};
+/// # use stasis::{Module, console};
+/// # use stasis::callbacks::CallbackId;
+/// # use stasis::futures::{callback_stream, push_stream, v02};
+/// # fn main() {
+/// let m = Module::new();
+/// m.register_callback("message", |(id, data): (CallbackId, String)| {
+///     push_stream(id, data);
+/// });
+/// m.register("listen", r#"
+///     function(id, url) {
+///         var message = this.callbacks.message;
+///         var socket = new WebSocket(url);
+///
+///         socket.onmessage = function(e) { message(id, e.data) };
+///     }
+/// "#);
+///
+/// let messages = callback_stream::<String, _>(|id| {
+///     let () = m.call("listen", (id, "wss://example.com"));
+/// });
+///
+/// v02::spawn(messages.for_each(|data| {
+///     console::log(data);
+///     Ok(())
+/// }).map(|_| ()));
+/// # }
+/// ```
+///
+/// [`push_stream`]: fn.push_stream.html
+pub fn callback_stream<T, F>(setup: F) -> CallbackStream<T>
+where
+    F: FnOnce(CallbackId),
+{
+    let id = CALLBACKS.create();
+
+    // Values are only kept for ids which have been listened to, so this makes
+    // sure none are lost before the first poll.
+    CALLBACKS.listen(id, || ());
+
+    setup(id);
+
+    CallbackStream {
+        id,
+        _item: PhantomData,
+    }
+}
+
+/// Push a value to the stream created for `id`.
+///
+/// The value is ignored once the stream has been dropped.
+pub fn push_stream<T>(id: CallbackId, value: T)
+where
+    T: Serialize,
+{
+    let value = match serde_json::to_value(value) {
+        Ok(value) => value,
+        Err(e) => panic!("Failed to serialize stream item: {}", e),
+    };
+
+    CALLBACKS.push(id, value);
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use futures_v02x::{FutureExt, StreamExt};
+
+    use callbacks::CallbackId;
+    use futures::v02;
+    use super::{callback_stream, push_stream};
+
+    #[test]
+    fn yields_in_order() {
+        let mut setup_id = None;
+        let stream = callback_stream::<u32, _>(|id| setup_id = Some(id));
+        let id = setup_id.unwrap();
+
+        // Pushed before the first poll.
+        push_stream(id, 1);
+
+        let items = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let items = items.clone();
+
+            v02::spawn(stream.take(4).for_each(move |n| {
+                items.lock().unwrap().push(n);
+                Ok(())
+            }).map(|_| ()));
+        }
+
+        push_stream(id, 2);
+        push_stream(id, 3);
+        push_stream(id, 4);
+
+        assert_eq!(*items.lock().unwrap(), vec![1, 2, 3, 4]);
+
+        // The stream has been dropped, so this is ignored.
+        push_stream(id, 5);
+    }
+
+    #[test]
+    fn drop_detaches() {
+        let mut setup_id: Option<CallbackId> = None;
+        let stream = callback_stream::<u32, _>(|id| setup_id = Some(id));
+        let id = setup_id.unwrap();
+
+        drop(stream);
+        push_stream(id, 1);
+
+        let stream = callback_stream::<u32, _>(|_| ());
+        assert!(stream.id() != id);
+    }
+}
@@ -11,6 +11,7 @@ use futures_v01x::{
     Future,
 };
 use global::Global;
+use super::SpawnError;
 
 type Boxed = Box<Future<Item = (), Error = ()> + 'static + Send>;
 
@@ -65,15 +66,74 @@ impl Notify for StasisNotify {
     }
 }
 
+/// The number of futures waiting to be woken.
+///
+/// A future is counted from when it is spawned until it completes or is
+/// cancelled. Tests can check this is back to zero to catch futures which are
+/// never woken. See also `futures::active_pools`.
+pub fn pending() -> usize {
+    POOL.lock().futures.len()
+}
+
+/// A handle to a spawned future.
+///
+/// Dropping the token does *not* cancel the future.
+pub struct SpawnToken {
+    id: usize,
+}
+
+impl SpawnToken {
+    /// Cancel the future, dropping it without polling it again.
+    ///
+    /// This is a no-op if the future has already completed. Cancelling a future
+    /// from within its own `poll` has no effect.
+    pub fn cancel(self) {
+        let f = POOL.lock().futures.remove(&self.id);
+
+        // The future is dropped outside of the lock as its destructor may
+        // access the pool.
+        drop(f);
+    }
+}
+
 /// Spawn a future.
-pub fn spawn<F: 'static + Send + Future<Item = (), Error = ()>>(f: F) {
+///
+/// # Panics
+///
+/// This function will panic if there is no memory left to store the future.
+/// See `try_spawn`.
+pub fn spawn<F>(f: F) -> SpawnToken
+where
+    F: 'static + Send + Future<Item = (), Error = ()>,
+{
+    match try_spawn(f) {
+        Ok(token) => token,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Spawn a future, returning an error if there is no memory left to store it.
+///
+/// The future is boxed before it is stored, and failing to allocate the box
+/// still aborts.
+pub fn try_spawn<F>(f: F) -> Result<SpawnToken, SpawnError>
+where
+    F: 'static + Send + Future<Item = (), Error = ()>,
+{
     let spawn = executor::spawn(Box::new(f) as Boxed);
 
     let mut guard = POOL.lock();
+
+    if guard.futures.try_reserve(1).is_err() {
+        return Err(SpawnError::out_of_memory());
+    }
+
     let id = guard.next();
     guard.futures.insert(id, spawn);
 
     drop(guard);
 
     poll(id);
+
+    Ok(SpawnToken { id })
 }
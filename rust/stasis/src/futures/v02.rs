@@ -1,13 +1,15 @@
 //! Support for `futures 0.2.x`.
 
 use std::{
-    collections::HashMap,
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
     sync::Arc,
 };
 
 use global::Global;
+use super::SpawnError;
 use futures_v02x::{
-    executor::{Executor, SpawnError},
+    executor::{self, Executor},
     task::{Context, LocalMap, Waker, Wake},
     Future,
     Never,
@@ -16,6 +18,19 @@ use futures_v02x::{
 
 static POOL: Global<Pool> = Global::INIT;
 
+thread_local! {
+    /// Whether queue mode is enabled on this thread.
+    static QUEUE: Cell<bool> = const { Cell::new(false) };
+
+    /// Set while this thread is draining its queue.
+    static DRAINING: Cell<bool> = const { Cell::new(false) };
+
+    /// IDs waiting to be polled in queue mode.
+    static QUEUED: RefCell<VecDeque<u32>> = const {
+        RefCell::new(VecDeque::new())
+    };
+}
+
 /// Futures stored in the pool.
 type Boxed = Box<Future<Item = (), Error = Never> + 'static + Send>;
 
@@ -26,6 +41,9 @@ struct Pool {
 
     /// All futures stored by ID.
     futures: HashMap<u32, Boxed>,
+
+    /// The number of futures which have completed.
+    completed: usize,
 }
 
 struct StasisWake {
@@ -34,16 +52,56 @@ struct StasisWake {
 
 impl Wake for StasisWake {
     fn wake(arc_self: &Arc<Self>) {
-        StasisExecutor.poll(arc_self.id);
+        StasisExecutor.schedule(arc_self.id);
     }
 }
 
 /// An executor.
 ///
 /// This can be freely constructed without any function calls.
+///
+/// By default, futures are polled as soon as they are spawned or woken. See
+/// [`StasisExecutor::with_queue`] for an alternative.
 pub struct StasisExecutor;
 
 impl StasisExecutor {
+    /// Switch the executor to queue mode.
+    ///
+    /// In queue mode, a future spawned or woken while another future is being
+    /// polled is pushed onto a FIFO queue instead of being polled right away.
+    /// The queue is drained iteratively, so a future spawning many children,
+    /// or a long chain of futures each spawning the next, no longer grows the
+    /// stack.
+    ///
+    /// The mode is shared by every `StasisExecutor` on the calling thread, and
+    /// cannot be switched off again. Other threads keep polling right away.
+    pub fn with_queue() -> Self {
+        QUEUE.with(|queue| queue.set(true));
+
+        StasisExecutor
+    }
+
+    /// Poll a future now, or queue it if queue mode is draining.
+    fn schedule(&mut self, id: u32) {
+        if !QUEUE.with(Cell::get) {
+            return self.poll(id);
+        }
+
+        QUEUED.with(|queued| queued.borrow_mut().push_back(id));
+
+        // An outer call is already draining the queue.
+        if DRAINING.with(|draining| draining.replace(true)) {
+            return;
+        }
+
+        // Important: the queue must not be borrowed while polling.
+        while let Some(id) = QUEUED.with(|q| q.borrow_mut().pop_front()) {
+            self.poll(id);
+        }
+
+        DRAINING.with(|draining| draining.set(false));
+    }
+
     fn poll(&mut self, id: u32) {
         let mut f = match POOL.lock().futures.remove(&id) {
             Some(f) => f,
@@ -66,16 +124,19 @@ impl StasisExecutor {
                     .insert(id, f);
             }
 
-            Ok(Async::Ready(())) => (),
+            Ok(Async::Ready(())) => POOL.lock().completed += 1,
             Err(e) => e.never_into(),
         }
     }
-}
 
-impl Executor for StasisExecutor {
-    fn spawn(&mut self, f: Boxed) -> Result<(), SpawnError> {
+    /// Spawn a future, returning its ID.
+    fn spawn_id(&mut self, f: Boxed) -> Result<u32, SpawnError> {
         let mut lock = POOL.lock();
 
+        if lock.futures.try_reserve(1).is_err() {
+            return Err(SpawnError::out_of_memory());
+        }
+
         let id = lock.counter;
         lock.counter += 1;
 
@@ -84,15 +145,185 @@ impl Executor for StasisExecutor {
         // Important: this must be dropped before poll to avoid deadlock.
         drop(lock);
 
-        self.poll(id);
+        self.schedule(id);
 
-        Ok(())
+        Ok(id)
+    }
+}
+
+impl Executor for StasisExecutor {
+    fn spawn(&mut self, f: Boxed) -> Result<(), executor::SpawnError> {
+        // This is the only error `futures 0.2` has to offer.
+        match self.spawn_id(f) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(executor::SpawnError::shutdown()),
+        }
+    }
+}
+
+/// The number of futures waiting to be woken.
+///
+/// A future is counted from when it is spawned until it completes or is
+/// cancelled. Tests can check this is back to zero to catch futures which are
+/// never woken. See also `futures::active_pools`.
+pub fn pending() -> usize {
+    POOL.lock().futures.len()
+}
+
+/// Poll every pending future until none of them complete, returning how many
+/// completed.
+///
+/// This advances async code in tests without an event loop. Each round polls
+/// every future in the pool, including those spawned by the previous round,
+/// and rounds continue for as long as any future completes. Futures waiting on
+/// JavaScript, such as a `Delay`, simply stay pending.
+///
+/// Futures completed by other threads meanwhile are counted too.
+pub fn run_until_stalled() -> usize {
+    let start = POOL.lock().completed;
+    let mut last = start;
+
+    loop {
+        let ids: Vec<u32> = POOL.lock().futures.keys().cloned().collect();
+
+        for id in ids {
+            StasisExecutor.schedule(id);
+        }
+
+        let completed = POOL.lock().completed;
+
+        if completed == last {
+            return completed - start;
+        }
+
+        last = completed;
+    }
+}
+
+/// A handle to a spawned future.
+///
+/// Dropping the token does *not* cancel the future.
+pub struct SpawnToken {
+    id: u32,
+}
+
+impl SpawnToken {
+    /// Cancel the future, dropping it without polling it again.
+    ///
+    /// This is a no-op if the future has already completed. Cancelling a future
+    /// from within its own `poll` has no effect.
+    pub fn cancel(self) {
+        let f = POOL.lock().futures.remove(&self.id);
+
+        // The future is dropped outside of the lock as its destructor may
+        // access the pool.
+        drop(f);
     }
 }
 
 /// Spawn a future.
-pub fn spawn<F: 'static + Send + Future<Item = (), Error = Never>>(f: F) {
-    StasisExecutor
-        .spawn(Box::new(f))
-        .expect("StasisExecutor failed to spawn. This should never happen.");
+///
+/// # Panics
+///
+/// This function will panic if there is no memory left to store the future.
+/// See `try_spawn`.
+pub fn spawn<F>(f: F) -> SpawnToken
+where
+    F: 'static + Send + Future<Item = (), Error = Never>,
+{
+    match try_spawn(f) {
+        Ok(token) => token,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Spawn a future, returning an error if there is no memory left to store it.
+///
+/// The future is boxed before it is stored, and failing to allocate the box
+/// still aborts.
+pub fn try_spawn<F>(f: F) -> Result<SpawnToken, SpawnError>
+where
+    F: 'static + Send + Future<Item = (), Error = Never>,
+{
+    let id = StasisExecutor.spawn_id(Box::new(f))?;
+
+    Ok(SpawnToken { id })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures_v02x::{task::Context, Async, Future, Never, Poll};
+
+    use super::{pending, run_until_stalled, spawn, StasisExecutor};
+
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+
+    /// Spawns the next link of the chain when polled.
+    struct Chain(u32);
+
+    impl Future for Chain {
+        type Item = ();
+        type Error = Never;
+
+        fn poll(&mut self, _cx: &mut Context) -> Poll<(), Never> {
+            if self.0 > 0 {
+                spawn(Chain(self.0 - 1));
+            }
+
+            DONE.fetch_add(1, Ordering::SeqCst);
+
+            Ok(Async::Ready(()))
+        }
+    }
+
+    static LAZY_DONE: AtomicUsize = AtomicUsize::new(0);
+
+    /// Returns `Pending` once without arranging a wake up, then spawns the
+    /// next link of the chain.
+    struct Lazy {
+        links: u32,
+        polled: bool,
+    }
+
+    impl Future for Lazy {
+        type Item = ();
+        type Error = Never;
+
+        fn poll(&mut self, _cx: &mut Context) -> Poll<(), Never> {
+            if !self.polled {
+                self.polled = true;
+                return Ok(Async::Pending);
+            }
+
+            if self.links > 0 {
+                spawn(Lazy { links: self.links - 1, polled: false });
+            }
+
+            LAZY_DONE.fetch_add(1, Ordering::SeqCst);
+
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn run_until_stalled_completes_chain() {
+        spawn(Lazy { links: 4, polled: false });
+        assert_eq!(LAZY_DONE.load(Ordering::SeqCst), 0);
+        assert!(pending() >= 1);
+
+        assert!(run_until_stalled() >= 5);
+        assert_eq!(LAZY_DONE.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn queue_does_not_recurse() {
+        // Each test runs on its own thread, so this leaves other tests alone.
+        StasisExecutor::with_queue();
+
+        spawn(Chain(9_999));
+
+        assert_eq!(DONE.load(Ordering::SeqCst), 10_000);
+    }
 }
@@ -0,0 +1,277 @@
+//! Support for `std::future` and `futures 0.3.x`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+    task::{Context, RawWaker, RawWakerVTable, Waker},
+};
+
+use global::Global;
+use super::SpawnError;
+
+static POOL: Global<Pool> = Global::INIT;
+
+/// Futures stored in the pool.
+type Boxed = Pin<Box<dyn Future<Output = ()> + 'static + Send>>;
+
+#[derive(Default)]
+struct Pool {
+    /// An incrementing ID counter.
+    counter: u32,
+
+    /// All futures stored by ID, except those being polled.
+    futures: HashMap<u32, Boxed>,
+
+    /// The futures being polled.
+    running: HashSet<u32>,
+
+    /// The futures which were woken while being polled.
+    woken: HashSet<u32>,
+}
+
+// A waker is nothing more than the ID of its future, stored directly in the
+// data pointer. There is no allocation, so cloning and dropping are trivial.
+static VTABLE: RawWakerVTable =
+    RawWakerVTable::new(clone, wake, wake, drop_waker);
+
+unsafe fn clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+unsafe fn wake(data: *const ()) {
+    poll(data as usize as u32);
+}
+
+unsafe fn drop_waker(_data: *const ()) {}
+
+fn waker(id: u32) -> Waker {
+    // This is safe as the vtable functions never dereference the data pointer.
+    unsafe {
+        Waker::from_raw(RawWaker::new(id as usize as *const (), &VTABLE))
+    }
+}
+
+fn poll(id: u32) {
+    let mut f = {
+        let mut pool = POOL.lock();
+
+        match pool.futures.remove(&id) {
+            Some(f) => {
+                pool.running.insert(id);
+                f
+            }
+            None => {
+                // The future is being polled further up the stack, or on
+                // another thread. It is polled again once that poll returns.
+                if pool.running.contains(&id) {
+                    pool.woken.insert(id);
+                }
+
+                return;
+            }
+        }
+    };
+
+    let waker = waker(id);
+    let mut context = Context::from_waker(&waker);
+
+    loop {
+        let poll = f.as_mut().poll(&mut context);
+
+        let mut pool = POOL.lock();
+
+        if poll.is_ready() {
+            pool.running.remove(&id);
+            pool.woken.remove(&id);
+            return;
+        }
+
+        // Re-insert if pending, unless it was woken meanwhile.
+        if !pool.woken.remove(&id) {
+            pool.running.remove(&id);
+            pool.futures.insert(id, f);
+            return;
+        }
+    }
+}
+
+/// The number of futures waiting to be woken.
+///
+/// A future is counted from when it is spawned until it completes or is
+/// cancelled. Tests can check this is back to zero to catch futures which are
+/// never woken. See also `futures::active_pools`.
+pub fn pending() -> usize {
+    POOL.lock().futures.len()
+}
+
+/// A handle to a spawned future.
+///
+/// Dropping the token does *not* cancel the future.
+pub struct SpawnToken {
+    id: u32,
+}
+
+impl SpawnToken {
+    /// Cancel the future, dropping it without polling it again.
+    ///
+    /// This is a no-op if the future has already completed. Cancelling a future
+    /// from within its own `poll` has no effect.
+    pub fn cancel(self) {
+        let f = POOL.lock().futures.remove(&self.id);
+
+        // The future is dropped outside of the lock as its destructor may
+        // access the pool.
+        drop(f);
+    }
+}
+
+/// Spawn a future.
+///
+/// The future is polled immediately, and then again each time it is woken.
+///
+/// # Panics
+///
+/// This function will panic if there is no memory left to store the future.
+/// See `try_spawn`.
+pub fn spawn<F: 'static + Send + Future<Output = ()>>(f: F) -> SpawnToken {
+    match try_spawn(f) {
+        Ok(token) => token,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Spawn a future, returning an error if there is no memory left to store it.
+///
+/// The future is boxed before it is stored, and failing to allocate the box
+/// still aborts.
+pub fn try_spawn<F>(f: F) -> Result<SpawnToken, SpawnError>
+where
+    F: 'static + Send + Future<Output = ()>,
+{
+    let f = Box::pin(f);
+
+    let mut lock = POOL.lock();
+
+    if lock.futures.try_reserve(1).is_err() {
+        return Err(SpawnError::out_of_memory());
+    }
+
+    let id = lock.counter;
+    lock.counter += 1;
+
+    lock.futures.insert(id, f);
+
+    // Important: this must be dropped before poll to avoid deadlock.
+    drop(lock);
+
+    poll(id);
+
+    Ok(SpawnToken { id })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::{self, Future},
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Waker},
+    };
+
+    use super::spawn;
+
+    /// Resolves on the second poll, once it has been woken.
+    struct WakeOnce {
+        waker: Arc<Mutex<Option<Waker>>>,
+        done: Arc<Mutex<bool>>,
+    }
+
+    impl Future for WakeOnce {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            let mut waker = self.waker.lock().unwrap();
+
+            if waker.is_some() {
+                *self.done.lock().unwrap() = true;
+                Poll::Ready(())
+            } else {
+                *waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn wake_repolls() {
+        let waker = Arc::new(Mutex::new(None));
+        let done = Arc::new(Mutex::new(false));
+
+        spawn(WakeOnce {
+            waker: waker.clone(),
+            done: done.clone(),
+        });
+
+        assert!(!*done.lock().unwrap());
+
+        let w = waker.lock().unwrap().clone().unwrap();
+        w.wake();
+
+        assert!(*done.lock().unwrap());
+    }
+
+    /// Yields to the executor a number of times before resolving.
+    struct Yield {
+        left: u32,
+        done: Arc<Mutex<bool>>,
+    }
+
+    impl Future for Yield {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            if self.left == 0 {
+                *self.done.lock().unwrap() = true;
+                return Poll::Ready(());
+            }
+
+            self.left -= 1;
+            cx.waker().wake_by_ref();
+
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn wake_while_polled() {
+        let done = Arc::new(Mutex::new(false));
+
+        // Each poll wakes the future before returning `Pending`.
+        spawn(Yield { left: 3, done: done.clone() });
+
+        assert!(*done.lock().unwrap());
+    }
+
+    #[test]
+    fn cancel() {
+        let waker = Arc::new(Mutex::new(None));
+        let done = Arc::new(Mutex::new(false));
+
+        let token = spawn(WakeOnce {
+            waker: waker.clone(),
+            done: done.clone(),
+        });
+
+        token.cancel();
+
+        // Waking a cancelled future does nothing.
+        let w = waker.lock().unwrap().clone().unwrap();
+        w.wake();
+
+        assert!(!*done.lock().unwrap());
+
+        // Cancelling a completed future is a no-op.
+        spawn(future::ready(())).cancel();
+    }
+}
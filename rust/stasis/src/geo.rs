@@ -0,0 +1,315 @@
+//! The browser `navigator.geolocation` API.
+//!
+//! This module is only available with the `browser` feature, which is enabled
+//! by default. The browser asks the user for permission first.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # extern crate futures_v02x;
+//! # use futures_v02x::FutureExt;
+//! # use stasis::{console, geo, futures::v02};
+//! # fn main() {
+//! v02::spawn(geo::current_position().then(|result| {
+//!     match result {
+//!         Ok(p) => console::log((p.latitude, p.longitude)),
+//!         Err(e) => console::error(e.to_string()),
+//!     }
+//!
+//!     Ok(())
+//! }));
+//! # }
+//! ```
+
+use std::{
+    error::Error,
+    fmt,
+    future,
+    pin::Pin,
+    task,
+};
+
+use futures_v02x::{self, Async};
+
+use Module;
+use global::Global;
+use callbacks::{Callbacks, CallbackId, LazyId};
+
+/// Every request shares this manager, which hands out a unique ID per request.
+static CALLBACKS: Callbacks<Result<Position, GeoError>> = Callbacks::INIT;
+
+/// The geolocation module.
+struct Geo(Module);
+
+static GEO: Global<Geo> = Global::INIT;
+
+impl Default for Geo {
+    fn default() -> Self {
+        let m = Module::new();
+
+        m.register_callback2("found", |id: CallbackId, position: Position| {
+            CALLBACKS.push(id, Ok(position));
+        });
+
+        m.register_callback2("failed", |id: CallbackId, code: u32| {
+            CALLBACKS.push(id, Err(GeoError::from_code(code)));
+        });
+
+        // Errors are passed on by their code, and a missing API is reported
+        // like an unavailable position.
+        m.register("getCurrentPosition", r#"
+            function(id, options) {
+                var found = this.callbacks.found;
+                var failed = this.callbacks.failed;
+
+                if (!navigator.geolocation) {
+                    return failed(id, 2);
+                }
+
+                navigator.geolocation.getCurrentPosition(
+                    function(position) {
+                        found(id, {
+                            latitude: position.coords.latitude,
+                            longitude: position.coords.longitude,
+                            accuracy: position.coords.accuracy,
+                        });
+                    },
+                    function(e) { failed(id, e.code) },
+                    options
+                );
+            }
+        "#);
+
+        Geo(m)
+    }
+}
+
+/// A position on Earth.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    /// The latitude in decimal degrees.
+    pub latitude: f64,
+
+    /// The longitude in decimal degrees.
+    pub longitude: f64,
+
+    /// The accuracy of the position in meters, with 95% confidence.
+    pub accuracy: f64,
+}
+
+/// Options for a position request.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PositionOptions {
+    /// Ask for the most accurate position the device can give, which may be
+    /// slower and use more power.
+    #[serde(rename = "enableHighAccuracy")]
+    pub high_accuracy: bool,
+
+    /// Fail with `GeoError::Timeout` after this many milliseconds. There is no
+    /// limit by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u32>,
+
+    /// Accept a cached position up to this many milliseconds old.
+    #[serde(rename = "maximumAge")]
+    pub maximum_age: u32,
+}
+
+/// A failed position request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeoError {
+    /// The user or the browser denied access to the location.
+    PermissionDenied,
+
+    /// The position could not be determined, or geolocation is unsupported.
+    Unavailable,
+
+    /// The timeout given in `PositionOptions` ran out.
+    Timeout,
+}
+
+impl GeoError {
+    /// Convert a `GeolocationPositionError` code.
+    fn from_code(code: u32) -> Self {
+        match code {
+            1 => GeoError::PermissionDenied,
+            3 => GeoError::Timeout,
+            _ => GeoError::Unavailable,
+        }
+    }
+}
+
+impl fmt::Display for GeoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GeoError::PermissionDenied => {
+                write!(f, "Geolocation permission denied")
+            }
+            GeoError::Unavailable => write!(f, "The position is unavailable"),
+            GeoError::Timeout => write!(f, "Timed out waiting for a position"),
+        }
+    }
+}
+
+impl Error for GeoError {}
+
+/// A pending position request.
+///
+/// This is created by the [`current_position`] function and works with both
+/// the `v02` and the `v03` executors.
+///
+/// [`current_position`]: fn.current_position.html
+pub struct CurrentPosition {
+    id: LazyId,
+    options: PositionOptions,
+}
+
+impl CurrentPosition {
+    /// Take the result if the request has finished, otherwise register `wake`
+    /// to run when it does.
+    fn poll_position<F>(
+        &mut self,
+        wake: F,
+    ) -> Option<Result<Position, GeoError>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let options = self.options;
+
+        // The request only starts on the first poll, like any other future.
+        let id = self.id.get_or_init(|id| {
+            let () = GEO.lock().0.call("getCurrentPosition", (id, options));
+        });
+
+        CALLBACKS.pop_listen(id, wake)
+    }
+}
+
+impl Drop for CurrentPosition {
+    fn drop(&mut self) {
+        // A pending request may still finish, which will simply be ignored.
+        CALLBACKS.remove(self.id.get_or_init(|_| ()));
+    }
+}
+
+impl futures_v02x::Future for CurrentPosition {
+    type Item = Position;
+    type Error = GeoError;
+
+    fn poll(
+        &mut self,
+        cx: &mut futures_v02x::task::Context,
+    ) -> futures_v02x::Poll<Position, GeoError> {
+        let waker = cx.waker().clone();
+
+        match self.poll_position(move || waker.wake()) {
+            Some(result) => result.map(Async::Ready),
+            None => Ok(Async::Pending),
+        }
+    }
+}
+
+impl future::Future for CurrentPosition {
+    type Output = Result<Position, GeoError>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context,
+    ) -> task::Poll<Self::Output> {
+        let waker = cx.waker().clone();
+
+        match self.poll_position(move || waker.wake()) {
+            Some(result) => task::Poll::Ready(result),
+            None => task::Poll::Pending,
+        }
+    }
+}
+
+/// Request the current position of the device.
+pub fn current_position() -> CurrentPosition {
+    current_position_with(PositionOptions::default())
+}
+
+/// Request the current position of the device with custom options.
+pub fn current_position_with(options: PositionOptions) -> CurrentPosition {
+    CurrentPosition {
+        id: CALLBACKS.lazy(),
+        options,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use futures_v02x::FutureExt;
+    use serde_json::{Value, json};
+
+    use stasis_internals::mock;
+
+    use futures::v02;
+    use super::{
+        GEO,
+        GeoError,
+        Position,
+        PositionOptions,
+        current_position,
+        current_position_with,
+    };
+
+    #[test]
+    fn current_position_settles() {
+        let started = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let started = started.clone();
+
+            mock::implement("getCurrentPosition", move |args| {
+                started.lock().unwrap().push(args);
+                Value::Null
+            });
+        }
+
+        let results = Arc::new(Mutex::new(Vec::new()));
+
+        let options = PositionOptions {
+            timeout: Some(5000),
+            ..PositionOptions::default()
+        };
+
+        let requests = vec![current_position(), current_position_with(options)];
+
+        for request in requests {
+            let results = results.clone();
+
+            v02::spawn(request.then(move |result| {
+                results.lock().unwrap().push(result);
+                Ok(())
+            }));
+        }
+
+        let started = started.lock().unwrap().clone();
+        assert_eq!(started.len(), 2);
+        assert_eq!(started[1][1], json!({
+            "enableHighAccuracy": false,
+            "timeout": 5000,
+            "maximumAge": 0,
+        }));
+
+        let m = GEO.lock().0;
+        let position = json!({
+            "latitude": 1.5,
+            "longitude": -2.5,
+            "accuracy": 10.0,
+        });
+
+        // Settle them out of order, each through its own callback.
+        mock::callback(m.id(), "failed", json!([started[1][0], 3])).unwrap();
+        mock::callback(m.id(), "found", json!([started[0][0], position]))
+            .unwrap();
+
+        assert_eq!(*results.lock().unwrap(), vec![
+            Err(GeoError::Timeout),
+            Ok(Position { latitude: 1.5, longitude: -2.5, accuracy: 10.0 }),
+        ]);
+    }
+}
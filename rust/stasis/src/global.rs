@@ -3,11 +3,71 @@
 //! This is useful for asynchronous functions and memoizing modules.
 
 use std::{
-    sync::{Arc, Mutex, MutexGuard, Once, ONCE_INIT},
+    sync::{
+        Arc, LockResult, Mutex, MutexGuard, PoisonError, RwLock,
+        RwLockReadGuard, RwLockWriteGuard, TryLockError,
+    },
     ops::{Deref, DerefMut},
     cell::UnsafeCell,
-    mem::ManuallyDrop,
+    future::Future,
+    mem::{self, ManuallyDrop},
+    pin::Pin,
+    task::{Context, Poll, Waker},
 };
+#[cfg(debug_assertions)]
+use std::cell::RefCell;
+
+use once_nonstatic::Once;
+
+use futures::v03;
+
+/// A hook run when a `Global` is first initialized.
+type InitHook = Box<dyn FnOnce() + Send>;
+
+/// Initialization hooks waiting on a `Global`.
+struct Hooks {
+    /// Set once the inner value has been created. No hooks are accepted after
+    /// this point.
+    initialized: bool,
+    pending: Vec<InitHook>,
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    /// The addresses of the mutexes locked by a `GlobalLock` on this thread.
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Whether this thread holds the mutex at `addr`.
+///
+/// This is only tracked in debug builds, and is always `false` otherwise.
+fn is_held(addr: usize) -> bool {
+    #[cfg(debug_assertions)]
+    return HELD.with(|held| held.borrow().contains(&addr));
+
+    #[cfg(not(debug_assertions))]
+    {
+        let _ = addr;
+        false
+    }
+}
+
+/// Record whether this thread holds the mutex at `addr`.
+fn set_held(addr: usize, held: bool) {
+    #[cfg(debug_assertions)]
+    HELD.with(|list| {
+        let mut list = list.borrow_mut();
+
+        if held {
+            list.push(addr);
+        } else if let Some(i) = list.iter().rposition(|&a| a == addr) {
+            list.swap_remove(i);
+        }
+    });
+
+    #[cfg(not(debug_assertions))]
+    let _ = (addr, held);
+}
 
 /// A global value wrapped in a [`Mutex`].
 ///
@@ -17,6 +77,7 @@ use std::{
 pub struct Global<T> {
     once: Once,
     inner: UnsafeCell<Option<Arc<Mutex<T>>>>,
+    hooks: Mutex<Hooks>,
 }
 
 // The inner value is only used to make an immutable call to `.clone()`. The
@@ -34,7 +95,22 @@ impl<T: Default> Global<T> {
     /// Ensure the inner value exists.
     ///
     /// This method *must* be called when accessing the inner `UnsafeCell`.
-    fn ensure_exists(&'static self) {
+    fn ensure_exists(&self) {
+        self.ensure_exists_with(T::default)
+    }
+}
+
+impl<T> Global<T> {
+    /// Ensure the inner value exists, creating it with `f` if it does not.
+    ///
+    /// This method or `ensure_exists` *must* be called when accessing the inner
+    /// `UnsafeCell`.
+    fn ensure_exists_with<F>(&self, f: F)
+    where
+        F: FnOnce() -> T,
+    {
+        let mut hooks = None;
+
         self.once.call_once(|| {
             let ptr = self.inner.get();
 
@@ -43,25 +119,124 @@ impl<T: Default> Global<T> {
             // is done.
             unsafe {
                 if (*ptr).is_none() {
-                    *ptr = Some(Arc::new(Mutex::new(T::default())));
+                    *ptr = Some(Arc::new(Mutex::new(f())));
                 }
             }
+
+            let mut guard = self.hooks.lock().unwrap();
+            guard.initialized = true;
+            hooks = Some(mem::take(&mut guard.pending));
         });
+
+        // Hooks are run outside of the `Once` guard so they are free to lock
+        // this global themselves.
+        if let Some(hooks) = hooks {
+            for hook in hooks {
+                hook();
+            }
+        }
+    }
+
+    /// Whether the inner value has been created.
+    ///
+    /// This never creates the value itself, so it is cheap even when the
+    /// `Default` constructor is expensive.
+    pub fn is_initialized(&self) -> bool {
+        self.hooks.lock().unwrap().initialized
+    }
+
+    /// Run a hook the first time the inner value is created.
+    ///
+    /// The hook runs on the thread that performs the initialization, right
+    /// after the `Default` constructor has finished. Returns `false` if the
+    /// value has already been created, in which case the hook is dropped
+    /// without being run.
+    pub fn on_init<F>(&self, f: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut hooks = self.hooks.lock().unwrap();
+
+        if hooks.initialized {
+            false
+        } else {
+            hooks.pending.push(Box::new(f));
+            true
+        }
     }
 }
 
-impl<T: Default + Send + 'static> Global<T> {
+impl<T: Send + 'static> Global<T> {
     /// The initial global value.
     pub const INIT: Global<T> = Global {
-        once: ONCE_INIT,
+        once: Once::INIT,
         inner: UnsafeCell::new(None),
+        hooks: Mutex::new(Hooks {
+            initialized: false,
+            pending: Vec::new(),
+        }),
     };
 
+    /// A non-constant version of `Global::INIT`.
+    ///
+    /// Prefer the constant value where possible.
+    pub fn new() -> Self {
+        Self::INIT
+    }
+
+    /// Obtain a lock on the inner reference, creating the value with `f` if
+    /// it does not exist yet.
+    ///
+    /// This does not require `T: Default`, so it can be used for values that
+    /// need arguments to be constructed. The initializer runs at most once,
+    /// any later calls simply drop their closure. Like `lock`, this method
+    /// will block the current thread until the lock is available.
+    pub fn get_or_init<F>(&self, f: F) -> GlobalLock<T>
+    where
+        F: FnOnce() -> T,
+    {
+        // Important: this *must* be called before accessing the inner pointer.
+        self.ensure_exists_with(f);
+
+        let ptr = self.inner.get() as *const Option<_>;
+
+        // This is safe as we already called `ensure_exists_with`.
+        let opt = unsafe { (*ptr).clone() };
+
+        GlobalLock::new(opt.unwrap())
+    }
+
+    /// Replace the inner value.
+    ///
+    /// If the value does not exist yet, it is created from `value` without
+    /// running the `Default` constructor. The previous value is dropped after
+    /// the lock is released, so its destructor is free to lock this global.
+    pub fn set(&self, value: T) {
+        let mut value = Some(value);
+
+        let old = {
+            let mut lock = self.get_or_init(|| value.take().unwrap());
+
+            value.map(|value| mem::replace(&mut *lock, value))
+        };
+
+        drop(old);
+    }
+}
+
+impl<T: Send + 'static> Default for Global<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Default + Send + 'static> Global<T> {
+
     /// Run a closure on the inner value.
     ///
     /// This will return the closure's return type. This is a cheap function
     /// call.
-    pub fn with<F, R>(&'static self, f: F) -> R
+    pub fn with<F, R>(&self, f: F) -> R
     where
         F: FnOnce(&mut T) -> R,
     {
@@ -75,8 +250,11 @@ impl<T: Default + Send + 'static> Global<T> {
     /// work on multi-threaded systems.
     ///
     /// This method will block the current thread until the lock is available.
-    /// If this is called recursively in WebAssembly, it will panic.
-    pub fn lock(&'static self) -> GlobalLock<T> {
+    /// Locking a global which the current thread already holds is a bug. Debug
+    /// builds panic with a message naming the type, while release builds
+    /// deadlock, or panic inside `Mutex` on WebAssembly. Use `try_lock` where
+    /// the lock may already be held.
+    pub fn lock(&self) -> GlobalLock<T> {
         // Important: this *must* be called before accessing the inner pointer.
         self.ensure_exists();
 
@@ -87,6 +265,52 @@ impl<T: Default + Send + 'static> Global<T> {
 
         GlobalLock::new(opt.unwrap())
     }
+
+    /// Obtain a lock on the inner reference, reporting a poisoned mutex.
+    ///
+    /// A global is poisoned when a thread panics while holding its lock. On
+    /// native targets `lock` then panics as well, while this returns an error
+    /// which still holds the lock, see `PoisonError::into_inner`. WebAssembly
+    /// is single threaded, so `lock` recovers from poison there instead.
+    /// Otherwise this behaves exactly like `lock`.
+    pub fn lock_checked(&self) -> LockResult<GlobalLock<T>> {
+        // Important: this *must* be called before accessing the inner pointer.
+        self.ensure_exists();
+
+        let ptr = self.inner.get() as *const Option<_>;
+
+        // This is safe as we already called `ensure_exists`.
+        let opt = unsafe { (*ptr).clone() };
+
+        GlobalLock::new_checked(opt.unwrap())
+    }
+
+    /// Take the inner value, leaving the default value in its place.
+    ///
+    /// This is useful to drain accumulated state, for example once per frame.
+    pub fn take(&self) -> T {
+        mem::take(&mut *self.lock())
+    }
+
+    /// Attempt to obtain a lock on the inner reference without blocking.
+    ///
+    /// Returns `None` if the lock is already held, including when it is held
+    /// further up the stack of the current thread. This is useful in callbacks
+    /// that may re-enter code already holding this global.
+    ///
+    /// A poisoned global is treated like with `lock`: this panics on native
+    /// targets, and recovers from the poison on WebAssembly.
+    pub fn try_lock(&self) -> Option<GlobalLock<T>> {
+        // Important: this *must* be called before accessing the inner pointer.
+        self.ensure_exists();
+
+        let ptr = self.inner.get() as *const Option<_>;
+
+        // This is safe as we already called `ensure_exists`.
+        let opt = unsafe { (*ptr).clone() };
+
+        GlobalLock::try_new(opt.unwrap())
+    }
 }
 
 /// A handle to some global value of type `T`.
@@ -100,6 +324,8 @@ pub struct GlobalLock<T: 'static> {
 
 impl<T: 'static> Drop for GlobalLock<T> {
     fn drop(&mut self) {
+        set_held(Self::addr(&self.mutex), false);
+
         // Drop the guard *before* the mutex.
         unsafe {
             ManuallyDrop::drop(&mut self.guard);
@@ -109,23 +335,94 @@ impl<T: 'static> Drop for GlobalLock<T> {
 }
 
 impl<T: 'static> GlobalLock<T> {
+    /// The address used to track which mutexes this thread holds.
+    fn addr(mutex: &Arc<Mutex<T>>) -> usize {
+        &**mutex as *const Mutex<T> as usize
+    }
+
     /// Construct a new `GlobalLock` with a reference-counted mutex.
-    fn new(mut mutex: Arc<Mutex<T>>) -> Self {
+    fn new(mutex: Arc<Mutex<T>>) -> Self {
+        match Self::new_checked(mutex) {
+            Ok(lock) => lock,
+
+            // Without threads, the panic which poisoned the mutex has already
+            // been dealt with by the time anything else runs.
+            #[cfg(target_arch = "wasm32")]
+            Err(e) => e.into_inner(),
+
+            #[cfg(not(target_arch = "wasm32"))]
+            Err(_) => panic!(
+                "stasis: Global<{}> is poisoned; use lock_checked",
+                ::std::any::type_name::<T>()
+            ),
+        }
+    }
+
+    /// Construct a new `GlobalLock`, reporting whether the mutex is poisoned.
+    fn new_checked(mutex: Arc<Mutex<T>>) -> Result<Self, PoisonError<Self>> {
+        // Blocking here would never return, so fail loudly instead.
+        if is_held(Self::addr(&mutex)) {
+            panic!(
+                "stasis: recursive Global::lock on {}; use try_lock",
+                ::std::any::type_name::<T>()
+            );
+        }
+
+        let mut poisoned = false;
+
+        // This should never fail.
+        let lock = Self::acquire(mutex, |mutex| match mutex.lock() {
+            Ok(guard) => Some(guard),
+            Err(e) => {
+                poisoned = true;
+                Some(e.into_inner())
+            }
+        }).unwrap();
+
+        if poisoned {
+            Err(PoisonError::new(lock))
+        } else {
+            Ok(lock)
+        }
+    }
+
+    /// Construct a new `GlobalLock` if the mutex is not already locked.
+    fn try_new(mutex: Arc<Mutex<T>>) -> Option<Self> {
+        Self::acquire(mutex, |mutex| match mutex.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::WouldBlock) => None,
+
+            // See `new`.
+            #[cfg(target_arch = "wasm32")]
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+
+            #[cfg(not(target_arch = "wasm32"))]
+            Err(TryLockError::Poisoned(_)) => panic!(
+                "stasis: Global<{}> is poisoned; use lock_checked",
+                ::std::any::type_name::<T>()
+            ),
+        })
+    }
+
+    /// Lock the mutex with `f` and move both into a `GlobalLock`.
+    fn acquire<F>(mut mutex: Arc<Mutex<T>>, f: F) -> Option<Self>
+    where
+        F: FnOnce(&'static Mutex<T>) -> Option<MutexGuard<'static, T>>,
+    {
         // Both the guard and the mutex are moved into the lock. Rust does not
         // support self-referential lifetimes so we must use unsafe code here.
         unsafe {
             // Remove the lifetime constraints on a borrow.
             let ptr = &mut mutex as *mut Arc<Mutex<T>>;
 
-            // This should never fail.
-            let guard = (*ptr)
-                .lock()
-                .unwrap();
+            let guard = f(&**ptr)?;
+
+            set_held(Self::addr(&mutex), true);
 
-            GlobalLock {
+            Some(GlobalLock {
                 guard: ManuallyDrop::new(guard),
                 mutex: ManuallyDrop::new(mutex),
-            }
+            })
         }
     }
 }
@@ -134,13 +431,407 @@ impl<T: 'static> Deref for GlobalLock<T> {
     type Target = T;
 
     fn deref(&self) -> &T {
-        &*self.guard
+        &self.guard
     }
 }
 
 impl<T: 'static> DerefMut for GlobalLock<T> {
     fn deref_mut(&mut self) -> &mut T {
-        &mut *self.guard
+        &mut self.guard
+    }
+}
+
+/// A global value wrapped in a [`RwLock`].
+///
+/// This is the read-heavy counterpart of [`Global`]. Any number of readers may
+/// hold a [`RwGlobal::read`] handle at once, while [`RwGlobal::write`] grants
+/// exclusive access.
+///
+/// [`RwLock`]: std::sync::RwLock
+pub struct RwGlobal<T> {
+    once: Once,
+    inner: UnsafeCell<Option<Arc<RwLock<T>>>>,
+}
+
+// See the `Sync` implementation of `Global`. `RwLock<T>` additionally requires
+// `T: Sync` as readers share the value across threads.
+unsafe impl<T> Sync for RwGlobal<T> where T: Send + Sync {}
+
+impl<T: Default + Send + Sync + 'static> RwGlobal<T> {
+    /// The initial global value.
+    // This is only meant to initialize statics, each of which gets a copy.
+    #[allow(clippy::declare_interior_mutable_const)]
+    pub const INIT: RwGlobal<T> = RwGlobal {
+        once: Once::INIT,
+        inner: UnsafeCell::new(None),
+    };
+
+    /// A non-constant version of `RwGlobal::INIT`.
+    ///
+    /// Prefer the constant value where possible.
+    pub fn new() -> Self {
+        Self::INIT
+    }
+
+    /// Ensure the inner value exists.
+    ///
+    /// This method *must* be called when accessing the inner `UnsafeCell`.
+    fn ensure_exists(&self) {
+        self.once.call_once(|| {
+            let ptr = self.inner.get();
+
+            // This is safe for the same reasons as in `Global::ensure_exists`.
+            unsafe {
+                if (*ptr).is_none() {
+                    *ptr = Some(Arc::new(RwLock::new(T::default())));
+                }
+            }
+        });
+    }
+
+    /// Get a clone of the inner reference.
+    fn get(&self) -> Arc<RwLock<T>> {
+        // Important: this *must* be called before accessing the inner pointer.
+        self.ensure_exists();
+
+        let ptr = self.inner.get() as *const Option<_>;
+
+        // This is safe as we already called `ensure_exists`.
+        let opt = unsafe { (*ptr).clone() };
+
+        opt.unwrap()
+    }
+
+    /// Obtain shared read access to the inner value.
+    ///
+    /// This method will block the current thread while a writer holds the
+    /// lock. Like `Global::lock`, this panics on native targets if a writer
+    /// panicked, see `read_checked`.
+    pub fn read(&self) -> GlobalReadLock<T> {
+        GlobalReadLock::new(self.get())
+    }
+
+    /// Obtain shared read access to the inner value, reporting a poisoned
+    /// lock.
+    ///
+    /// See `Global::lock_checked`. Otherwise this behaves exactly like `read`.
+    pub fn read_checked(&self) -> LockResult<GlobalReadLock<T>> {
+        GlobalReadLock::new_checked(self.get())
+    }
+
+    /// Obtain exclusive write access to the inner value.
+    ///
+    /// This method will block the current thread until all other handles have
+    /// been dropped. Like `read`, this panics on native targets if a writer
+    /// panicked, see `write_checked`.
+    pub fn write(&self) -> GlobalWriteLock<T> {
+        GlobalWriteLock::new(self.get())
+    }
+
+    /// Obtain exclusive write access to the inner value, reporting a poisoned
+    /// lock.
+    ///
+    /// See `Global::lock_checked`. Otherwise this behaves exactly like `write`.
+    pub fn write_checked(&self) -> LockResult<GlobalWriteLock<T>> {
+        GlobalWriteLock::new_checked(self.get())
+    }
+}
+
+impl<T: Default + Send + Sync + 'static> Default for RwGlobal<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A shared handle to some global value of type `T`.
+pub struct GlobalReadLock<T: 'static> {
+    // See `GlobalLock` for the drop order requirements.
+    lock: ManuallyDrop<Arc<RwLock<T>>>,
+    guard: ManuallyDrop<RwLockReadGuard<'static, T>>,
+}
+
+impl<T: 'static> Drop for GlobalReadLock<T> {
+    fn drop(&mut self) {
+        // Drop the guard *before* the lock.
+        unsafe {
+            ManuallyDrop::drop(&mut self.guard);
+            ManuallyDrop::drop(&mut self.lock);
+        }
+    }
+}
+
+impl<T: 'static> GlobalReadLock<T> {
+    /// Construct a new `GlobalReadLock` with a reference-counted lock.
+    fn new(lock: Arc<RwLock<T>>) -> Self {
+        match Self::new_checked(lock) {
+            Ok(lock) => lock,
+
+            // See `GlobalLock::new`.
+            #[cfg(target_arch = "wasm32")]
+            Err(e) => e.into_inner(),
+
+            #[cfg(not(target_arch = "wasm32"))]
+            Err(_) => panic!(
+                "stasis: RwGlobal<{}> is poisoned; use read_checked",
+                ::std::any::type_name::<T>()
+            ),
+        }
+    }
+
+    /// Construct a new `GlobalReadLock`, reporting whether the lock is
+    /// poisoned.
+    fn new_checked(mut lock: Arc<RwLock<T>>) -> LockResult<Self> {
+        // See `GlobalLock::acquire`.
+        unsafe {
+            // Remove the lifetime constraints on a borrow.
+            let ptr = &mut lock as *mut Arc<RwLock<T>>;
+
+            let (guard, poisoned) = match (*ptr).read() {
+                Ok(guard) => (guard, false),
+                Err(e) => (e.into_inner(), true),
+            };
+
+            let lock = GlobalReadLock {
+                guard: ManuallyDrop::new(guard),
+                lock: ManuallyDrop::new(lock),
+            };
+
+            if poisoned {
+                Err(PoisonError::new(lock))
+            } else {
+                Ok(lock)
+            }
+        }
+    }
+}
+
+impl<T: 'static> Deref for GlobalReadLock<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+/// An exclusive handle to some global value of type `T`.
+pub struct GlobalWriteLock<T: 'static> {
+    // See `GlobalLock` for the drop order requirements.
+    lock: ManuallyDrop<Arc<RwLock<T>>>,
+    guard: ManuallyDrop<RwLockWriteGuard<'static, T>>,
+}
+
+impl<T: 'static> Drop for GlobalWriteLock<T> {
+    fn drop(&mut self) {
+        // Drop the guard *before* the lock.
+        unsafe {
+            ManuallyDrop::drop(&mut self.guard);
+            ManuallyDrop::drop(&mut self.lock);
+        }
+    }
+}
+
+impl<T: 'static> GlobalWriteLock<T> {
+    /// Construct a new `GlobalWriteLock` with a reference-counted lock.
+    fn new(lock: Arc<RwLock<T>>) -> Self {
+        match Self::new_checked(lock) {
+            Ok(lock) => lock,
+
+            // See `GlobalLock::new`.
+            #[cfg(target_arch = "wasm32")]
+            Err(e) => e.into_inner(),
+
+            #[cfg(not(target_arch = "wasm32"))]
+            Err(_) => panic!(
+                "stasis: RwGlobal<{}> is poisoned; use write_checked",
+                ::std::any::type_name::<T>()
+            ),
+        }
+    }
+
+    /// Construct a new `GlobalWriteLock`, reporting whether the lock is
+    /// poisoned.
+    fn new_checked(mut lock: Arc<RwLock<T>>) -> LockResult<Self> {
+        // See `GlobalLock::acquire`.
+        unsafe {
+            // Remove the lifetime constraints on a borrow.
+            let ptr = &mut lock as *mut Arc<RwLock<T>>;
+
+            let (guard, poisoned) = match (*ptr).write() {
+                Ok(guard) => (guard, false),
+                Err(e) => (e.into_inner(), true),
+            };
+
+            let lock = GlobalWriteLock {
+                guard: ManuallyDrop::new(guard),
+                lock: ManuallyDrop::new(lock),
+            };
+
+            if poisoned {
+                Err(PoisonError::new(lock))
+            } else {
+                Ok(lock)
+            }
+        }
+    }
+}
+
+impl<T: 'static> Deref for GlobalWriteLock<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: 'static> DerefMut for GlobalWriteLock<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// The boxed future which initializes an `AsyncGlobal`.
+pub type AsyncInit<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// The state of an `AsyncGlobal`.
+#[derive(Default)]
+enum AsyncState<T> {
+    #[default]
+    Empty,
+    /// The initializer is running, and these tasks are waiting on it.
+    Running(Vec<Waker>),
+    Ready(Arc<T>),
+}
+
+/// A global value which is initialized asynchronously.
+///
+/// This is the async counterpart of [`Global`], for values which need async
+/// setup such as fetching a config. The initializer is spawned on the `v03`
+/// executor by the first call to [`AsyncGlobal::get`], and runs exactly once.
+/// Every task which calls `get` meanwhile waits for that same run.
+///
+/// ```rust,no_run
+/// # extern crate stasis;
+/// # use std::future;
+/// # use stasis::global::AsyncGlobal;
+/// # fn main() {
+/// static ANSWER: AsyncGlobal<u32> = AsyncGlobal::new(|| {
+///     Box::pin(future::ready(42))
+/// });
+///
+/// // Within an async function:
+/// // let answer = ANSWER.get().await;
+/// # }
+/// ```
+pub struct AsyncGlobal<T> {
+    init: fn() -> AsyncInit<T>,
+    state: Global<AsyncState<T>>,
+}
+
+impl<T: Send + Sync + 'static> AsyncGlobal<T> {
+    /// Create a global which is initialized by the future `init` returns.
+    pub const fn new(init: fn() -> AsyncInit<T>) -> Self {
+        AsyncGlobal {
+            init,
+            state: Global::INIT,
+        }
+    }
+
+    /// Wait for the value, starting the initializer if this is the first call.
+    pub fn get(&'static self) -> AsyncGlobalGet<T> {
+        AsyncGlobalGet { global: self }
+    }
+
+    /// The value, if it has been initialized already.
+    ///
+    /// This never starts the initializer.
+    pub fn try_get(&self) -> Option<Arc<T>> {
+        match *self.state.lock() {
+            AsyncState::Ready(ref t) => Some(t.clone()),
+            _ => None,
+        }
+    }
+
+    /// Store the value and wake every waiting task.
+    fn finish(&self, t: T) {
+        let previous = mem::replace(
+            &mut *self.state.lock(),
+            AsyncState::Ready(Arc::new(t)),
+        );
+
+        // Wakers are run without the lock held, as they may poll right away.
+        if let AsyncState::Running(waiters) = previous {
+            for waker in waiters {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// A future which resolves to the value of an `AsyncGlobal`.
+///
+/// This is created by [`AsyncGlobal::get`].
+pub struct AsyncGlobalGet<T: 'static> {
+    global: &'static AsyncGlobal<T>,
+}
+
+impl<T: Send + Sync + 'static> Future for AsyncGlobalGet<T> {
+    type Output = Arc<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Arc<T>> {
+        let global = self.global;
+
+        {
+            let mut state = global.state.lock();
+
+            match *state {
+                AsyncState::Ready(ref t) => return Poll::Ready(t.clone()),
+
+                AsyncState::Running(ref mut waiters) => {
+                    if !waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                        waiters.push(cx.waker().clone());
+                    }
+
+                    return Poll::Pending;
+                }
+
+                AsyncState::Empty => {
+                    *state = AsyncState::Running(vec![cx.waker().clone()]);
+                }
+            }
+        }
+
+        v03::spawn(Initialize {
+            global,
+            f: (global.init)(),
+        });
+
+        // The initializer may have finished right away, in which case there is
+        // no need to wait to be polled again.
+        match global.try_get() {
+            Some(t) => Poll::Ready(t),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Runs the initializer of an `AsyncGlobal` on the executor.
+struct Initialize<T: 'static> {
+    global: &'static AsyncGlobal<T>,
+    f: AsyncInit<T>,
+}
+
+impl<T: Send + Sync + 'static> Future for Initialize<T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        match self.f.as_mut().poll(cx) {
+            Poll::Ready(t) => {
+                self.global.finish(t);
+                Poll::Ready(())
+            }
+
+            Poll::Pending => Poll::Pending,
+        }
     }
 }
 
@@ -148,11 +839,90 @@ impl<T: 'static> DerefMut for GlobalLock<T> {
 mod test {
     use std::{
         thread,
-        sync::mpsc,
+        sync::{
+            mpsc,
+            atomic::{AtomicUsize, Ordering},
+        },
         time::Duration,
     };
 
-    use super::Global;
+    use super::{AsyncGlobal, AsyncGlobalGet, Global, RwGlobal};
+
+    #[test]
+    fn lock_checked_reports_poison() {
+        static POISONED: Global<Vec<u32>> = Global::INIT;
+
+        POISONED.lock().push(1);
+        assert!(POISONED.lock_checked().is_ok());
+
+        let panicked = thread::spawn(|| {
+            let mut lock = POISONED.lock();
+            lock.push(2);
+
+            panic!("poison the global");
+        }).join();
+
+        assert!(panicked.is_err());
+
+        // The lock is still handed out, along with the value left behind.
+        match POISONED.lock_checked() {
+            Ok(_) => panic!("the global should be poisoned"),
+            Err(e) => assert_eq!(*e.into_inner(), vec![1, 2]),
+        }
+
+        // The error released the lock again, and the poison stays.
+        assert!(POISONED.lock_checked().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "is poisoned; use lock_checked")]
+    fn try_lock_poisoned_panics() {
+        static POISONED: Global<u32> = Global::INIT;
+
+        let _ = thread::spawn(|| {
+            let _lock = POISONED.lock();
+            panic!("poison the global");
+        }).join();
+
+        POISONED.try_lock();
+    }
+
+    #[test]
+    fn rw_checked_reports_poison() {
+        static POISONED: RwGlobal<Vec<u32>> = RwGlobal::INIT;
+
+        POISONED.write().push(1);
+        assert!(POISONED.read_checked().is_ok());
+
+        let panicked = thread::spawn(|| {
+            POISONED.write().push(2);
+
+            let _lock = POISONED.write();
+            panic!("poison the global");
+        }).join();
+
+        assert!(panicked.is_err());
+
+        match POISONED.read_checked() {
+            Ok(_) => panic!("the global should be poisoned"),
+            Err(e) => assert_eq!(*e.into_inner(), vec![1, 2]),
+        }
+
+        assert!(POISONED.write_checked().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "is poisoned; use read_checked")]
+    fn rw_read_poisoned_panics() {
+        static POISONED: RwGlobal<u32> = RwGlobal::INIT;
+
+        let _ = thread::spawn(|| {
+            let _lock = POISONED.write();
+            panic!("poison the global");
+        }).join();
+
+        POISONED.read();
+    }
 
     #[test]
     fn no_race_condition() {
@@ -208,4 +978,244 @@ mod test {
 
         assert_eq!(*NUM.lock(), 2);
     }
+
+    // Readers must not block each other.
+    #[test]
+    fn rw_concurrent_reads() {
+        static NUM: RwGlobal<i32> = RwGlobal::INIT;
+
+        *NUM.write() = 5;
+
+        let (tx, rx) = mpsc::channel();
+
+        // Hold a read handle for the rest of the test.
+        let read = NUM.read();
+
+        let t = thread::spawn(move || {
+            // This would deadlock if reads were exclusive.
+            tx.send(*NUM.read()).unwrap();
+        });
+
+        assert_eq!(rx.recv().unwrap(), 5);
+        assert_eq!(*read, 5);
+
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn rw_no_race_condition() {
+        static NUM: RwGlobal<i32> = RwGlobal::INIT;
+
+        let mut v = Vec::new();
+
+        for _ in 0..100 {
+            v.push(thread::spawn(|| {
+                for _ in 0..100 {
+                    *NUM.write() += 1;
+                    assert!(*NUM.read() > 0);
+                }
+            }));
+        }
+
+        for thread in v {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*NUM.read(), 10_000);
+    }
+
+    #[test]
+    fn try_lock_while_held() {
+        static NUM: Global<i32> = Global::INIT;
+
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        let t = thread::spawn(move || {
+            let mut lock = NUM.lock();
+
+            locked_tx.send(()).unwrap();
+
+            // Hold the lock until the main thread is done checking.
+            let () = done_rx.recv().unwrap();
+
+            *lock += 1;
+        });
+
+        let () = locked_rx.recv().unwrap();
+
+        assert!(NUM.try_lock().is_none());
+
+        done_tx.send(()).unwrap();
+        t.join().unwrap();
+
+        let mut lock = NUM.try_lock().unwrap();
+        *lock += 1;
+
+        // Recursive locking does not block.
+        assert!(NUM.try_lock().is_none());
+
+        drop(lock);
+
+        assert_eq!(*NUM.lock(), 2);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "recursive Global::lock on i32; use try_lock")]
+    fn recursive_lock_panics() {
+        static NUM: Global<i32> = Global::INIT;
+
+        let _outer = NUM.lock();
+        *NUM.lock() += 1;
+    }
+
+    #[test]
+    fn set_and_take() {
+        // No `Default` implementation.
+        struct Config(&'static str);
+
+        static CONFIG: Global<Config> = Global::INIT;
+        static FRAME: Global<Vec<u32>> = Global::INIT;
+
+        assert!(!CONFIG.is_initialized());
+        CONFIG.set(Config("initial"));
+        assert!(CONFIG.is_initialized());
+        assert_eq!(CONFIG.get_or_init(|| unreachable!()).0, "initial");
+
+        CONFIG.set(Config("reloaded"));
+        assert_eq!(CONFIG.get_or_init(|| unreachable!()).0, "reloaded");
+
+        FRAME.lock().extend(&[1, 2]);
+        assert_eq!(FRAME.take(), vec![1, 2]);
+        assert!(FRAME.lock().is_empty());
+    }
+
+    #[test]
+    fn get_or_init_runs_once() {
+        // No `Default` implementation.
+        struct Counter(i32);
+
+        static NUM: Global<Counter> = Global::INIT;
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let mut v = Vec::new();
+
+        for _ in 0..100 {
+            v.push(thread::spawn(|| {
+                let mut lock = NUM.get_or_init(|| {
+                    CALLS.fetch_add(1, Ordering::SeqCst);
+                    Counter(10)
+                });
+
+                lock.0 += 1;
+            }));
+        }
+
+        for thread in v {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(NUM.get_or_init(|| unreachable!()).0, 110);
+    }
+
+    #[test]
+    fn on_init_runs_once() {
+        static NUM: Global<i32> = Global::INIT;
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        assert!(NUM.on_init(|| {
+            // The hook is free to lock the global it is attached to.
+            assert_eq!(*NUM.lock(), 0);
+            CALLS.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        assert!(!NUM.is_initialized());
+
+        let mut v = Vec::new();
+
+        for _ in 0..100 {
+            v.push(thread::spawn(|| *NUM.lock() += 1));
+        }
+
+        for thread in v {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+        // Too late, the value already exists.
+        assert!(!NUM.on_init(|| panic!("Hook ran after initialization")));
+    }
+
+    #[test]
+    fn async_global_initializes_once() {
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::sync::{Arc, Mutex};
+        use std::sync::atomic::AtomicBool;
+        use std::task::{Context, Poll, Waker};
+
+        use futures::v03;
+
+        static INITS: AtomicUsize = AtomicUsize::new(0);
+        static OPEN: AtomicBool = AtomicBool::new(false);
+        static GATE_WAKER: Global<Option<Waker>> = Global::INIT;
+
+        /// Pending until `OPEN` is set.
+        struct Gate;
+
+        impl Future for Gate {
+            type Output = u32;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+                if OPEN.load(Ordering::SeqCst) {
+                    Poll::Ready(7)
+                } else {
+                    *GATE_WAKER.lock() = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+
+        static VALUE: AsyncGlobal<u32> = AsyncGlobal::new(|| {
+            INITS.fetch_add(1, Ordering::SeqCst);
+            Box::pin(Gate)
+        });
+
+        /// Records the value once it is available.
+        struct Record(AsyncGlobalGet<u32>, Arc<Mutex<Vec<u32>>>);
+
+        impl Future for Record {
+            type Output = ();
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+                match Pin::new(&mut self.0).poll(cx) {
+                    Poll::Ready(n) => {
+                        self.1.lock().unwrap().push(*n);
+                        Poll::Ready(())
+                    }
+
+                    Poll::Pending => Poll::Pending,
+                }
+            }
+        }
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        v03::spawn(Record(VALUE.get(), seen.clone()));
+        v03::spawn(Record(VALUE.get(), seen.clone()));
+
+        assert!(seen.lock().unwrap().is_empty());
+        assert!(VALUE.try_get().is_none());
+
+        OPEN.store(true, Ordering::SeqCst);
+        let waker = GATE_WAKER.lock().take().unwrap();
+        waker.wake();
+
+        assert_eq!(*seen.lock().unwrap(), vec![7, 7]);
+        assert_eq!(VALUE.try_get().map(|n| *n), Some(7));
+        assert_eq!(INITS.load(Ordering::SeqCst), 1);
+    }
 }
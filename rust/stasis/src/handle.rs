@@ -0,0 +1,114 @@
+//! References to live JavaScript values.
+//!
+//! Values normally cross the boundary as JSON, so an object such as a DOM node
+//! or a WebGL context cannot be kept between calls. Instead, a function can
+//! return `this.handle(value)`, which stores the value in the runtime and
+//! hands Rust a `Handle` to it. Passing the `Handle` to a later call gives the
+//! function the original value back.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # use stasis::{Module, handle::Handle};
+//! # fn main() {
+//! let m = Module::new();
+//! m.register("createCanvas", r#"
+//!     function() {
+//!         return this.handle(document.createElement("canvas"));
+//!     }
+//! "#);
+//! m.register("resize", r#"
+//!     function(canvas, width, height) {
+//!         canvas.width = width;
+//!         canvas.height = height;
+//!     }
+//! "#);
+//!
+//! let canvas: Handle = m.call("createCanvas", ());
+//! let () = m.call("resize", (&canvas, 640, 480));
+//!
+//! // The runtime releases the canvas once the handle is dropped.
+//! drop(canvas);
+//! # }
+//! ```
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use stasis_internals::outgoing;
+
+/// The wire representation of a `Handle`.
+#[derive(Serialize, Deserialize)]
+struct RawHandle {
+    #[serde(rename = "$handle")]
+    id: u32,
+}
+
+/// An opaque reference to a JavaScript value held by the runtime.
+///
+/// The value is released when the handle is dropped, or explicitly with
+/// `release`. A handle is serialized by reference, so pass `&handle` to keep
+/// using it after a call.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Handle {
+    id: u32,
+}
+
+impl Handle {
+    /// The id of the value within the runtime.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Release the value.
+    ///
+    /// This is the same as dropping the handle.
+    pub fn release(self) {}
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        outgoing::release_handle(self.id);
+    }
+}
+
+impl Serialize for Handle {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        RawHandle { id: self.id }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Handle {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let RawHandle { id } = RawHandle::deserialize(deserializer)?;
+
+        Ok(Handle { id })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json;
+    use stasis_internals::mock;
+
+    use super::Handle;
+
+    #[test]
+    fn wire_format_and_release() {
+        let handle: Handle = serde_json::from_str(r#"{"$handle":9001}"#)
+            .unwrap();
+
+        assert_eq!(handle.id(), 9001);
+        assert_eq!(
+            serde_json::to_string(&handle).unwrap(),
+            r#"{"$handle":9001}"#
+        );
+        assert!(!mock::is_released(9001));
+
+        handle.release();
+        assert!(mock::is_released(9001));
+    }
+}
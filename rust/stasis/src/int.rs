@@ -0,0 +1,154 @@
+//! 64-bit integers which survive JavaScript.
+//!
+//! JavaScript numbers are doubles, so integers past 2^53 lose precision on the
+//! way through. `Int64` and `UInt64` are sent as decimal strings instead, which
+//! JavaScript can keep as-is or turn into a `BigInt`.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # use stasis::{Module, Int64};
+//! # fn main() {
+//! let m = Module::new();
+//! m.register("echo", r#"
+//!     function(id) {
+//!         // `BigInt(id)` gives a number to compute with.
+//!         return id;
+//!     }
+//! "#);
+//!
+//! let id = Int64(i64::MAX);
+//! let back: Int64 = m.call("echo", id);
+//!
+//! assert_eq!(back, id);
+//! # }
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, Visitor};
+
+/// An `i64` sent as a decimal string.
+///
+/// Deserializing also accepts a plain number, as long as it is an integer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Int64(pub i64);
+
+/// A `u64` sent as a decimal string.
+///
+/// Deserializing also accepts a plain number, as long as it is an integer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UInt64(pub u64);
+
+macro_rules! string_int {
+    ($name:ident, $int:ty, $expecting:expr) => {
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                serializer.collect_str(&self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                struct IntVisitor;
+
+                impl<'de> Visitor<'de> for IntVisitor {
+                    type Value = $name;
+
+                    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                        f.write_str($expecting)
+                    }
+
+                    fn visit_str<E>(self, s: &str) -> Result<$name, E>
+                    where
+                        E: de::Error,
+                    {
+                        <$int>::from_str(s).map($name).map_err(E::custom)
+                    }
+
+                    fn visit_i64<E>(self, n: i64) -> Result<$name, E>
+                    where
+                        E: de::Error,
+                    {
+                        <$int>::try_from(n)
+                            .map($name)
+                            .map_err(|_| E::custom("integer out of range"))
+                    }
+
+                    fn visit_u64<E>(self, n: u64) -> Result<$name, E>
+                    where
+                        E: de::Error,
+                    {
+                        <$int>::try_from(n)
+                            .map($name)
+                            .map_err(|_| E::custom("integer out of range"))
+                    }
+                }
+
+                deserializer.deserialize_any(IntVisitor)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl From<$int> for $name {
+            fn from(n: $int) -> Self {
+                $name(n)
+            }
+        }
+
+        impl From<$name> for $int {
+            fn from(n: $name) -> Self {
+                n.0
+            }
+        }
+    };
+}
+
+string_int!(Int64, i64, "an i64 as a string or number");
+string_int!(UInt64, u64, "a u64 as a string or number");
+
+#[cfg(test)]
+mod test {
+    use serde_json::{self, json};
+
+    use super::{Int64, UInt64};
+
+    #[test]
+    fn exact_round_trip() {
+        let max = Int64(i64::MAX);
+        let min = Int64(i64::MIN);
+        let unsigned = UInt64(u64::MAX);
+
+        assert_eq!(
+            serde_json::to_value(max).unwrap(),
+            json!("9223372036854775807")
+        );
+
+        for &n in &[max, min] {
+            let json = serde_json::to_string(&n).unwrap();
+            assert_eq!(serde_json::from_str::<Int64>(&json).unwrap(), n);
+        }
+
+        let json = serde_json::to_string(&unsigned).unwrap();
+        assert_eq!(serde_json::from_str::<UInt64>(&json).unwrap(), unsigned);
+
+        // Small values may come back as plain numbers.
+        let small: Int64 = serde_json::from_value(json!(-5)).unwrap();
+        assert_eq!(small, Int64(-5));
+        assert!(serde_json::from_value::<UInt64>(json!(-5)).is_err());
+        assert!(serde_json::from_value::<Int64>(json!(1.5)).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+//! Support for the `js!` macro.
+//!
+//! Each distinct snippet is registered once on a hidden module, and then
+//! called like any other function.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use Module;
+use global::Global;
+
+/// The module snippets are registered on, with the name of each snippet.
+#[derive(Default)]
+struct Snippets {
+    module: Option<Module>,
+    names: HashMap<String, String>,
+}
+
+static SNIPPETS: Global<Snippets> = Global::INIT;
+
+/// Run a snippet built by `js!`, with `args` as `arguments`.
+#[doc(hidden)]
+pub fn run<R>(code: &str, args: Vec<Value>) -> R
+where
+    R: for<'a> Deserialize<'a>,
+{
+    let (id, name) = {
+        let mut snippets = SNIPPETS.lock();
+        let snippets = &mut *snippets;
+
+        let module = snippets.module.get_or_insert_with(Module::new);
+        let id = module.id();
+
+        let next = snippets.names.len();
+        let name = snippets.names
+            .entry(code.to_owned())
+            .or_insert_with(|| {
+                let name = format!("js{}", next);
+                let f = format!("function() {{ {} }}", code);
+
+                module.register(&name, &f);
+                name
+            })
+            .clone();
+
+        (id, name)
+    };
+
+    // The lock is released, so the snippet is free to call back into Rust and
+    // run other snippets.
+    ::stasis_internals::outgoing::call(id, &name, args)
+}
+
+/// Append a piece of code, separated from the previous one by a space where
+/// one may be needed.
+#[doc(hidden)]
+pub fn push(code: &mut String, piece: &str) {
+    if piece.is_empty() {
+        return;
+    }
+
+    let after_open = code.ends_with('(') || code.ends_with('[');
+    let before_close = piece.starts_with(|c| "()[];,".contains(c));
+
+    if !code.is_empty() && !after_open && !before_close {
+        code.push(' ');
+    }
+
+    code.push_str(piece);
+}
+
+/// Serialize a value interpolated into a snippet.
+#[doc(hidden)]
+pub fn arg<T>(t: &T) -> Value
+where
+    T: ?Sized + ::serde::Serialize,
+{
+    match ::serde_json::to_value(t) {
+        Ok(value) => value,
+        Err(e) => panic!("Failed to serialize js! argument: {}", e),
+    }
+}
+
+
+/// Every registered snippet, with its name.
+#[cfg(test)]
+pub(crate) fn names() -> Vec<(String, String)> {
+    SNIPPETS
+        .lock()
+        .names
+        .iter()
+        .map(|(code, name)| (code.clone(), name.clone()))
+        .collect()
+}
@@ -1,11 +1,12 @@
-/// This crate is recommended as the way to implement module memoization.
-pub extern crate global;
-
 extern crate futures_v01x;
 extern crate futures_v02x;
+#[cfg_attr(test, macro_use)] extern crate log as log_crate;
 extern crate once_nonstatic;
 extern crate serde;
+extern crate serde_json;
 #[macro_use] extern crate serde_derive;
+#[cfg(feature = "bindings")]
+extern crate stasis_macros;
 
 /// This must be public to be accessed via the `stasis!` macro. There is a
 /// `#[doc(hidden)]` attribute on here as this should never be used by a user of
@@ -13,28 +14,269 @@ extern crate serde;
 #[doc(hidden)]
 pub extern crate stasis_internals;
 
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "browser")]
+use std::fmt::Debug;
+
 use global::Global;
 use serde::{Serialize, Deserialize};
+use stasis_internals::incoming;
+
+pub use stasis_internals::Bytes;
+pub use stasis_internals::outgoing::ScopedReturn;
+pub use int::{Int64, UInt64};
+pub use undefined::Undefined;
+pub use futures::yield_now;
+pub use stasis_internals::wire;
+#[cfg(feature = "mock")]
+pub use stasis_internals::mock;
+#[cfg(feature = "bindings")]
+pub use stasis_macros::bindings;
 
 pub mod callbacks;
+#[cfg(feature = "browser")]
+pub mod clipboard;
+#[cfg(feature = "browser")]
+pub mod dom;
+#[cfg(feature = "browser")]
+pub mod fetch;
+#[cfg(feature = "browser")]
+pub mod geo;
+pub mod global;
+pub mod handle;
+pub mod int;
+#[doc(hidden)]
+pub mod js;
+pub mod log;
+#[cfg(feature = "browser")]
+pub mod navigator;
+#[cfg(feature = "browser")]
+pub mod net;
+#[cfg(feature = "browser")]
+pub mod raf;
+#[cfg(feature = "browser")]
+pub mod storage;
+pub mod time;
+#[cfg(feature = "tutorial")]
 pub mod tutorial;
+pub mod undefined;
 pub mod futures;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 
 /// A unique module instance.
 #[derive(Clone, Copy)]
 pub struct Module {
     id: u32,
+    namespace: Option<&'static str>,
 }
 
 impl Module {
     pub fn new() -> Self {
         Self {
             id: stasis_internals::outgoing::create_module(),
+            namespace: None,
+        }
+    }
+
+    /// Create a module whose function names are all prefixed with `prefix`.
+    ///
+    /// Registering `"random"` registers `"prefix.random"`, and calling
+    /// `"random"` calls it back. This keeps function tables, and the logs of
+    /// the `trace` feature, organized in larger programs. JavaScript sees the
+    /// full names, so registered code looks up others as
+    /// `this.functions["prefix.random"]`.
+    ///
+    /// Each distinct prefix is allocated once for the life of the program.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::with_namespace("dice");
+    /// m.register("random", "function() { return Math.random() }");
+    ///
+    /// let n: f32 = m.call("random", ());
+    /// assert_eq!(m.qualify("random"), "dice.random");
+    /// # }
+    /// ```
+    pub fn with_namespace(prefix: &str) -> Self {
+        Self {
+            namespace: Some(intern(prefix)),
+            ..Self::new()
+        }
+    }
+
+    /// The namespace given to `with_namespace`, if any.
+    pub fn namespace(&self) -> Option<&'static str> {
+        self.namespace
+    }
+
+    /// The full name JavaScript sees for `name`, including the namespace.
+    pub fn qualify<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match self.namespace {
+            Some(prefix) => Cow::Owned(format!("{}.{}", prefix, name)),
+            None => Cow::Borrowed(name),
         }
     }
 
+    /// Qualify `name` and make the call `f` with it.
+    ///
+    /// With the `metrics` feature enabled, this records the round-trip time.
+    fn timed<F, R>(&self, name: &str, f: F) -> R
+    where
+        F: FnOnce(&str) -> R,
+    {
+        let name = self.qualify(name);
+
+        #[cfg(feature = "metrics")]
+        let start = metrics::now();
+
+        let ret = f(&name);
+
+        #[cfg(feature = "metrics")]
+        metrics::record(&name, start);
+
+        ret
+    }
+
+    /// The same module, without its namespace.
+    pub(crate) fn without_namespace(&self) -> Module {
+        Module { id: self.id, namespace: None }
+    }
+
+    /// Register a JavaScript function on this module.
+    ///
+    /// `code` must evaluate to a function. When called, its `this` context
+    /// holds:
+    ///
+    /// - `data`: an object shared by every function of this module.
+    /// - `functions`: the functions registered on this module.
+    /// - `callbacks`: the callbacks registered with `register_callback`.
+    /// - `handler`: turns a `HandlerId` into a function.
+    /// - `handle`: stores a value to return as a `handle::Handle`.
+    /// - `moduleId`: the id of this module, as returned by `Module::id`.
+    /// - `cancelled`: see `call_cancellable`. Always `false` for other calls.
+    ///
+    /// Functions registered with `register_with_memory` also see `memory`.
     pub fn register(&self, name: &str, code: &str) {
-        stasis_internals::outgoing::register_fn(self.id, name, code);
+        let name = self.qualify(name);
+
+        stasis_internals::outgoing::register_fn(self.id, &name, code);
+    }
+
+    /// Register a JavaScript function which returns a `Promise`, such as an
+    /// `async function`.
+    ///
+    /// This is `register` for functions which are only ever called with
+    /// `call_promise`, which awaits the promise and settles the returned
+    /// future. Calling the function with `call` or any other synchronous call
+    /// is an error, as the promise itself could not be sent back.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register_async("fetchText", r#"
+    ///     async function(url) {
+    ///         var response = await fetch(url);
+    ///         return await response.text();
+    ///     }
+    /// "#);
+    ///
+    /// let text = m.call_promise::<_, String>("fetchText", "/index.html");
+    /// # }
+    /// ```
+    pub fn register_async(&self, name: &str, code: &str) {
+        let name = self.qualify(name);
+
+        stasis_internals::outgoing::register_async_fn(self.id, &name, code);
+    }
+
+    /// Register a JavaScript function which reads linear memory directly.
+    ///
+    /// This is `register` with one addition: `this.memory` is a `Uint8Array`
+    /// over the whole linear memory of the binary. Passing a pointer and a
+    /// length lets JavaScript read Rust data in place, without copying it
+    /// through the usual encoding. This is meant for binary-heavy bindings
+    /// where that copy matters.
+    ///
+    /// Nothing checks what JavaScript does with the memory, so the usual
+    /// rules of raw pointers apply on the Rust side. The data must stay alive
+    /// and unmoved for the whole call, and must not be written to by Rust in
+    /// the meantime. Growing the memory detaches any view taken before, so
+    /// read `this.memory` again after anything that may allocate, such as a
+    /// callback into Rust. Never keep a view after the function returns.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register_with_memory("upload", r#"
+    ///     function(ptr, len) {
+    ///         var bytes = this.memory.subarray(ptr, ptr + len);
+    ///
+    ///         // `bytes` is only valid during this call, so it is copied.
+    ///         gl.bufferData(gl.ARRAY_BUFFER, bytes, gl.STATIC_DRAW);
+    ///     }
+    /// "#);
+    ///
+    /// let vertices = vec![0u8; 4096];
+    /// let () = m.call("upload", (vertices.as_ptr() as usize, vertices.len()));
+    /// # }
+    /// ```
+    pub fn register_with_memory(&self, name: &str, code: &str) {
+        let name = self.qualify(name);
+
+        stasis_internals::outgoing::register_memory_fn(self.id, &name, code);
+    }
+
+    /// Register many JavaScript functions at once.
+    ///
+    /// This is equivalent to calling `register` for each `(name, code)` pair
+    /// in order, but crosses into JavaScript only once. A function which fails
+    /// to compile is reported to the console without affecting the others.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register_batch(&[
+    ///     ("add", "function(a, b) { return a + b }"),
+    ///     ("sub", "function(a, b) { return a - b }"),
+    /// ]);
+    ///
+    /// let n: i32 = m.call("add", (1, 2));
+    /// # }
+    /// ```
+    pub fn register_batch(&self, entries: &[(&str, &str)]) {
+        if self.namespace.is_none() {
+            return stasis_internals::outgoing::register_fns(self.id, entries);
+        }
+
+        let names: Vec<_> = entries
+            .iter()
+            .map(|&(name, _)| self.qualify(name))
+            .collect();
+        let entries: Vec<_> = names
+            .iter()
+            .zip(entries)
+            .map(|(name, &(_, code))| (&**name, code))
+            .collect();
+
+        stasis_internals::outgoing::register_fns(self.id, &entries);
+    }
+
+    /// The id of this module.
+    ///
+    /// Registered functions see the same value as `this.moduleId`.
+    pub fn id(&self) -> u32 {
+        self.id
     }
 
     pub fn register_callback<F, A, R>(&self, name: &str, f: F)
@@ -43,18 +285,711 @@ impl Module {
         A: for<'a> Deserialize<'a>,
         R: Serialize,
     {
-        stasis_internals::outgoing::register_callback(self.id, name, f);
+        stasis_internals::outgoing::register_callback(
+            self.id,
+            &self.qualify(name),
+            f,
+        );
+    }
+
+    /// Register a callback which mutates its captured state.
+    ///
+    /// Unlike `register_callback`, the closure may be `FnMut` and does not need
+    /// to be `Sync`, so state such as a `Cell` does not need to be wrapped in
+    /// a `Mutex` or a `Global`. The callback must not call itself, such as
+    /// through a JavaScript function which calls the callback again. This
+    /// panics with a clear message instead of deadlocking.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// let mut clicks = 0;
+    /// m.register_callback_mut("click", move |()| {
+    ///     clicks += 1;
+    ///     clicks
+    /// });
+    /// m.register("init", r#"
+    ///     function() {
+    ///         var click = this.callbacks.click;
+    ///         document.body.onclick = function() {
+    ///             console.log("Clicks:", click());
+    ///         };
+    ///     }
+    /// "#);
+    /// # }
+    /// ```
+    pub fn register_callback_mut<F, A, R>(&self, name: &str, f: F)
+    where
+        F: 'static + Send + FnMut(A) -> R,
+        A: for<'a> Deserialize<'a>,
+        R: Serialize,
+    {
+        stasis_internals::outgoing::register_callback_mut(
+            self.id,
+            &self.qualify(name),
+            f,
+        );
+    }
+
+    /// Register a callback which rejects arguments of the wrong shape.
+    ///
+    /// With `register_callback`, an argument which fails to deserialize is a
+    /// fatal panic. Here the JavaScript caller receives a thrown exception
+    /// instead, and the callback is not run. Use this when the arguments come
+    /// from code you do not control.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register_callback_fallible("square", |n: u32| n * n);
+    /// m.register("check", r#"
+    ///     function() {
+    ///         try {
+    ///             this.callbacks.square("not a number");
+    ///         } catch (e) {
+    ///             console.log("Rejected:", e.message);
+    ///         }
+    ///     }
+    /// "#);
+    /// # }
+    /// ```
+    pub fn register_callback_fallible<F, A, R>(&self, name: &str, f: F)
+    where
+        F: 'static + Send + Sync + Fn(A) -> R,
+        A: for<'a> Deserialize<'a>,
+        R: Serialize,
+    {
+        stasis_internals::outgoing::register_callback_fallible(
+            self.id,
+            &self.qualify(name),
+            f,
+        );
+    }
+
+    /// Register a callback which JavaScript calls with two arguments.
+    ///
+    /// The runtime passes several arguments to a callback as an array. This
+    /// unpacks them, so the arity is part of the closure's signature rather
+    /// than a tuple kept in sync by hand.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register_callback2("resize", |width: u32, height: u32| {
+    ///     width * height
+    /// });
+    /// m.register("init", r#"
+    ///     function() {
+    ///         var resize = this.callbacks.resize;
+    ///         window.onresize = function() {
+    ///             resize(window.innerWidth, window.innerHeight);
+    ///         };
+    ///     }
+    /// "#);
+    /// # }
+    /// ```
+    pub fn register_callback2<F, A, B, R>(&self, name: &str, f: F)
+    where
+        F: 'static + Send + Sync + Fn(A, B) -> R,
+        A: for<'a> Deserialize<'a>,
+        B: for<'a> Deserialize<'a>,
+        R: Serialize,
+    {
+        self.register_callback(name, move |(a, b)| f(a, b));
+    }
+
+    /// Register a callback which JavaScript calls with three arguments.
+    ///
+    /// See `register_callback2`.
+    pub fn register_callback3<F, A, B, C, R>(&self, name: &str, f: F)
+    where
+        F: 'static + Send + Sync + Fn(A, B, C) -> R,
+        A: for<'a> Deserialize<'a>,
+        B: for<'a> Deserialize<'a>,
+        C: for<'a> Deserialize<'a>,
+        R: Serialize,
+    {
+        self.register_callback(name, move |(a, b, c)| f(a, b, c));
+    }
+
+    /// Register a callback using a custom wire format.
+    ///
+    /// See `call_with`.
+    pub fn register_callback_with<W, F, A, R>(&self, name: &str, f: F)
+    where
+        W: wire::WireFormat,
+        F: 'static + Send + Sync + Fn(A) -> R,
+        A: for<'a> Deserialize<'a>,
+        R: Serialize,
+    {
+        stasis_internals::outgoing::register_callback_with::<W, F, A, R>(
+            self.id,
+            &self.qualify(name),
+            f,
+        );
+    }
+
+    /// Attach a long-lived handler without binding it to a name.
+    ///
+    /// JavaScript turns the handler's id into a function with
+    /// `this.handler(id)`. The handler is detached once the returned handle is
+    /// dropped, which makes this suitable for event listeners that come and
+    /// go.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register("onClick", r#"
+    ///     function(id) {
+    ///         document.body.onclick = this.handler(id);
+    ///     }
+    /// "#);
+    ///
+    /// let mut clicks = 0;
+    /// let handle = m.attach_handler(move |()| {
+    ///     clicks += 1;
+    /// });
+    ///
+    /// let () = m.call("onClick", handle.id());
+    /// # }
+    /// ```
+    pub fn attach_handler<F, A>(&self, f: F) -> callbacks::HandlerHandle
+    where
+        F: 'static + Send + FnMut(A),
+        A: for<'a> Deserialize<'a>,
+    {
+        let id = stasis_internals::internal_callbacks::attach(f);
+
+        callbacks::HandlerHandle::new(id)
     }
 
+    /// Call a registered JavaScript function.
+    ///
+    /// A tuple of N values is passed as N positional arguments, so
+    /// `m.call("f", (1, "a", true))` calls `f(1, "a", true)`. `()` calls the
+    /// function without arguments, and any other value is passed as the only
+    /// argument. Sequences such as `Vec` are spread just like tuples, so wrap
+    /// one in a 1-tuple to pass it as a single array.
+    ///
+    /// Recursive argument types, such as trees built from `Option<Box<T>>`,
+    /// are supported up to a nesting depth of 128. Anything deeper panics with
+    /// a serialization error rather than overflowing the stack.
+    ///
+    /// `None` arguments arrive as `null`. Use `Undefined` where the function
+    /// needs `undefined` instead.
+    ///
+    /// A function without a `return` statement returns `undefined`, which the
+    /// runtime hands back without encoding anything. Reading it as `()` never
+    /// goes through `null`. Use `call_opt` to tell it apart from `null`.
+    ///
+    /// With the `metrics` feature enabled, the round-trip time of this and
+    /// every other kind of call is recorded and available through
+    /// `metrics::call_latency`.
     pub fn call<T, R>(&self, name: &str, args: T) -> R
     where
         T: Serialize,
         R: for<'a> Deserialize<'a>
     {
-        stasis_internals::outgoing::call(self.id, name, args)
+        self.call_ref(name, &args)
+    }
+
+    /// Call a registered JavaScript function with borrowed arguments.
+    ///
+    /// This is `call` without taking ownership of `args`, which saves cloning
+    /// large arguments that are still needed afterwards. Unsized arguments
+    /// such as slices are accepted, and are spread into separate arguments
+    /// just like tuples are.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register("draw", "function(points) { /* ... */ }");
+    ///
+    /// let points = vec![(0, 0), (10, 20), (30, 5)];
+    /// let () = m.call_ref("draw", &(&points,));
+    ///
+    /// // `points` was only borrowed.
+    /// assert_eq!(points.len(), 3);
+    /// # }
+    /// ```
+    pub fn call_ref<T, R>(&self, name: &str, args: &T) -> R
+    where
+        T: ?Sized + Serialize,
+        R: for<'a> Deserialize<'a>
+    {
+        self.call_with::<wire::Json, &T, R>(name, args)
+    }
+
+    /// Call a registered JavaScript function using a custom wire format.
+    ///
+    /// The bundled runtime only understands JSON, so any other format requires
+    /// a runtime that decodes it.
+    pub fn call_with<W, T, R>(&self, name: &str, args: T) -> R
+    where
+        W: wire::WireFormat,
+        T: Serialize,
+        R: for<'a> Deserialize<'a>
+    {
+        self.timed(name, |name| {
+            stasis_internals::outgoing::call_with::<W, T, R>(
+                self.id,
+                name,
+                args,
+            )
+        })
+    }
+
+    /// Call a registered JavaScript function, catching any exception it
+    /// throws.
+    ///
+    /// Unlike `call`, an exception is not fatal. It is returned as a
+    /// `CallError::JsException` holding the message of the thrown `Error`, or
+    /// the thrown value converted to a string otherwise.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::{CallError, Module};
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register("fail", r#"
+    ///     function() {
+    ///         throw new Error("boom");
+    ///     }
+    /// "#);
+    ///
+    /// match m.try_call::<_, ()>("fail", ()) {
+    ///     Err(CallError::JsException(message)) => assert_eq!(message, "boom"),
+    ///     Ok(()) => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub fn try_call<T, R>(&self, name: &str, args: T) -> Result<R, CallError>
+    where
+        T: Serialize,
+        R: for<'a> Deserialize<'a>
+    {
+        self.timed(name, |name| {
+            stasis_internals::outgoing::try_call(self.id, name, args)
+        })
+        .map_err(CallError::JsException)
+    }
+
+    /// Call a function, telling apart `undefined` from other return values.
+    ///
+    /// Returns `None` if the function returned `undefined`, or returned
+    /// nothing at all. An explicit `null` is deserialized into `Some`, so with
+    /// `R = serde_json::Value` it reads as `Some(Value::Null)`.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # extern crate serde_json;
+    /// # use stasis::Module;
+    /// # use serde_json::Value;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register("get", "function(key) { return localStorage[key] }");
+    ///
+    /// match m.call_opt::<_, Value>("get", "theme") {
+    ///     None => { /* The key was never set. */ }
+    ///     Some(Value::Null) => { /* The key holds an explicit null. */ }
+    ///     Some(_) => { /* Anything else. */ }
+    /// }
+    /// # }
+    /// ```
+    pub fn call_opt<T, R>(&self, name: &str, args: T) -> Option<R>
+    where
+        T: Serialize,
+        R: for<'a> Deserialize<'a>,
+    {
+        self.timed(name, |name| {
+            stasis_internals::outgoing::call_opt(self.id, name, args)
+        })
+    }
+
+    /// Call a function, reading its return value within `f`.
+    ///
+    /// The return value stays in its encoded form until `f` returns, so it
+    /// can be deserialized into types which borrow from it, such as `&str`.
+    /// This saves allocating owned copies of fields which are only read
+    /// briefly.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # #[macro_use] extern crate serde_derive;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// #[derive(Deserialize)]
+    /// struct Location<'a> {
+    ///     host: &'a str,
+    ///     port: &'a str,
+    /// }
+    ///
+    /// let m = Module::new();
+    /// m.register("location", "function() { return window.location }");
+    ///
+    /// let secure = m.call_scoped("location", (), |ret| {
+    ///     let location: Location = ret.deserialize();
+    ///     location.host.ends_with(".example.com") && location.port == "443"
+    /// });
+    /// # }
+    /// ```
+    pub fn call_scoped<T, U, F>(&self, name: &str, args: T, f: F) -> U
+    where
+        T: Serialize,
+        F: FnOnce(&ScopedReturn) -> U,
+    {
+        self.timed(name, |name| {
+            stasis_internals::outgoing::call_scoped(self.id, name, args, f)
+        })
+    }
+
+    /// Call a function whose return value has no known shape.
+    ///
+    /// Unlike `call`, this never panics while reading the return value. A
+    /// function returning `undefined` or `null` gives `Value::Null`, and a
+    /// return which is not valid JSON is kept as a `Value::String`. This is
+    /// useful when exploring an API before writing typed bindings for it.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register("screen", "function() { return window.screen }");
+    ///
+    /// let screen = m.call_value("screen", ());
+    /// let width = screen["width"].as_u64();
+    /// # }
+    /// ```
+    pub fn call_value<T>(&self, name: &str, args: T) -> serde_json::Value
+    where
+        T: Serialize,
+    {
+        self.timed(name, |name| {
+            stasis_internals::outgoing::call_value(self.id, name, args)
+        })
+    }
+
+    /// Call many JavaScript functions at once.
+    ///
+    /// This is equivalent to calling `try_call` for each `(name, args)` pair
+    /// in order, but crosses into JavaScript only once. It pays off when many
+    /// small, independent calls are made in a row, such as updating hundreds
+    /// of DOM nodes per frame.
+    ///
+    /// `args` is handled like the arguments of `call`: an array is spread
+    /// into the arguments, and any other value is the only argument. A
+    /// function which throws does not stop the others, its exception is
+    /// returned in its place. The results come back in the order of `calls`,
+    /// with `undefined` read as `Value::Null`.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # #[macro_use] extern crate serde_json;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register("setText", r#"
+    ///     function(id, text) {
+    ///         document.getElementById(id).textContent = text;
+    ///     }
+    /// "#);
+    ///
+    /// let calls: Vec<_> = (0..100)
+    ///     .map(|i| ("setText", json!([format!("cell-{}", i), i])))
+    ///     .collect();
+    ///
+    /// for result in m.call_batch(&calls) {
+    ///     if let Err(e) = result {
+    ///         eprintln!("{}", e);
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn call_batch(
+        &self,
+        calls: &[(&str, serde_json::Value)],
+    ) -> Vec<Result<serde_json::Value, CallError>> {
+        let names: Vec<_> = calls
+            .iter()
+            .map(|&(name, _)| self.qualify(name))
+            .collect();
+        let calls: Vec<_> = names
+            .iter()
+            .zip(calls)
+            .map(|(name, (_, args))| (&**name, args))
+            .collect();
+
+        #[cfg(feature = "metrics")]
+        let start = metrics::now();
+
+        let results = stasis_internals::outgoing::call_batch(self.id, &calls);
+
+        // The functions share one crossing, so each is given its full time.
+        #[cfg(feature = "metrics")]
+        for name in &names {
+            metrics::record(name, start);
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.map_err(CallError::JsException))
+            .collect()
+    }
+
+    /// Call a function with arguments which are already encoded as JSON.
+    ///
+    /// This skips serializing the arguments and deserializing the return
+    /// value, which is handy for forwarding payloads that are already JSON.
+    /// The return value is the JSON the function returned, or `null` for
+    /// `undefined`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `args_json` is not valid JSON.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register("sum", "function(a, b) { return { sum: a + b } }");
+    ///
+    /// let ret = m.call_raw("sum", "[1, 2]");
+    /// assert_eq!(ret, r#"{"sum":3}"#);
+    /// # }
+    /// ```
+    pub fn call_raw(&self, name: &str, args_json: &str) -> String {
+        self.timed(name, |name| {
+            stasis_internals::outgoing::call_json(self.id, name, args_json)
+        })
+    }
+
+    /// Evaluate a JavaScript expression once, without registering it.
+    ///
+    /// The expression sees the same `this` as functions registered on this
+    /// module, and its value is returned like `call` would. Statements can be
+    /// run by wrapping them in a function which is called immediately.
+    ///
+    /// This is only available with the `eval` feature, which is enabled by
+    /// default.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    ///
+    /// let language: String = m.eval("navigator.language");
+    /// let webgl: bool = m.eval(r#"
+    ///     (function() {
+    ///         var canvas = document.createElement("canvas");
+    ///         return !!canvas.getContext("webgl");
+    ///     })()
+    /// "#);
+    /// # }
+    /// ```
+    #[cfg(feature = "eval")]
+    pub fn eval<R>(&self, code: &str) -> R
+    where
+        R: for<'a> Deserialize<'a>
+    {
+        stasis_internals::outgoing::eval(self.id, code)
+    }
+
+    /// Evaluate a JavaScript expression, catching any exception it throws.
+    ///
+    /// See `eval` and `try_call`.
+    #[cfg(feature = "eval")]
+    pub fn try_eval<R>(&self, code: &str) -> Result<R, CallError>
+    where
+        R: for<'a> Deserialize<'a>
+    {
+        stasis_internals::outgoing::try_eval(self.id, code)
+            .map_err(CallError::JsException)
+    }
+
+    /// Call a registered JavaScript function with a list of strings.
+    ///
+    /// The function receives the strings as an array in its first argument,
+    /// and `args` as its second. If `args` is `()`, the strings are the only
+    /// argument.
+    ///
+    /// This is an optimization for frequently sent lists of strings. The call
+    /// is encoded straight from the borrowed slice into a buffer that is
+    /// reused between calls, so no intermediate `Vec<String>` is built and
+    /// the only allocation per call is the exact-size buffer handed to
+    /// JavaScript. The encoding is otherwise identical to `call`.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register("suggest", r#"
+    ///     function(words, limit) {
+    ///         console.log(words.slice(0, limit));
+    ///     }
+    /// "#);
+    ///
+    /// let () = m.call_strs("suggest", &["apple", "apricot", "avocado"], 2);
+    /// # }
+    /// ```
+    pub fn call_strs<T, R>(&self, name: &str, strs: &[&str], args: T) -> R
+    where
+        T: Serialize,
+        R: for<'a> Deserialize<'a>
+    {
+        self.timed(name, |name| {
+            stasis_internals::outgoing::call_strs(self.id, name, strs, args)
+        })
+    }
+
+    /// Call a registered JavaScript function which may be cancelled.
+    ///
+    /// The function can check `this.cancelled()` to see whether `cancel` has
+    /// been set and stop early. If the flag is set once the function returns,
+    /// its return value is discarded and `None` is returned.
+    ///
+    /// Cancellation is cooperative. The flag can only be set while the
+    /// function runs from a callback it invokes, or from another thread, and a
+    /// function that never checks it will always run to completion.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use std::sync::atomic::{AtomicBool, Ordering};
+    /// # use stasis::Module;
+    /// # fn main() {
+    /// static CANCEL: AtomicBool = AtomicBool::new(false);
+    ///
+    /// let m = Module::new();
+    /// m.register_callback("tick", |n: u32| {
+    ///     if n == 1000 {
+    ///         CANCEL.store(true, Ordering::SeqCst);
+    ///     }
+    /// });
+    /// m.register("count", r#"
+    ///     function() {
+    ///         var n = 0;
+    ///         while (!this.cancelled()) {
+    ///             this.callbacks.tick(++n);
+    ///         }
+    ///         return n;
+    ///     }
+    /// "#);
+    ///
+    /// let n: Option<u32> = m.call_cancellable("count", (), &CANCEL);
+    /// assert_eq!(n, None);
+    /// # }
+    /// ```
+    pub fn call_cancellable<T, R>(
+        &self,
+        name: &str,
+        args: T,
+        cancel: &AtomicBool,
+    ) -> Option<R>
+    where
+        T: Serialize,
+        R: for<'a> Deserialize<'a>
+    {
+        self.timed(name, |name| {
+            stasis_internals::outgoing::call_cancellable(
+                self.id,
+                name,
+                args,
+                cancel,
+            )
+        })
+    }
+
+    /// Call a registered JavaScript function which returns a `Promise`.
+    ///
+    /// The returned future completes once the promise settles. A rejection
+    /// completes it with a `PromiseError` holding the rejection reason.
+    /// Functions returning a plain value are treated as an already resolved
+    /// promise. Functions which always return a promise should be registered
+    /// with `register_async`.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # extern crate futures_v02x;
+    /// # use futures_v02x::FutureExt;
+    /// # use stasis::{Module, console, futures::v02};
+    /// # fn main() {
+    /// let m = Module::new();
+    /// m.register("fetchText", r#"
+    ///     function(url) {
+    ///         return fetch(url).then(function(r) { return r.text() });
+    ///     }
+    /// "#);
+    ///
+    /// let text = m.call_promise::<_, String>("fetchText", "/index.html");
+    ///
+    /// v02::spawn(text.then(|result| {
+    ///     match result {
+    ///         Ok(text) => console::log(text),
+    ///         Err(e) => console::error(e.to_string()),
+    ///     }
+    ///
+    ///     Ok(())
+    /// }));
+    /// # }
+    /// ```
+    pub fn call_promise<T, R>(&self, name: &str, args: T) -> futures::Promise<R>
+    where
+        T: Serialize,
+        R: for<'a> Deserialize<'a>
+    {
+        // Only the call is timed, not the wait for the promise to settle.
+        self.timed(name, |_| futures::Promise::call(self, name, args))
+    }
+}
+
+/// The prefixes given to `Module::with_namespace`, allocated once each.
+static NAMESPACES: Global<HashSet<&'static str>> = Global::INIT;
+
+fn intern(prefix: &str) -> &'static str {
+    let mut namespaces = NAMESPACES.lock();
+
+    if let Some(&prefix) = namespaces.get(prefix) {
+        return prefix;
+    }
+
+    let prefix: &'static str = Box::leak(prefix.to_owned().into_boxed_str());
+    namespaces.insert(prefix);
+
+    prefix
+}
+
+/// A failed call made with `Module::try_call` or `Module::call_batch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallError {
+    /// The function threw an exception with the given message.
+    JsException(String),
+}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            CallError::JsException(ref message) => {
+                write!(f, "JavaScript exception: {}", message)
+            }
+        }
     }
 }
 
+impl std::error::Error for CallError {}
+
 /// Prelude implementation.
 struct Prelude(Module);
 
@@ -66,40 +1001,281 @@ impl Default for Prelude {
         let m = Module::new();
 
         // Common global functions.
-        m.register("console.log", "console.log");
-        m.register("console.error", "console.error");
-        m.register("console.warn", "console.warn");
-        m.register("alert", r#"
-            function(s) {
-                window.alert(s);
+        m.register_batch(&[
+            ("console.log", "console.log"),
+            ("console.error", "console.error"),
+            ("console.warn", "console.warn"),
+            ("console.debug", "console.debug"),
+            ("console.info", "console.info"),
+            ("console.group", "console.group"),
+            ("console.groupCollapsed", "console.groupCollapsed"),
+            ("console.groupEnd", "console.groupEnd"),
+            ("console.time", "console.time"),
+            ("console.timeEnd", "console.timeEnd"),
+        ]);
+        m.register("console.assert", r#"
+            function(cond, data) {
+                console.assert.apply(console, [cond].concat(data));
+            }
+        "#);
+        m.register("console.batch", r#"
+            function(messages) {
+                for (var i = 0; i < messages.length; i++) {
+                    var args = messages[i];
+                    console.log.apply(
+                        console,
+                        args instanceof Array ? args : [args]
+                    );
+                }
             }
         "#);
 
+        // Only browsers have a `window` to show dialogs with.
+        #[cfg(feature = "browser")]
+        {
+            m.register("alert", r#"
+                function(s) {
+                    window.alert(s);
+                }
+            "#);
+            m.register("alert.json", r#"
+                function(value) {
+                    window.alert(JSON.stringify(value, null, 2));
+                }
+            "#);
+            m.register("confirm", r#"
+                function(s) {
+                    return window.confirm(s);
+                }
+            "#);
+            m.register("prompt", r#"
+                function(s, value) {
+                    return window.prompt(s, value);
+                }
+            "#);
+
+            m.register_callback("console.flush", |()| console::Batch::flush());
+            m.register("console.flushEveryFrame", r#"
+                function() {
+                    var flush = this.callbacks["console.flush"];
+                    var tick = function() {
+                        flush();
+                        window.requestAnimationFrame(tick);
+                    };
+
+                    window.requestAnimationFrame(tick);
+                }
+            "#);
+        }
+
         Prelude(m)
     }
 }
 
 /// Browser alert.
 ///
-/// Equivalent to `window.alert(...)`.
+/// Equivalent to `window.alert(...)`. To show structured data without
+/// formatting it first, use `alert_json`, which takes any `Serialize` value
+/// like `console::log` does.
+#[cfg(feature = "browser")]
 pub fn alert<T>(t: T) where T: ToString {
     PRELUDE.lock().0.call("alert", t.to_string())
 }
 
-pub mod console {
-    //! The browser `console` interface.
+/// Browser alert using `Debug` formatting.
+///
+/// Equivalent to `alert(format!("{:?}", t))`.
+#[cfg(feature = "browser")]
+pub fn alert_debug<T>(t: T) where T: Debug {
+    alert(format!("{:?}", t))
+}
 
-    use serde::Serialize;
+/// Browser alert showing a value as pretty-printed JSON.
+///
+/// The value is formatted in JavaScript with `JSON.stringify`.
+#[cfg(feature = "browser")]
+pub fn alert_json<T>(t: T) where T: Serialize {
+    // The argument is wrapped so that tuples are not spread into arguments.
+    PRELUDE.lock().0.call("alert.json", (t,))
+}
 
+/// Browser confirmation dialog.
+///
+/// Equivalent to `window.confirm(...)`. Returns `true` if the user clicked OK.
+#[cfg(feature = "browser")]
+pub fn confirm(message: &str) -> bool {
+    PRELUDE.lock().0.call("confirm", message)
+}
+
+/// Browser prompt dialog.
+///
+/// Equivalent to `window.prompt(...)`, with `default` as the initial input.
+/// Returns `None` if the user cancelled the dialog.
+#[cfg(feature = "browser")]
+pub fn prompt(message: &str, default: &str) -> Option<String> {
+    PRELUDE.lock().0.call("prompt", (message, default))
+}
+
+/// Log each argument to the console, like `console.log(a, b, c)`.
+///
+/// `console_log!(a, b, c)` expands to exactly `console::log((a, b, c))`, and
+/// `console_log!(a)` to `console::log((a,))`. It is not a `println!`-style
+/// macro: there is no format string, and each argument is serialized and
+/// formatted by the browser on its own. Use `format!` to log a formatted
+/// string, or `console::logf` for printf-style substitutions.
+///
+/// As the arguments are always wrapped in a tuple, a single `Vec` or array is
+/// logged as one argument rather than spread into several.
+///
+/// ```rust,no_run
+/// #[macro_use] extern crate stasis;
+///
+/// # fn main() {
+/// let count = 3;
+///
+/// // Logs `items 3 [1, 2, 3]`.
+/// console_log!("items", count, vec![1, 2, 3]);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! console_log {
+    () => {
+        $crate::console::log::<[(); 0]>([])
+    };
+
+    ($($arg:expr),+ $(,)*) => {
+        $crate::console::log(($($arg,)+))
+    };
+}
+
+/// Run inline JavaScript, interpolating Rust values with `@{...}`.
+///
+/// The snippet is the body of a function, so use `return` to give back a value.
+/// Each `@{expr}` is serialized and passed as an argument. Identical snippets
+/// are registered only once, on a hidden module.
+///
+/// ```rust,no_run
+/// #[macro_use] extern crate stasis;
+///
+/// # fn main() {
+/// let name = "stasis";
+/// let x = 41;
+///
+/// let y: u32 = js! {
+///     console.log("Hello from", @{name});
+///     return @{x} + 1;
+/// };
+/// # }
+/// ```
+///
+/// The snippet is tokenized by Rust, so string literals must use double
+/// quotes, and comments are dropped. Long snippets may need a higher
+/// `#![recursion_limit]`.
+#[macro_export]
+macro_rules! js {
+    ($($tt:tt)*) => {{
+        let mut code = String::new();
+        let mut args = Vec::new();
+
+        $crate::__js_munch!(code, args; [] $($tt)*);
+
+        $crate::js::run(&code, args)
+    }};
+}
+
+/// Build the code and arguments of a `js!` snippet.
+///
+/// Runs of plain tokens are collected in brackets and stringified at once,
+/// which keeps operators such as `===` intact.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __js_munch {
+    ($c:ident, $a:ident; [$($acc:tt)*] @ { $e:expr } $($rest:tt)*) => {
+        $crate::js::push(&mut $c, stringify!($($acc)*));
+        $crate::js::push(&mut $c, &format!("arguments[{}]", $a.len()));
+        $a.push($crate::js::arg(&$e));
+        $crate::__js_munch!($c, $a; [] $($rest)*);
+    };
+
+    ($c:ident, $a:ident; [$($acc:tt)*] ( $($inner:tt)* ) $($rest:tt)*) => {
+        $crate::js::push(&mut $c, stringify!($($acc)*));
+        $crate::js::push(&mut $c, "(");
+        $crate::__js_munch!($c, $a; [] $($inner)*);
+        $crate::js::push(&mut $c, ")");
+        $crate::__js_munch!($c, $a; [] $($rest)*);
+    };
+
+    ($c:ident, $a:ident; [$($acc:tt)*] [ $($inner:tt)* ] $($rest:tt)*) => {
+        $crate::js::push(&mut $c, stringify!($($acc)*));
+        $crate::js::push(&mut $c, "[");
+        $crate::__js_munch!($c, $a; [] $($inner)*);
+        $crate::js::push(&mut $c, "]");
+        $crate::__js_munch!($c, $a; [] $($rest)*);
+    };
+
+    ($c:ident, $a:ident; [$($acc:tt)*] { $($inner:tt)* } $($rest:tt)*) => {
+        $crate::js::push(&mut $c, stringify!($($acc)*));
+        $crate::js::push(&mut $c, "{");
+        $crate::__js_munch!($c, $a; [] $($inner)*);
+        $crate::js::push(&mut $c, "}");
+        $crate::__js_munch!($c, $a; [] $($rest)*);
+    };
+
+    ($c:ident, $a:ident; [$($acc:tt)*] $t:tt $($rest:tt)*) => {
+        $crate::__js_munch!($c, $a; [$($acc)* $t] $($rest)*);
+    };
+
+    ($c:ident, $a:ident; [$($acc:tt)*]) => {
+        $crate::js::push(&mut $c, stringify!($($acc)*));
+    };
+}
+
+pub mod console {
+    //! The browser `console` interface.
+
+    use std::mem;
+
+    use serde::Serialize;
+    use serde_json::{self, Value};
+
+    use global::Global;
     use super::PRELUDE;
 
     /// Log a message to the console.
     ///
-    /// This can be called with multiple arguments in a tuple or array.
+    /// This can be called with multiple arguments in a tuple or array. Prefer
+    /// the `console_log!` macro to pass several arguments unambiguously.
     pub fn log<T>(t: T) where T: Serialize {
         PRELUDE.lock().0.call("console.log", t)
     }
 
+    /// Log a message with printf-style substitutions, such as `%d` and `%o`.
+    ///
+    /// The substitution is done by the browser, with `fmt` as the first
+    /// argument to `console.log` followed by each element of `args`. Pass the
+    /// values as a tuple, with `()` for none.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::console;
+    /// # fn main() {
+    /// console::logf("x=%d, y=%d", (5, 7));
+    /// console::logf("items: %o", (vec![1, 2, 3],));
+    /// # }
+    /// ```
+    pub fn logf<T>(fmt: &str, args: T) where T: Serialize {
+        let mut spread = vec![Value::from(fmt)];
+
+        match serde_json::to_value(args) {
+            Ok(Value::Array(args)) => spread.extend(args),
+            Ok(Value::Null) => {}
+            Ok(arg) => spread.push(arg),
+            Err(e) => panic!("Failed to serialize arguments: {}", e),
+        }
+
+        PRELUDE.lock().0.call("console.log", spread)
+    }
+
     /// Log an error to the console.
     ///
     /// This can be called with multiple arguments in a tuple or array.
@@ -113,24 +1289,169 @@ pub mod console {
     pub fn warn<T>(t: T) where T: Serialize {
         PRELUDE.lock().0.call("console.warn", t)
     }
+
+    /// Log a debug message to the console.
+    ///
+    /// This can be called with multiple arguments in a tuple or array.
+    pub fn debug<T>(t: T) where T: Serialize {
+        PRELUDE.lock().0.call("console.debug", t)
+    }
+
+    /// Log an informational message to the console.
+    ///
+    /// This can be called with multiple arguments in a tuple or array.
+    pub fn info<T>(t: T) where T: Serialize {
+        PRELUDE.lock().0.call("console.info", t)
+    }
+
+    /// Log an error to the console if `cond` is false.
+    ///
+    /// Unlike `assert!`, this does not abort execution. The data can be
+    /// multiple arguments in a tuple or array.
+    pub fn assert<T>(cond: bool, data: T) where T: Serialize {
+        PRELUDE.lock().0.call("console.assert", (cond, data))
+    }
+
+    /// Messages waiting for `Batch::flush`.
+    static BATCH: Global<Vec<Value>> = Global::INIT;
+
+    /// A buffered logger for log-heavy code, such as a loop logging on every
+    /// iteration.
+    ///
+    /// Messages are kept in Rust until `flush`, which logs them all with a
+    /// single call into JavaScript. Each message takes arguments just like
+    /// `log`. The regular `log` is unaffected, and still logs immediately.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::console::Batch;
+    /// # fn main() {
+    /// for i in 0..1000 {
+    ///     Batch::log(("step", i));
+    /// }
+    ///
+    /// Batch::flush();
+    /// # }
+    /// ```
+    pub struct Batch {
+        _private: (),
+    }
+
+    impl Batch {
+        /// Buffer a message.
+        pub fn log<T>(t: T) where T: Serialize {
+            let value = match serde_json::to_value(t) {
+                Ok(value) => value,
+                Err(e) => panic!("Failed to serialize arguments: {}", e),
+            };
+
+            BATCH.lock().push(value);
+        }
+
+        /// The number of buffered messages.
+        pub fn len() -> usize {
+            BATCH.lock().len()
+        }
+
+        /// Log every buffered message.
+        pub fn flush() {
+            let messages = mem::take(&mut *BATCH.lock());
+
+            if messages.is_empty() {
+                return;
+            }
+
+            PRELUDE.lock().0.call("console.batch", (messages,))
+        }
+
+        /// Flush automatically at the start of every animation frame.
+        ///
+        /// This keeps running for the rest of the program, so it only needs
+        /// to be called once. This is only available with the `browser`
+        /// feature.
+        #[cfg(feature = "browser")]
+        pub fn flush_every_frame() {
+            PRELUDE.lock().0.call("console.flushEveryFrame", ())
+        }
+    }
+
+    /// Start a timer.
+    ///
+    /// Calling `time_end` with the same label logs the elapsed time.
+    pub fn time(label: &str) {
+        PRELUDE.lock().0.call("console.time", label)
+    }
+
+    /// Stop a timer started with `time` and log the elapsed time.
+    pub fn time_end(label: &str) {
+        PRELUDE.lock().0.call("console.timeEnd", label)
+    }
+
+    /// An open console group.
+    ///
+    /// The group is closed when this is dropped.
+    #[must_use = "the group is closed as soon as this is dropped"]
+    pub struct ConsoleGroup {
+        _private: (),
+    }
+
+    impl Drop for ConsoleGroup {
+        fn drop(&mut self) {
+            group_end();
+        }
+    }
+
+    /// Start a new console group.
+    ///
+    /// Following messages are indented until the returned guard is dropped.
+    /// This can be called with multiple arguments in a tuple or array.
+    ///
+    /// ```rust,no_run
+    /// # extern crate stasis;
+    /// # use stasis::console;
+    /// # fn main() {
+    /// let _group = console::group("phase");
+    /// console::log("inside the group");
+    /// # }
+    /// ```
+    pub fn group<T>(label: T) -> ConsoleGroup where T: Serialize {
+        let () = PRELUDE.lock().0.call("console.group", label);
+
+        ConsoleGroup { _private: () }
+    }
+
+    /// Start a new console group which is initially collapsed.
+    ///
+    /// See `group`.
+    pub fn group_collapsed<T>(label: T) -> ConsoleGroup where T: Serialize {
+        let () = PRELUDE.lock().0.call("console.groupCollapsed", label);
+
+        ConsoleGroup { _private: () }
+    }
+
+    /// Close the current console group.
+    ///
+    /// This is only needed for groups whose guard has been forgotten.
+    pub fn group_end() {
+        PRELUDE.lock().0.call("console.groupEnd", ())
+    }
 }
 
-/// Setup a panic handler.
+/// A panic, as passed to a hook set with [`setup_panic_with`].
 ///
-/// This sends all panics to the console.
-pub fn setup_panic() {
-    std::panic::set_hook(Box::new(|info| {
-        let message = info
-            .location()
-            .map(|loc| {
-                format!("Panic!\nLine {} in {}", loc.line(), loc.file())
-            })
-            .unwrap_or("Panic in unknown location".to_owned());
+/// [`setup_panic_with`]: fn.setup_panic_with.html
+#[derive(Debug, Clone)]
+pub struct PanicReport {
+    message: String,
+    location: Option<(String, u32, u32)>,
+}
 
-        let payload = info
+impl PanicReport {
+    fn new(info: &std::panic::PanicHookInfo) -> Self {
+        let message = info
             .payload()
             .downcast_ref::<String>()
-            .map(|s| s.clone())
+            .cloned()
             .or_else(|| {
                 info.payload()
                     .downcast_ref::<&str>()
@@ -138,8 +1459,690 @@ pub fn setup_panic() {
             })
             .unwrap_or("No panic info.".to_owned());
 
-        let s = format!("{}:\n\n{}", message, payload);
+        let location = info
+            .location()
+            .map(|loc| (loc.file().to_owned(), loc.line(), loc.column()));
+
+        PanicReport { message, location }
+    }
+
+    /// The panic message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The source file which panicked, if known.
+    pub fn file(&self) -> Option<&str> {
+        self.location.as_ref().map(|(file, _, _)| &file[..])
+    }
+
+    /// The line which panicked, if known.
+    pub fn line(&self) -> Option<u32> {
+        self.location.as_ref().map(|&(_, line, _)| line)
+    }
+
+    /// The column which panicked, if known.
+    pub fn column(&self) -> Option<u32> {
+        self.location.as_ref().map(|&(_, _, column)| column)
+    }
+}
+
+impl std::fmt::Display for PanicReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.location {
+            Some((ref file, line, _)) => {
+                write!(f, "Panic!\nLine {} in {}", line, file)?;
+            }
+            None => write!(f, "Panic in unknown location")?,
+        }
+
+        write!(f, ":\n\n{}", self.message)
+    }
+}
+
+/// Whether one of the `setup_panic` functions has installed a hook.
+static PANIC_HOOK: AtomicBool = AtomicBool::new(false);
 
-        console::error(&s);
+/// Setup a panic handler.
+///
+/// This sends all panics to the console. Only the first call has any effect,
+/// and nothing happens if a handler was already set with `setup_panic_with` or
+/// `setup_panic_chained`. Libraries and applications may therefore both call
+/// this without replacing each other's handler.
+pub fn setup_panic() {
+    // Checking and setting the flag at once makes sure only one call wins.
+    if !PANIC_HOOK.swap(true, Ordering::SeqCst) {
+        set_panic_hook(|_, report| console::error(report.to_string()));
+    }
+}
+
+/// Setup a custom panic handler.
+///
+/// This replaces any previous handler, including the one set by
+/// `setup_panic`. The handler may for example send panics to a telemetry
+/// service, or log them with `console::error` before doing so.
+///
+/// Panics which are rethrown in JavaScript due to `catch_panics` never reach
+/// the handler.
+pub fn setup_panic_with<F>(f: F)
+where
+    F: Fn(&PanicReport) + Send + Sync + 'static,
+{
+    PANIC_HOOK.store(true, Ordering::SeqCst);
+    set_panic_hook(move |_, report| f(report));
+}
+
+/// Install `f` as the panic hook, unless a callback is catching panics.
+fn set_panic_hook<F>(f: F)
+where
+    F: Fn(&std::panic::PanicHookInfo, &PanicReport) + Send + Sync + 'static,
+{
+    std::panic::set_hook(Box::new(move |info| {
+        let report = PanicReport::new(info);
+
+        if incoming::is_catching() {
+            incoming::set_panic_message(report.to_string());
+        } else {
+            f(info, &report);
+        }
     }));
 }
+
+/// Setup a panic handler which keeps the current one.
+///
+/// Each panic is first given to the previously installed hook, and then sent
+/// to the console like `setup_panic` does. Use this to add console reporting on
+/// top of a hook installed by other code.
+///
+/// If the current hook was installed by one of the `setup_panic` functions,
+/// it is kept as is and panics are not sent to the console a second time.
+pub fn setup_panic_chained() {
+    let previous = std::panic::take_hook();
+    let ours = PANIC_HOOK.swap(true, Ordering::SeqCst);
+
+    set_panic_hook(move |info, report| {
+        previous(info);
+
+        if !ours {
+            console::error(report.to_string());
+        }
+    });
+}
+
+/// Rethrow panics inside callbacks as JavaScript exceptions.
+///
+/// When enabled, a callback which panics is caught with `catch_unwind` and the
+/// JavaScript caller receives a thrown `Error` instead, which it may handle
+/// with `try`/`catch`. The message includes the panic location if a handler
+/// was set up with `setup_panic` or `setup_panic_with`. Panics outside of a
+/// callback still go to the panic handler.
+///
+/// This only works on targets which unwind on panic. With `panic = "abort"`,
+/// panics abort regardless.
+pub fn catch_panics(enabled: bool) {
+    incoming::set_catch_panics(enabled);
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{Value, json};
+    use stasis_internals::mock;
+
+    use super::{Bytes, CallError, Module, PanicReport, console};
+
+    #[test]
+    fn call_mocked() {
+        mock::implement("test.greet", |args| {
+            json!(format!("Hello, {}!", args[0].as_str().unwrap()))
+        });
+
+        let m = Module::new();
+        m.register("test.greet", "function(name) { return 'Hello' }");
+
+        let greeting: String = m.call("test.greet", "stasis");
+
+        assert!(mock::is_registered(m.id(), "test.greet"));
+        assert_eq!(greeting, "Hello, stasis!");
+    }
+
+    #[test]
+    fn console_log_arguments() {
+        use std::sync::Mutex;
+
+        static LOGGED: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+
+        mock::implement("console.log", |args| {
+            LOGGED.lock().unwrap().push(args);
+            Value::Null
+        });
+
+        console_log!("a", 1);
+        console_log!(vec![1, 2]);
+        console_log!();
+        console::logf("x=%d, y=%d", (5, 7));
+        console::logf("%o", (vec![1, 2],));
+        console::logf("done", ());
+
+        assert_eq!(
+            *LOGGED.lock().unwrap(),
+            vec![
+                json!(["a", 1]),
+                json!([[1, 2]]),
+                json!([]),
+                json!(["x=%d, y=%d", 5, 7]),
+                json!(["%o", [1, 2]]),
+                json!(["done"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn chained_panic_hook() {
+        use std::panic;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static PREVIOUS: AtomicUsize = AtomicUsize::new(0);
+        static LOGGED: AtomicUsize = AtomicUsize::new(0);
+
+        type Hook = Box<dyn Fn(&panic::PanicHookInfo) + Sync + Send>;
+
+        /// The panic hook is shared by every test, so the original one is put
+        /// back even if this test fails.
+        struct RestoreHook(Option<Hook>);
+
+        impl Drop for RestoreHook {
+            fn drop(&mut self) {
+                if let Some(hook) = self.0.take() {
+                    panic::set_hook(hook);
+                }
+            }
+        }
+
+        let _restore = RestoreHook(Some(panic::take_hook()));
+
+        // Other tests may panic meanwhile, so only count this test's panics.
+        fn ours(s: &str) -> bool {
+            s.contains("chained test panic")
+        }
+
+        mock::implement("console.error", |args| {
+            if ours(args[0].as_str().unwrap_or("")) {
+                LOGGED.fetch_add(1, Ordering::SeqCst);
+            }
+
+            Value::Null
+        });
+
+        panic::set_hook(Box::new(|info| {
+            if ours(&info.to_string()) {
+                PREVIOUS.fetch_add(1, Ordering::SeqCst);
+            }
+        }));
+
+        super::setup_panic_chained();
+        let _ = panic::catch_unwind(|| panic!("chained test panic"));
+
+        assert_eq!(PREVIOUS.load(Ordering::SeqCst), 1);
+        assert_eq!(LOGGED.load(Ordering::SeqCst), 1);
+
+        // This is now a no-op, and keeps the chained hook.
+        super::setup_panic();
+        let _ = panic::catch_unwind(|| panic!("chained test panic"));
+
+        assert_eq!(PREVIOUS.load(Ordering::SeqCst), 2);
+        assert_eq!(LOGGED.load(Ordering::SeqCst), 2);
+
+        // Chaining onto our own hook does not log each panic twice.
+        super::setup_panic_chained();
+        let _ = panic::catch_unwind(|| panic!("chained test panic"));
+
+        assert_eq!(PREVIOUS.load(Ordering::SeqCst), 3);
+        assert_eq!(LOGGED.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn js_snippet() {
+        let name = "stasis";
+        let x = 41;
+
+        mock::implement("js0", |args| {
+            json!(format!("{}:{}", args[0].as_str().unwrap(), args[1]))
+        });
+
+        for _ in 0..2 {
+            let ret: String = js! {
+                if (a === b) { console.log(@{name}); }
+                return @{x + 1};
+            };
+
+            assert_eq!(ret, "stasis:42");
+        }
+
+        // The snippet was only registered once. Other tests may register
+        // their own, so only look at this one.
+        let code =
+            "if(a === b) { console.log(arguments[0]); } return arguments[1];";
+        let names: Vec<_> = super::js::names()
+            .into_iter()
+            .filter(|(c, _)| c == code)
+            .map(|(_, name)| name)
+            .collect();
+
+        assert_eq!(names, vec!["js0"]);
+    }
+
+    #[test]
+    fn call_strs_arguments() {
+        mock::implement("test.strsArgs", |args| args);
+
+        let m = Module::new();
+
+        let args: Value = m.call_strs("test.strsArgs", &["a", "b"], 2);
+        assert_eq!(args, json!([["a", "b"], 2]));
+
+        let args: Value = m.call_strs("test.strsArgs", &["a"], ());
+        assert_eq!(args, json!([["a"]]));
+    }
+
+    #[test]
+    fn call_value_mocked() {
+        mock::implement("test.shape", |args| match args[0].as_u64() {
+            Some(0) => Value::Null,
+            _ => json!({"width": 640}),
+        });
+
+        let m = Module::new();
+
+        assert_eq!(m.call_value("test.shape", 0), Value::Null);
+        assert_eq!(m.call_value("test.shape", 1)["width"], 640);
+    }
+
+    #[test]
+    fn call_raw_mocked() {
+        mock::implement("test.raw", |args| match args[0].as_str() {
+            Some("nothing") => Value::Null,
+            _ => json!({ "got": args }),
+        });
+
+        let m = Module::new();
+
+        assert_eq!(
+            m.call_raw("test.raw", r#"[1, {"a": "b"}]"#),
+            r#"{"got":[1,{"a":"b"}]}"#,
+        );
+        assert_eq!(m.call_raw("test.raw", r#""nothing""#), "null");
+
+        // Returns are UTF-8, not one byte per character.
+        assert_eq!(
+            m.call_raw("test.raw", r#"["héllo ✓"]"#),
+            r#"{"got":["héllo ✓"]}"#,
+        );
+    }
+
+    #[test]
+    fn bytes_return_mocked() {
+        mock::implement("test.blob", |_| mock::bytes(&[1, 2, 3]));
+        mock::implement("test.forged", |_| json!({ "$bytes": [1234, 3] }));
+
+        let m = Module::new();
+        let blob: Bytes = m.call("test.blob", ());
+
+        assert_eq!(blob, Bytes(vec![1, 2, 3]));
+
+        // User data which merely looks like a buffer is left alone.
+        let forged = m.call_value("test.forged", ());
+        assert_eq!(forged, json!({ "$bytes": [1234, 3] }));
+    }
+
+    #[test]
+    fn bytes_argument_mocked() {
+        mock::implement("test.echo_bytes", |args| args[0].clone());
+
+        let m = Module::new();
+        let echoed: Vec<u8> = m.call("test.echo_bytes", (Bytes(vec![7, 8]),));
+
+        assert_eq!(echoed, vec![7, 8]);
+    }
+
+    #[test]
+    fn namespaced_module() {
+        mock::implement("nstest.double", |args| {
+            json!(args[0].as_u64().unwrap() * 2)
+        });
+
+        let m = Module::with_namespace("nstest");
+        m.register("double", "function(n) { return n * 2 }");
+        m.register_batch(&[("half", "function(n) { return n / 2 }")]);
+
+        assert!(mock::is_registered(m.id(), "nstest.double"));
+        assert!(mock::is_registered(m.id(), "nstest.half"));
+        assert!(!mock::is_registered(m.id(), "double"));
+
+        let n: u64 = m.call("double", 21);
+        assert_eq!(n, 42);
+
+        // The same prefix is only allocated once.
+        let other = Module::with_namespace("nstest");
+        assert!(::std::ptr::eq(
+            m.namespace().unwrap(),
+            other.namespace().unwrap(),
+        ));
+        assert_eq!(Module::new().qualify("double"), "double");
+    }
+
+    #[test]
+    fn call_batch() {
+        mock::implement("batch.add", |args| {
+            json!(args[0].as_u64().unwrap() + args[1].as_u64().unwrap())
+        });
+        mock::implement("batch.fail", |_| mock::exception("boom"));
+        mock::implement("batch.none", |_| mock::undefined());
+
+        let m = Module::with_namespace("batch");
+        m.register_batch(&[
+            ("add", "function(a, b) { return a + b }"),
+            ("fail", "function() { throw new Error('boom') }"),
+            ("none", "function() {}"),
+        ]);
+
+        // The failed call does not stop the ones after it.
+        let results = m.call_batch(&[
+            ("add", json!([1, 2])),
+            ("fail", json!([])),
+            ("none", json!([])),
+            ("add", json!([3, 4])),
+        ]);
+
+        assert_eq!(results, vec![
+            Ok(json!(3)),
+            Err(CallError::JsException("boom".to_owned())),
+            Ok(Value::Null),
+            Ok(json!(7)),
+        ]);
+
+        assert_eq!(m.call_batch(&[]), vec![]);
+    }
+
+    #[test]
+    fn positional_arguments() {
+        mock::implement("args.echo", |args| args);
+
+        let m = Module::new();
+        m.register("args.echo", "function() {}");
+
+        let none: Value = m.call("args.echo", ());
+        assert_eq!(none, json!([]));
+
+        let one: Value = m.call("args.echo", (1u32,));
+        assert_eq!(one, json!([1]));
+
+        let two: Value = m.call("args.echo", (1u32, "a"));
+        assert_eq!(two, json!([1, "a"]));
+
+        let three: Value = m.call("args.echo", (1u32, "a", true));
+        assert_eq!(three, json!([1, "a", true]));
+
+        let four: Value = m.call("args.echo", (1u32, "a", true, 2.5));
+        assert_eq!(four, json!([1, "a", true, 2.5]));
+
+        let five: Value = m.call(
+            "args.echo",
+            (1u32, "a", true, 2.5, None::<u32>),
+        );
+        assert_eq!(five, json!([1, "a", true, 2.5, null]));
+
+        // A value which is not a tuple is the only argument.
+        let single: Value = m.call("args.echo", "a");
+        assert_eq!(single, json!(["a"]));
+
+        // Only `()` itself calls without arguments, even though `null` is
+        // serialized the same way.
+        let null: Value = m.call("args.echo", Value::Null);
+        assert_eq!(null, json!([null]));
+
+        let null: Value = m.call_ref("args.echo", &Value::Null);
+        assert_eq!(null, json!([null]));
+
+        let array: Value = m.call("args.echo", (vec![1, 2],));
+        assert_eq!(array, json!([[1, 2]]));
+    }
+
+    #[test]
+    fn non_sync_callback() {
+        use std::cell::Cell;
+
+        let m = Module::new();
+        let count = Cell::new(0);
+
+        m.register_callback_mut("nonSync.count", move |()| {
+            count.set(count.get() + 1);
+            count.get()
+        });
+
+        let count = || mock::callback(m.id(), "nonSync.count", Value::Null);
+
+        assert_eq!(count(), Ok(json!(1)));
+        assert_eq!(count(), Ok(json!(2)));
+    }
+
+    #[test]
+    fn call_ref_borrows() {
+        mock::implement("ref.sum", |args| {
+            let sum: u64 = args
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|n| n.as_u64().unwrap())
+                .sum();
+
+            json!(sum)
+        });
+
+        let m = Module::new();
+        m.register("ref.sum", "function() {}");
+
+        let data = [3u8; 4096];
+        let sum: u64 = m.call_ref("ref.sum", &data[..]);
+
+        assert_eq!(sum, 3 * 4096);
+        assert_eq!(data[0], 3);
+    }
+
+    #[test]
+    fn call_scoped_borrows() {
+        #[derive(Deserialize)]
+        struct User<'a> {
+            name: &'a str,
+            age: u32,
+        }
+
+        mock::implement("scoped.user", |_| {
+            json!({ "name": "Ada", "age": 36, "bio": "..." })
+        });
+
+        let m = Module::new();
+        m.register("scoped.user", "function() {}");
+
+        // `name` is a `&str` into the return buffer, so nothing is copied out.
+        let summary = m.call_scoped("scoped.user", (), |ret| {
+            let user: User = ret.deserialize();
+            (user.name.len(), user.age)
+        });
+
+        assert_eq!(summary, (3, 36));
+
+        let raw = m.call_scoped("scoped.user", (), |ret| ret.as_bytes().len());
+        assert!(raw > 0);
+    }
+
+    #[test]
+    fn register_async() {
+        use std::sync::{Arc, Mutex};
+
+        use futures_v02x::FutureExt;
+
+        use futures::{promise::testing, v02};
+
+        testing::install();
+
+        let m = Module::new();
+        m.register_async("async.double", r#"
+            async function(n) {
+                return n * 2;
+            }
+        "#);
+
+        assert!(mock::is_async(m.id(), "async.double"));
+
+        let result = Arc::new(Mutex::new(None));
+
+        {
+            let result = result.clone();
+            let doubled = m.call_promise::<_, u32>("async.double", 21);
+
+            v02::spawn(doubled.then(move |n| {
+                *result.lock().unwrap() = Some(n);
+                Ok(())
+            }));
+        }
+
+        let started = testing::started("async.double");
+        assert_eq!(started.len(), 1);
+        assert_eq!(started[0].1, json!(21));
+
+        testing::settle(&m, &started[0].0, Ok(json!(42)));
+        assert_eq!(*result.lock().unwrap(), Some(Ok(42)));
+    }
+
+    #[test]
+    fn register_with_memory() {
+        // The mock shares an address space with the test, so reading through
+        // the pointer stands in for `this.memory.subarray(ptr, ptr + len)`.
+        mock::implement("memory.checksum", |args| {
+            let ptr = args[0].as_u64().unwrap() as usize as *const u8;
+            let len = args[1].as_u64().unwrap() as usize;
+
+            let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+            let sum: u64 = bytes.iter().map(|&b| u64::from(b)).sum();
+
+            json!([bytes[0], bytes[len - 1], sum])
+        });
+
+        let m = Module::new();
+        m.register_with_memory("memory.checksum", r#"
+            function(ptr, len) {
+                var bytes = this.memory.subarray(ptr, ptr + len);
+                var sum = 0;
+
+                for (var i = 0; i < len; i++) sum += bytes[i];
+
+                return [bytes[0], bytes[len - 1], sum];
+            }
+        "#);
+
+        assert!(mock::has_memory(m.id(), "memory.checksum"));
+
+        let pattern: Vec<u8> = (0..=255).collect();
+        let args = (pattern.as_ptr() as usize, pattern.len());
+        let checksum: (u8, u8, u64) = m.call("memory.checksum", args);
+
+        assert_eq!(checksum, (0, 255, 255 * 256 / 2));
+    }
+
+    #[test]
+    fn batched_console() {
+        use std::sync::{Arc, Mutex};
+
+        let batches = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let batches = batches.clone();
+
+            mock::implement("console.batch", move |args| {
+                batches.lock().unwrap().push(args);
+                Value::Null
+            });
+        }
+
+        console::Batch::log("first");
+        console::Batch::log(("second", 2));
+        assert_eq!(console::Batch::len(), 2);
+
+        console::Batch::flush();
+        console::Batch::flush();
+
+        assert_eq!(console::Batch::len(), 0);
+        assert_eq!(
+            *batches.lock().unwrap(),
+            vec![json!([["first", ["second", 2]]])],
+        );
+    }
+
+    #[test]
+    fn fallible_callback() {
+        let m = Module::new();
+
+        m.register_callback_fallible("square", |n: u32| n * n);
+
+        assert_eq!(mock::callback(m.id(), "square", json!(3)), Ok(json!(9)));
+
+        let err = mock::callback(m.id(), "square", json!("three")).unwrap_err();
+        assert!(err.contains("Failed to deserialize argument"));
+    }
+
+    #[test]
+    fn multi_argument_callbacks() {
+        let m = Module::new();
+
+        m.register_callback2("sum2", |a: u32, b: u32| a + b);
+        m.register_callback3("join3", |a: String, b: u32, c: bool| {
+            format!("{}{}{}", a, b, c)
+        });
+
+        // JavaScript calling `sum2(2, 3)` sends the arguments as an array.
+        assert_eq!(mock::callback(m.id(), "sum2", json!([2, 3])), Ok(json!(5)));
+        assert_eq!(
+            mock::callback(m.id(), "join3", json!(["a", 1, true])),
+            Ok(json!("a1true"))
+        );
+    }
+
+    #[cfg(feature = "eval")]
+    #[test]
+    fn eval_mocked() {
+        mock::implement("navigator.language", |_| json!("en-US"));
+
+        let m = Module::new();
+        let language: String = m.eval("navigator.language");
+        let checked: Result<String, _> = m.try_eval("navigator.language");
+
+        assert_eq!(language, "en-US");
+        assert_eq!(checked, Ok("en-US".to_owned()));
+    }
+
+    #[test]
+    fn panic_report_display() {
+        let report = PanicReport {
+            message: "oops".to_owned(),
+            location: Some(("src/lib.rs".to_owned(), 7, 5)),
+        };
+
+        assert_eq!(report.file(), Some("src/lib.rs"));
+        assert_eq!(report.line(), Some(7));
+        assert_eq!(
+            report.to_string(),
+            "Panic!\nLine 7 in src/lib.rs:\n\noops"
+        );
+
+        let report = PanicReport {
+            message: "oops".to_owned(),
+            location: None,
+        };
+
+        assert_eq!(report.line(), None);
+        assert_eq!(
+            report.to_string(),
+            "Panic in unknown location:\n\noops"
+        );
+    }
+}
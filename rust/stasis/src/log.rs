@@ -0,0 +1,197 @@
+//! Routing of `log` records to the console or a JavaScript sink.
+//!
+//! Once installed with `init` or `set_sink`, the macros of the `log` crate,
+//! such as `info!` and `error!`, write to the browser console. A sink set with
+//! `set_sink` also receives every record as a structured `Record`, which is
+//! useful for shipping logs to a server.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! #[macro_use] extern crate log;
+//!
+//! # use stasis::Module;
+//! # fn main() {
+//! let m = Module::new();
+//! m.register("ship", r#"
+//!     function(record) {
+//!         navigator.sendBeacon("/logs", JSON.stringify(record));
+//!     }
+//! "#);
+//!
+//! stasis::log::set_sink(&m, "ship");
+//!
+//! info!("Started in {} ms", 42);
+//! # }
+//! ```
+
+use std::sync::Once;
+
+use log_crate::{self, Level, LevelFilter, Log, Metadata};
+
+use Module;
+use console;
+use global::Global;
+use time;
+
+/// A log record as given to the sink.
+///
+/// The sink is called with this as its only argument, serialized as a plain
+/// object with these fields.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    /// One of `"error"`, `"warn"`, `"info"`, `"debug"` or `"trace"`.
+    pub level: String,
+
+    /// The target of the record, which is the module path by default.
+    pub target: String,
+
+    /// The formatted message.
+    pub message: String,
+
+    /// Milliseconds since the Unix epoch, as given by `Date.now()`.
+    pub timestamp: f64,
+}
+
+struct Config {
+    sink: Option<(Module, String)>,
+    console: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            sink: None,
+            console: true,
+        }
+    }
+}
+
+static CONFIG: Global<Config> = Global::INIT;
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log_crate::Record) {
+        let (sink, console) = {
+            let config = CONFIG.lock();
+
+            (config.sink.clone(), config.console)
+        };
+
+        let message = record.args().to_string();
+
+        // The lock is released by now, so the sink is free to log as well.
+        if console {
+            let line = format!("{}: {}", record.target(), message);
+
+            match record.level() {
+                Level::Error => console::error(line),
+                Level::Warn => console::warn(line),
+                Level::Info => console::info(line),
+                Level::Debug | Level::Trace => console::debug(line),
+            }
+        }
+
+        if let Some((module, name)) = sink {
+            let record = Record {
+                level: record.level().as_str().to_lowercase(),
+                target: record.target().to_owned(),
+                message,
+                timestamp: time::unix_millis(),
+            };
+
+            let () = module.call(&name, (record,));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the logger, writing every record to the console.
+///
+/// This is called by `set_sink`, and does nothing once the logger has been
+/// installed. If another logger was installed first, that one is kept.
+pub fn init() {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        if log_crate::set_logger(&LOGGER).is_ok() {
+            log_crate::set_max_level(LevelFilter::Trace);
+        }
+    });
+}
+
+/// Send every record to the JavaScript function `fn_name` of `module`.
+///
+/// This replaces any previous sink, and installs the logger with `init`.
+/// Records still go to the console as well, unless turned off with
+/// `set_console`.
+pub fn set_sink(module: &Module, fn_name: &str) {
+    init();
+
+    CONFIG.lock().sink = Some((*module, fn_name.to_owned()));
+}
+
+/// Stop sending records to the sink set with `set_sink`.
+pub fn clear_sink() {
+    CONFIG.lock().sink = None;
+}
+
+/// Set whether records are written to the console. This is on by default.
+pub fn set_console(enabled: bool) {
+    CONFIG.lock().console = enabled;
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{self, Value, json};
+
+    use stasis_internals::mock;
+
+    use Module;
+    use super::{Record, set_console, set_sink};
+
+    #[test]
+    fn sink() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let records = records.clone();
+
+            mock::implement("log.sink", move |args| {
+                records.lock().unwrap().push(args);
+                Value::Null
+            });
+        }
+
+        mock::implement("unixMillis", |_| json!(1500.0));
+
+        let m = Module::new();
+        m.register("log.sink", "function(record) {}");
+
+        set_console(false);
+        set_sink(&m, "log.sink");
+
+        info!(target: "app", "Started in {} ms", 42);
+
+        let args = records.lock().unwrap().clone();
+        assert_eq!(args.len(), 1);
+
+        let record = args[0][0].clone();
+        let record: Record = serde_json::from_value(record).unwrap();
+        assert_eq!(record, Record {
+            level: "info".to_owned(),
+            target: "app".to_owned(),
+            message: "Started in 42 ms".to_owned(),
+            timestamp: 1500.0,
+        });
+    }
+}
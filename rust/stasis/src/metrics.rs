@@ -0,0 +1,156 @@
+//! Call latency measurements.
+//!
+//! This module is only available with the `metrics` feature. Once enabled,
+//! every call made through a `Module` is timed, whether with `call`,
+//! `try_call`, `call_batch` or any other method. WebAssembly has no clock of
+//! its own, so there the time is read with `performance.now()` in JavaScript.
+//! Native builds, such as tests against the `mock` runtime, use the system
+//! clock instead. The time includes serialization on both sides of the
+//! boundary.
+//!
+//! Latencies are tracked by function name, so functions of the same name on
+//! different modules share their measurements.
+
+use std::{
+    collections::HashMap,
+    time::Duration,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{sync::OnceLock, time::Instant};
+
+use global::Global;
+#[cfg(target_arch = "wasm32")]
+use time;
+
+/// The weight given to each new sample of the moving average.
+const WEIGHT: f64 = 0.125;
+
+struct Latency {
+    /// The last measurement in milliseconds.
+    last: f64,
+
+    /// An exponential moving average in milliseconds.
+    average: f64,
+}
+
+impl Latency {
+    fn new(ms: f64) -> Self {
+        Self {
+            last: ms,
+            average: ms,
+        }
+    }
+
+    fn update(&mut self, ms: f64) {
+        self.average += WEIGHT * (ms - self.average);
+        self.last = ms;
+    }
+}
+
+static LATENCIES: Global<HashMap<String, Latency>> = Global::INIT;
+
+/// The current time in milliseconds, as given by `performance.now()`.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> f64 {
+    time::now()
+}
+
+/// The current time in milliseconds, since the first time it was read.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> f64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
+
+/// Record a call that started at `start`.
+pub(crate) fn record(name: &str, start: f64) {
+    let elapsed = now() - start;
+
+    record_elapsed(name, elapsed);
+}
+
+fn record_elapsed(name: &str, ms: f64) {
+    let ms = ms.max(0.0);
+    let mut latencies = LATENCIES.lock();
+
+    if let Some(latency) = latencies.get_mut(name) {
+        latency.update(ms);
+        return;
+    }
+
+    latencies.insert(name.to_owned(), Latency::new(ms));
+}
+
+fn duration(ms: f64) -> Duration {
+    Duration::from_nanos((ms * 1_000_000.0) as u64)
+}
+
+/// The moving average round-trip time of calls to `name`.
+///
+/// Returns `None` if no function of this name has been called yet.
+pub fn call_latency(name: &str) -> Option<Duration> {
+    LATENCIES
+        .lock()
+        .get(name)
+        .map(|latency| duration(latency.average))
+}
+
+/// The round-trip time of the most recent call to `name`.
+///
+/// Returns `None` if no function of this name has been called yet.
+pub fn last_call_latency(name: &str) -> Option<Duration> {
+    LATENCIES
+        .lock()
+        .get(name)
+        .map(|latency| duration(latency.last))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use serde_json::{Value, json};
+    use stasis_internals::mock;
+
+    use Module;
+    use super::{call_latency, last_call_latency, record_elapsed};
+
+    #[test]
+    fn moving_average() {
+        assert_eq!(call_latency("test.average"), None);
+
+        let ms = |n| Some(Duration::from_millis(n));
+
+        record_elapsed("test.average", 8.0);
+        assert_eq!(call_latency("test.average"), ms(8));
+
+        record_elapsed("test.average", 16.0);
+        assert_eq!(call_latency("test.average"), ms(9));
+        assert_eq!(last_call_latency("test.average"), ms(16));
+    }
+
+    #[test]
+    fn every_call_is_timed() {
+        mock::implement("metrics.call", |_| Value::Null);
+        mock::implement("metrics.tryCall", |_| Value::Null);
+        mock::implement("metrics.callOpt", |_| Value::Null);
+        mock::implement("metrics.batch", |_| Value::Null);
+
+        let m = Module::new();
+
+        let () = m.call("metrics.call", ());
+        let () = m.try_call("metrics.tryCall", ()).unwrap();
+        let _: Option<()> = m.call_opt("metrics.callOpt", ());
+        m.call_batch(&[("metrics.batch", json!([]))]);
+
+        for name in &[
+            "metrics.call",
+            "metrics.tryCall",
+            "metrics.callOpt",
+            "metrics.batch",
+        ] {
+            assert!(call_latency(name).is_some(), "{} was not timed", name);
+        }
+    }
+}
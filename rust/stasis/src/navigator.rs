@@ -0,0 +1,111 @@
+//! Browser details and feature detection.
+//!
+//! This module is only available with the `browser` feature, which is enabled
+//! by default.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # use stasis::{console, navigator};
+//! # fn main() {
+//! if !navigator::online() {
+//!     console::warn("Offline, changes are saved locally.");
+//! }
+//!
+//! if navigator::has_feature("WebGL2RenderingContext") {
+//!     console::log(format!("WebGL 2 on {}", navigator::user_agent()));
+//! }
+//! # }
+//! ```
+
+use Module;
+use global::Global;
+
+/// The navigator module.
+struct Navigator(Module);
+
+static NAVIGATOR: Global<Navigator> = Global::INIT;
+
+impl Default for Navigator {
+    fn default() -> Self {
+        let m = Module::new();
+
+        m.register_batch(&[
+            ("language", "function() { return navigator.language }"),
+            ("online", "function() { return navigator.onLine }"),
+            ("userAgent", "function() { return navigator.userAgent }"),
+        ]);
+
+        m.register("hasFeature", r#"
+            function(name) {
+                var value = typeof globalThis !== 'undefined'
+                    ? globalThis
+                    : window;
+                var parts = name.split('.');
+
+                for (var i = 0; i < parts.length; i++) {
+                    if (value === null || value === undefined) {
+                        return false;
+                    }
+
+                    value = value[parts[i]];
+                }
+
+                return value !== null && value !== undefined;
+            }
+        "#);
+
+        Navigator(m)
+    }
+}
+
+/// The preferred language of the user, such as `"en-US"`.
+pub fn language() -> String {
+    NAVIGATOR.lock().0.call("language", ())
+}
+
+/// Whether the browser is online.
+///
+/// Being online does not mean a server is reachable, only that the browser has
+/// a network connection.
+pub fn online() -> bool {
+    NAVIGATOR.lock().0.call("online", ())
+}
+
+/// The user agent string of the browser.
+pub fn user_agent() -> String {
+    NAVIGATOR.lock().0.call("userAgent", ())
+}
+
+/// Whether a global exists, such as `"WebSocket"` or `"indexedDB"`.
+///
+/// Dotted names look up properties, so `"navigator.clipboard"` checks for the
+/// clipboard API. A property which exists but is `null` or `undefined` counts
+/// as missing.
+pub fn has_feature(name: &str) -> bool {
+    NAVIGATOR.lock().0.call("hasFeature", name)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{Value, json};
+    use stasis_internals::mock;
+
+    use super::{has_feature, language, online, user_agent};
+
+    #[test]
+    fn navigator() {
+        mock::implement("language", |_| json!("en-US"));
+        mock::implement("online", |_| json!(true));
+        mock::implement("userAgent", |_| json!("Mock/1.0"));
+        mock::implement("hasFeature", |args| {
+            let known = [json!("WebSocket"), json!("navigator.clipboard")];
+            Value::Bool(known.contains(&args[0]))
+        });
+
+        assert_eq!(language(), "en-US");
+        assert!(online());
+        assert_eq!(user_agent(), "Mock/1.0");
+        assert!(has_feature("navigator.clipboard"));
+        assert!(!has_feature("navigator.gpu"));
+    }
+}
@@ -0,0 +1,258 @@
+//! WebSocket connections.
+//!
+//! This module is only available with the `browser` feature, which is enabled
+//! by default.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # use stasis::{console, net::WebSocket};
+//! # fn main() {
+//! let socket = WebSocket::connect("wss://echo.example.com");
+//!
+//! socket.on_message(|message| console::log(message));
+//! socket.on_close(|| console::log("Disconnected"));
+//! socket.send("hello");
+//!
+//! // Keep the connection open for the rest of the program.
+//! std::mem::forget(socket);
+//! # }
+//! ```
+
+use Module;
+use global::Global;
+use handle::Handle;
+use callbacks::{Callbacks, CallbackId};
+
+/// Incoming messages, with one ID per socket.
+static MESSAGES: Callbacks<String> = Callbacks::INIT;
+
+/// Open, close and error events, with one ID per event per socket.
+static EVENTS: Callbacks<()> = Callbacks::INIT;
+
+/// The WebSocket module.
+struct Net(Module);
+
+static NET: Global<Net> = Global::INIT;
+
+impl Default for Net {
+    fn default() -> Self {
+        let m = Module::new();
+
+        m.register_callback2("message", |id: CallbackId, data: String| {
+            MESSAGES.push(id, data);
+        });
+        m.register_callback("event", |id: CallbackId| {
+            EVENTS.push(id, ());
+        });
+
+        m.register("connect", r#"
+            function(url, ids) {
+                var message = this.callbacks.message;
+                var event = this.callbacks.event;
+                var socket = new WebSocket(url);
+
+                socket.onmessage = function(e) {
+                    message(ids.message, String(e.data));
+                };
+                socket.onopen = function() { event(ids.open) };
+                socket.onclose = function() { event(ids.close) };
+                socket.onerror = function() { event(ids.error) };
+
+                return this.handle(socket);
+            }
+        "#);
+
+        m.register("send", r#"
+            function(socket, data) {
+                socket.send(data);
+            }
+        "#);
+
+        m.register("close", r#"
+            function(socket) {
+                socket.close();
+            }
+        "#);
+
+        // Run when the Rust side goes away, so that no event arrives for a
+        // socket which no longer exists.
+        m.register("disconnect", r#"
+            function(socket) {
+                socket.onmessage = null;
+                socket.onopen = null;
+                socket.onclose = null;
+                socket.onerror = null;
+                socket.close();
+            }
+        "#);
+
+        Net(m)
+    }
+}
+
+/// The callback IDs of a single socket.
+#[derive(Serialize)]
+struct Ids {
+    message: CallbackId,
+    open: CallbackId,
+    close: CallbackId,
+    error: CallbackId,
+}
+
+/// A WebSocket connection.
+///
+/// The JavaScript socket is kept alive until this is dropped, which also
+/// closes the connection. Messages and events which arrive before a listener
+/// is attached are dropped.
+pub struct WebSocket {
+    socket: Handle,
+    ids: Ids,
+}
+
+impl WebSocket {
+    /// Open a connection to `url`.
+    ///
+    /// This returns immediately. Use `on_open` to find out when the connection
+    /// is ready, as anything sent beforehand fails.
+    pub fn connect(url: &str) -> Self {
+        let ids = Ids {
+            message: MESSAGES.create(),
+            open: EVENTS.create(),
+            close: EVENTS.create(),
+            error: EVENTS.create(),
+        };
+
+        let socket = NET.lock().0.call("connect", (url, &ids));
+
+        WebSocket { socket, ids }
+    }
+
+    /// Send a text message.
+    pub fn send(&self, data: &str) {
+        let () = NET.lock().0.call("send", (&self.socket, data));
+    }
+
+    /// Start closing the connection.
+    ///
+    /// The close listener runs once the connection has closed. Dropping the
+    /// socket also closes it, but without notifying any listener.
+    pub fn close(&self) {
+        let () = NET.lock().0.call("close", &self.socket);
+    }
+
+    /// Call `f` with every incoming text message.
+    pub fn on_message<F>(&self, f: F)
+    where
+        F: FnMut(String) + Send + 'static,
+    {
+        MESSAGES.on(self.ids.message, f);
+    }
+
+    /// Call `f` once the connection is open.
+    pub fn on_open<F>(&self, f: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        Self::on_event(self.ids.open, f);
+    }
+
+    /// Call `f` once the connection has closed.
+    pub fn on_close<F>(&self, f: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        Self::on_event(self.ids.close, f);
+    }
+
+    /// Call `f` when the connection fails.
+    pub fn on_error<F>(&self, f: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        Self::on_event(self.ids.error, f);
+    }
+
+    fn on_event<F>(id: CallbackId, mut f: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        EVENTS.on(id, move |()| f());
+    }
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        let () = NET.lock().0.call("disconnect", &self.socket);
+
+        MESSAGES.remove(self.ids.message);
+        EVENTS.remove(self.ids.open);
+        EVENTS.remove(self.ids.close);
+        EVENTS.remove(self.ids.error);
+
+        // The handle itself is released once this returns.
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{self, Value, json};
+    use stasis_internals::mock;
+
+    use super::{EVENTS, MESSAGES, WebSocket};
+    use callbacks::CallbackId;
+
+    #[test]
+    fn socket() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+
+        for &name in &["connect", "send", "close", "disconnect"] {
+            let calls = calls.clone();
+
+            mock::implement(name, move |args| {
+                calls.lock().unwrap().push((name, args));
+
+                match name {
+                    "connect" => json!({ "$handle": 4242 }),
+                    _ => Value::Null,
+                }
+            });
+        }
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(Mutex::new(false));
+
+        let socket = WebSocket::connect("wss://example.com");
+
+        {
+            let messages = messages.clone();
+            let closed = closed.clone();
+
+            socket.on_message(move |m| messages.lock().unwrap().push(m));
+            socket.on_close(move || *closed.lock().unwrap() = true);
+        }
+
+        socket.send("ping");
+
+        let ids = calls.lock().unwrap()[0].1[1].clone();
+        let id = |name: &str| -> CallbackId {
+            serde_json::from_value(ids[name].clone()).unwrap()
+        };
+
+        MESSAGES.push(id("message"), "pong".to_owned());
+        EVENTS.push(id("close"), ());
+
+        assert_eq!(*messages.lock().unwrap(), vec!["pong".to_owned()]);
+        assert!(*closed.lock().unwrap());
+        assert_eq!(
+            calls.lock().unwrap()[1],
+            ("send", json!([{ "$handle": 4242 }, "ping"]))
+        );
+
+        drop(socket);
+
+        assert_eq!(calls.lock().unwrap()[2].0, "disconnect");
+        assert!(mock::is_released(4242));
+    }
+}
@@ -0,0 +1,125 @@
+//! A `requestAnimationFrame` loop.
+//!
+//! This module is only available with the `browser` feature, which is enabled
+//! by default.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # extern crate futures_v02x;
+//! # use futures_v02x::{FutureExt, StreamExt};
+//! # use stasis::{console, futures::v02, raf};
+//! # fn main() {
+//! let animation = raf::frames().take(60).for_each(|timestamp| {
+//!     console::log(timestamp);
+//!     Ok(())
+//! });
+//!
+//! v02::spawn(animation.map(|_| ()));
+//! # }
+//! ```
+
+use futures_v02x::{self, Async, Never};
+
+use Module;
+use global::Global;
+use callbacks::{Callbacks, CallbackId};
+
+/// Every stream shares this manager, which hands out a unique ID per stream.
+static CALLBACKS: Callbacks<f64> = Callbacks::INIT;
+
+/// The animation frame module.
+struct Raf(Module);
+
+static RAF: Global<Raf> = Global::INIT;
+
+impl Default for Raf {
+    fn default() -> Self {
+        let m = Module::new();
+
+        m.register_callback("frame", |(id, timestamp): (CallbackId, f64)| {
+            CALLBACKS.push(id, timestamp);
+        });
+
+        m.register("request", r#"
+            function(id) {
+                var frame = this.callbacks.frame;
+                var requests = this.data;
+
+                requests[id] = requestAnimationFrame(function(timestamp) {
+                    delete requests[id];
+                    frame(id, timestamp);
+                });
+            }
+        "#);
+
+        m.register("cancel", r#"
+            function(id) {
+                if (id in this.data) {
+                    cancelAnimationFrame(this.data[id]);
+                    delete this.data[id];
+                }
+            }
+        "#);
+
+        Raf(m)
+    }
+}
+
+/// A stream of animation frames.
+///
+/// This is created by the [`frames`] function. Each item is the timestamp in
+/// milliseconds that `requestAnimationFrame` passes to its callback.
+///
+/// The next frame is only requested once the stream is polled again, and a
+/// pending request is cancelled when the stream is dropped.
+///
+/// [`frames`]: fn.frames.html
+pub struct Frames {
+    id: CallbackId,
+    requested: bool,
+}
+
+impl Drop for Frames {
+    fn drop(&mut self) {
+        if self.requested {
+            let () = RAF.lock().0.call("cancel", self.id);
+        }
+
+        CALLBACKS.remove(self.id);
+    }
+}
+
+impl futures_v02x::Stream for Frames {
+    type Item = f64;
+    type Error = Never;
+
+    fn poll_next(
+        &mut self,
+        cx: &mut futures_v02x::task::Context,
+    ) -> futures_v02x::Poll<Option<f64>, Never> {
+        let waker = cx.waker().clone();
+        let frame = CALLBACKS.pop_listen(self.id, move || waker.wake());
+
+        if let Some(timestamp) = frame {
+            self.requested = false;
+            return Ok(Async::Ready(Some(timestamp)));
+        }
+
+        if !self.requested {
+            let () = RAF.lock().0.call("request", self.id);
+            self.requested = true;
+        }
+
+        Ok(Async::Pending)
+    }
+}
+
+/// Yield the timestamp of every animation frame.
+///
+/// The stream never ends on its own, drop it to stop the loop.
+pub fn frames() -> Frames {
+    Frames {
+        id: CALLBACKS.create(),
+        requested: false,
+    }
+}
@@ -0,0 +1,143 @@
+//! The browser `localStorage` API.
+//!
+//! This module is only available with the `browser` feature, which is enabled
+//! by default.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # use stasis::{console, storage};
+//! # fn main() {
+//! storage::set("name", "stasis").unwrap();
+//! assert_eq!(storage::get("name"), Some("stasis".to_owned()));
+//!
+//! storage::set_json("visits", &3).unwrap();
+//! let visits: Option<u32> = storage::get_json("visits").unwrap();
+//! console::log(visits);
+//! # }
+//! ```
+
+use std::{
+    error::Error,
+    fmt,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+use Module;
+use global::Global;
+
+/// The storage module.
+struct Storage(Module);
+
+static STORAGE: Global<Storage> = Global::INIT;
+
+impl Default for Storage {
+    fn default() -> Self {
+        let m = Module::new();
+
+        m.register("get", r#"
+            function(key) {
+                return localStorage.getItem(key);
+            }
+        "#);
+        m.register("set", r#"
+            function(key, value) {
+                try {
+                    localStorage.setItem(key, value);
+                    return null;
+                } catch (e) {
+                    return e instanceof Error ? e.message : String(e);
+                }
+            }
+        "#);
+        m.register("remove", r#"
+            function(key) {
+                localStorage.removeItem(key);
+            }
+        "#);
+        m.register("clear", r#"
+            function() {
+                localStorage.clear();
+            }
+        "#);
+
+        Storage(m)
+    }
+}
+
+/// A failed storage operation.
+#[derive(Debug)]
+pub enum StorageError {
+    /// The browser refused to store the value, usually because the storage
+    /// quota was exceeded.
+    Write(String),
+
+    /// The value could not be converted to or from JSON.
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StorageError::Write(ref e) => write!(f, "Write failed: {}", e),
+            StorageError::Json(ref e) => write!(f, "Invalid JSON: {}", e),
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+/// Get the value stored under `key`.
+///
+/// Returns `None` if the key does not exist.
+pub fn get(key: &str) -> Option<String> {
+    STORAGE.lock().0.call("get", key)
+}
+
+/// Store `value` under `key`, replacing any previous value.
+pub fn set(key: &str, value: &str) -> Result<(), StorageError> {
+    let error: Option<String> = STORAGE.lock().0.call("set", (key, value));
+
+    match error {
+        Some(e) => Err(StorageError::Write(e)),
+        None => Ok(()),
+    }
+}
+
+/// Remove the value stored under `key`, if any.
+pub fn remove(key: &str) {
+    STORAGE.lock().0.call("remove", key)
+}
+
+/// Remove every stored value.
+pub fn clear() {
+    STORAGE.lock().0.call("clear", ())
+}
+
+/// Get the value stored under `key`, parsed as JSON.
+///
+/// Returns `Ok(None)` if the key does not exist.
+pub fn get_json<T>(key: &str) -> Result<Option<T>, StorageError>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    match get(key) {
+        Some(value) => {
+            serde_json::from_str(&value)
+                .map(Some)
+                .map_err(StorageError::Json)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Store `value` under `key` as JSON.
+pub fn set_json<T>(key: &str, value: &T) -> Result<(), StorageError>
+where
+    T: ?Sized + Serialize,
+{
+    let value = serde_json::to_string(value).map_err(StorageError::Json)?;
+
+    set(key, &value)
+}
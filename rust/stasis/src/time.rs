@@ -0,0 +1,187 @@
+//! Timing primitives.
+//!
+//! WebAssembly has no clock of its own, so these read the JavaScript clocks.
+//! The functions are registered on first use.
+
+use callbacks::{Callbacks, CallbackId};
+use global::Global;
+use stasis_internals::outgoing;
+
+/// Every interval shares this manager, with one ID per interval.
+static INTERVALS: Callbacks<()> = Callbacks::INIT;
+
+/// The clock module.
+struct Clock(u32);
+
+static CLOCK: Global<Clock> = Global::INIT;
+
+impl Default for Clock {
+    fn default() -> Self {
+        // This does not go through `Module`, otherwise reading the clock would
+        // itself be timed by the `metrics` feature.
+        let id = outgoing::create_module();
+
+        outgoing::register_fn(id, "now", r#"
+            function() {
+                return performance.now();
+            }
+        "#);
+        outgoing::register_fn(id, "unixMillis", r#"
+            function() {
+                return Date.now();
+            }
+        "#);
+
+        outgoing::register_callback(id, "tick", |id: CallbackId| {
+            INTERVALS.push(id, ());
+        });
+        outgoing::register_fn(id, "setInterval", r#"
+            function(id, ms) {
+                var tick = this.callbacks.tick;
+                this.data[id] = setInterval(function() { tick(id) }, ms);
+            }
+        "#);
+        outgoing::register_fn(id, "clearInterval", r#"
+            function(id) {
+                clearInterval(this.data[id]);
+                delete this.data[id];
+            }
+        "#);
+
+        Clock(id)
+    }
+}
+
+/// A monotonic time in milliseconds, as given by `performance.now()`.
+///
+/// The value is relative to an arbitrary starting point, so it is only useful
+/// for measuring elapsed time.
+pub fn now() -> f64 {
+    let id = CLOCK.lock().0;
+
+    outgoing::call(id, "now", ())
+}
+
+/// The milliseconds since the Unix epoch, as given by `Date.now()`.
+///
+/// Unlike `now`, this follows the system clock and may jump backwards.
+pub fn unix_millis() -> f64 {
+    let id = CLOCK.lock().0;
+
+    outgoing::call(id, "unixMillis", ())
+}
+
+/// A running interval, created by [`interval`].
+///
+/// The interval is stopped when this is dropped.
+///
+/// [`interval`]: fn.interval.html
+pub struct IntervalHandle {
+    id: CallbackId,
+}
+
+impl IntervalHandle {
+    /// Stop the interval.
+    ///
+    /// This is the same as dropping the handle.
+    pub fn clear(self) {}
+}
+
+impl Drop for IntervalHandle {
+    fn drop(&mut self) {
+        let clock = CLOCK.lock().0;
+
+        // The JavaScript timer is cleared first, so that no tick arrives after
+        // the listener is gone.
+        let () = outgoing::call(clock, "clearInterval", self.id);
+        INTERVALS.remove(self.id);
+    }
+}
+
+/// Run `f` every `ms` milliseconds, using `setInterval`.
+///
+/// The interval runs until the returned handle is dropped, which also clears
+/// the JavaScript timer.
+///
+/// ```rust,no_run
+/// # extern crate stasis;
+/// # use stasis::{console, time};
+/// # fn main() {
+/// let mut seconds = 0;
+/// let handle = time::interval(1000, move || {
+///     seconds += 1;
+///     console::log(seconds);
+/// });
+///
+/// // Keep the interval running for the rest of the program.
+/// std::mem::forget(handle);
+/// # }
+/// ```
+pub fn interval<F>(ms: u32, mut f: F) -> IntervalHandle
+where
+    F: FnMut() + Send + 'static,
+{
+    let clock = CLOCK.lock().0;
+    let id = INTERVALS.create();
+
+    INTERVALS.on(id, move |()| f());
+
+    let () = outgoing::call(clock, "setInterval", (id, ms));
+
+    IntervalHandle { id }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde_json::{self, Value};
+    use stasis_internals::mock;
+
+    use super::{INTERVALS, interval};
+    use callbacks::CallbackId;
+
+    #[test]
+    fn interval_clears_timer() {
+        static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+        let timers = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let timers = timers.clone();
+
+            mock::implement("setInterval", move |args| {
+                timers.lock().unwrap().push(args[0].clone());
+                Value::Null
+            });
+        }
+
+        {
+            let timers = timers.clone();
+
+            mock::implement("clearInterval", move |args| {
+                timers.lock().unwrap().retain(|id| *id != args[0]);
+                Value::Null
+            });
+        }
+
+        let handle = interval(16, || {
+            TICKS.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let started = timers.lock().unwrap()[0].clone();
+        let id: CallbackId = serde_json::from_value(started).unwrap();
+
+        INTERVALS.push(id, ());
+        INTERVALS.push(id, ());
+        assert_eq!(TICKS.load(Ordering::SeqCst), 2);
+
+        handle.clear();
+        assert_eq!(*timers.lock().unwrap(), Vec::<Value>::new());
+
+        // A stray tick after clearing is ignored.
+        INTERVALS.push(id, ());
+        assert_eq!(TICKS.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,6 +1,7 @@
 //! Tutorials for stasis.
 //!
-//! This is a dummy module only used for doc generation.
+//! This is a dummy module only used for doc generation. It is left out when
+//! the `tutorial` feature, which is enabled by default, is disabled.
 
 pub mod t01_hello_world;
 pub mod t02_js_functions;
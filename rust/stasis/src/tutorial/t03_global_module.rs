@@ -151,3 +151,21 @@
 //!     console::log(random());
 //! }
 //! ```
+//!
+//! ## Shortcut
+//!
+//! With the `bindings` feature, the `#[stasis::bindings]` attribute writes all
+//! of the above for you:
+//!
+//! ```rust,ignore
+//! #[stasis::bindings]
+//! pub trait Random {
+//!     fn random() -> f32 {
+//!         "function() { return Math.random(); }"
+//!     }
+//! }
+//!
+//! fn main() {
+//!     console::log(Random::random());
+//! }
+//! ```
@@ -0,0 +1,109 @@
+//! JavaScript's `undefined`.
+//!
+//! Arguments cross the boundary as JSON, which has no `undefined`. `None`
+//! becomes `null`, and so does `()`, which is what most JavaScript APIs expect.
+//! Some APIs treat `undefined` differently though, such as default parameters
+//! and `Object.assign`. For these, pass `Undefined`, which the runtime turns
+//! back into a real `undefined`.
+//!
+//! ```rust,no_run
+//! # extern crate stasis;
+//! # use stasis::{Module, Undefined};
+//! # fn main() {
+//! let m = Module::new();
+//! m.register("greet", r#"
+//!     function(name = "world") {
+//!         return "Hello, " + name + "!";
+//!     }
+//! "#);
+//!
+//! let hello: String = m.call("greet", (Undefined,));
+//! let nobody: String = m.call("greet", (None::<&str>,));
+//!
+//! assert_eq!(hello, "Hello, world!");
+//! assert_eq!(nobody, "Hello, null!");
+//! # }
+//! ```
+//!
+//! Within an object, a field set to `Undefined` is left out entirely, just as
+//! `JSON.parse` does for `undefined`.
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+/// The wire representation of `Undefined`.
+#[derive(Serialize)]
+struct RawUndefined {
+    #[serde(rename = "$undefined")]
+    undefined: bool,
+}
+
+/// A value which the runtime passes to JavaScript as `undefined`.
+///
+/// As a return type, this accepts `undefined` and `null`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Undefined;
+
+impl Serialize for Undefined {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        RawUndefined { undefined: true }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Undefined {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        <()>::deserialize(deserializer).map(|()| Undefined)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{self, Value, json};
+    use stasis_internals::mock;
+
+    use super::Undefined;
+    use Module;
+
+    #[test]
+    fn optional_arguments() {
+        mock::implement("test.optional", |args| args);
+
+        let m = Module::new();
+        m.register("test.optional", "function(a, b) { return [a, b]; }");
+
+        let some: Value = m.call("test.optional", (Some(1), Some("two")));
+        let none: Value = m.call("test.optional", (None::<u32>, None::<&str>));
+        let undefined: Value = m.call("test.optional", (Undefined, 3));
+
+        assert_eq!(some, json!([1, "two"]));
+        assert_eq!(none, json!([null, null]));
+        assert_eq!(undefined, json!([{ "$undefined": true }, 3]));
+
+        let back: Undefined = serde_json::from_value(Value::Null).unwrap();
+        assert_eq!(back, Undefined);
+    }
+
+    #[test]
+    fn undefined_return() {
+        mock::implement("test.returnsUndefined", |_| mock::undefined());
+        mock::implement("test.returnsNull", |_| Value::Null);
+
+        let m = Module::new();
+
+        let _: Undefined = m.call("test.returnsUndefined", ());
+        let _: Undefined = m.call("test.returnsNull", ());
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to deserialize return value")]
+    fn undefined_rejects_values() {
+        mock::implement("test.returnsFive", |_| json!(5));
+
+        let _: Undefined = Module::new().call("test.returnsFive", ());
+    }
+}